@@ -0,0 +1,28 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use amq_proto_codegen::{Extension, Result, Spec};
+
+const SPEC: &str = "amqp-rabbitmq-0.9.1.json";
+/// Extension specs to merge into the spec, separated like `PATH`.
+const EXTENSIONS: &str = "AMQ_PROTO_EXTENSION_SPECS";
+
+fn generate(out_dir: &Path) -> Result<()> {
+    let mut spec = Spec::from_file(SPEC)?;
+    if let Some(paths) = env::var_os(EXTENSIONS) {
+        for path in env::split_paths(&paths) {
+            println!("cargo:rerun-if-changed={}", path.display());
+            spec.merge(Extension::from_file(&path)?)?;
+        }
+    }
+    amq_proto_codegen::generate(&spec, out_dir)
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", SPEC);
+    println!("cargo:rerun-if-env-changed={}", EXTENSIONS);
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo"));
+    if let Err(err) = generate(&out_dir) {
+        panic!("Generating the protocol from {} failed: {}", SPEC, err);
+    }
+}
@@ -0,0 +1,721 @@
+//! Generates the `amq-proto` protocol code from an AMQP spec JSON file, such as
+//! `amqp-rabbitmq-0.9.1.json`.
+//!
+//! Two files are generated:
+//!
+//! * `protocol.rs` - the spec constants, for every class a module with the method
+//!   structs and the properties struct, and the `AnyMethod` enum, written as
+//!   `method_struct!`, `properties_struct!` and `any_method!` invocations, followed
+//!   by the `CLASSES` metadata table.
+//! * `method_frame_methods.rs` - `method_name`, `method_carries_content`,
+//!   `method_is_synchronous` and `fmt_method` for `MethodFrame`.
+//!
+//! The generated code only refers to `::amq_proto` and the macros it exports, so crates
+//! depending on `amq-proto` can generate code for their own specs from their build
+//! script and `include!` it. The `serde` derives of the generated types are behind the
+//! `serde` feature of the including crate, which then needs `amq-proto/serde`.
+//!
+//! `amq-proto` runs the generator from its build script, merging in the extension
+//! specs listed in `AMQ_PROTO_EXTENSION_SPECS`:
+//!
+//! ```no_run
+//! use amq_proto_codegen::{Extension, Spec};
+//!
+//! let mut spec = Spec::from_file("amqp-rabbitmq-0.9.1.json").unwrap();
+//! spec.merge(Extension::from_file("my-plugin.json").unwrap()).unwrap();
+//! amq_proto_codegen::generate(&spec, "target/generated").unwrap();
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error(err.to_string())
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(err: fmt::Error) -> Error {
+        Error(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error(format!("invalid spec: {}", err))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The wire types method arguments and properties can have.
+const TYPES: &[&str] = &["bit", "octet", "short", "long", "longlong", "shortstr", "longstr",
+                         "table", "timestamp"];
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Spec {
+    pub name: String,
+    pub major_version: u8,
+    pub minor_version: u8,
+    pub revision: u8,
+    pub port: u16,
+    /// Domain names with the type they stand for.
+    #[serde(default)]
+    pub domains: Vec<(String, String)>,
+    #[serde(default)]
+    pub constants: Vec<Constant>,
+    #[serde(default)]
+    pub classes: Vec<Class>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Constant {
+    pub name: String,
+    pub value: u32,
+    /// `soft-error` or `hard-error` for reply codes.
+    #[serde(default)]
+    pub class: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Class {
+    pub id: u16,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<Argument>,
+    #[serde(default)]
+    pub methods: Vec<MethodSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MethodSpec {
+    pub id: u16,
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Vec<Argument>,
+    #[serde(default)]
+    pub synchronous: bool,
+    #[serde(default)]
+    pub content: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Argument {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub ty: Option<String>,
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub default_value: Option<Value>,
+}
+
+/// Classes, methods, properties, domains and constants to add to a spec, in the
+/// same format as the spec. A class with the name of an existing class adds its
+/// methods and properties to it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Extension {
+    #[serde(default)]
+    pub domains: Vec<(String, String)>,
+    #[serde(default)]
+    pub constants: Vec<Constant>,
+    #[serde(default)]
+    pub classes: Vec<Class>,
+}
+
+impl Extension {
+    pub fn from_json(json: &str) -> Result<Extension> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Extension> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|err| Error(format!("can't read {}: {}", path.display(), err)))?;
+        Extension::from_json(&json)
+    }
+}
+
+impl Spec {
+    pub fn from_json(json: &str) -> Result<Spec> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Spec> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|err| Error(format!("can't read {}: {}", path.display(), err)))?;
+        Spec::from_json(&json)
+    }
+
+    /// Adds the contents of an extension spec. Fails if the extension redefines
+    /// anything, e.g. uses the id of an existing method.
+    pub fn merge(&mut self, extension: Extension) -> Result<()> {
+        for (name, ty) in extension.domains {
+            match self.domains.iter().find(|(existing, _)| *existing == name) {
+                Some((_, existing_ty)) if *existing_ty != ty => {
+                    return Err(Error(format!("domain '{}' is already defined as '{}'",
+                                             name,
+                                             existing_ty)))
+                }
+                Some(_) => {}
+                None => self.domains.push((name, ty)),
+            }
+        }
+        self.constants.extend(extension.constants);
+        for class in extension.classes {
+            match self.classes.iter_mut().find(|existing| existing.name == class.name) {
+                Some(existing) => {
+                    if existing.id != class.id {
+                        return Err(Error(format!("class '{}' has id {}, not {}",
+                                                 class.name,
+                                                 existing.id,
+                                                 class.id)));
+                    }
+                    existing.properties.extend(class.properties);
+                    existing.methods.extend(class.methods);
+                }
+                None => self.classes.push(class),
+            }
+        }
+        self.validate()
+    }
+
+    /// The wire type of an argument, looking up its domain.
+    pub fn argument_type<'a>(&'a self, argument: &'a Argument) -> Result<&'a str> {
+        let ty = match (&argument.ty, &argument.domain) {
+            (Some(ty), _) => ty.as_str(),
+            (None, Some(domain)) => self.domains
+                .iter()
+                .find(|(name, _)| name == domain)
+                .map(|(_, ty)| ty.as_str())
+                .ok_or_else(|| Error(format!("unknown domain '{}' of '{}'", domain, argument.name)))?,
+            (None, None) => return Err(Error(format!("'{}' has no type or domain", argument.name))),
+        };
+        if TYPES.contains(&ty) {
+            Ok(ty)
+        } else {
+            Err(Error(format!("unknown type '{}' of '{}'", ty, argument.name)))
+        }
+    }
+
+    /// Checks that class and method ids and names are unique and all types are known.
+    pub fn validate(&self) -> Result<()> {
+        let mut class_ids = HashMap::new();
+        let mut class_names = HashSet::new();
+        for class in &self.classes {
+            if let Some(other) = class_ids.insert(class.id, &class.name) {
+                return Err(Error(format!("classes '{}' and '{}' have the same id {}",
+                                         other,
+                                         class.name,
+                                         class.id)));
+            }
+            if !class_names.insert(&class.name) {
+                return Err(Error(format!("class '{}' is defined twice", class.name)));
+            }
+            let mut method_ids = HashMap::new();
+            let mut method_names = HashSet::new();
+            for method in &class.methods {
+                if let Some(other) = method_ids.insert(method.id, &method.name) {
+                    return Err(Error(format!("methods '{}.{}' and '{}.{}' have the same id {}",
+                                             class.name,
+                                             other,
+                                             class.name,
+                                             method.name,
+                                             method.id)));
+                }
+                if !method_names.insert(&method.name) {
+                    return Err(Error(format!("method '{}.{}' is defined twice",
+                                             class.name,
+                                             method.name)));
+                }
+                for argument in &method.arguments {
+                    self.argument_type(argument)?;
+                }
+            }
+            for property in &class.properties {
+                self.argument_type(property)?;
+            }
+        }
+        let mut constant_names = HashSet::new();
+        for constant in &self.constants {
+            if !constant_names.insert(&constant.name) {
+                return Err(Error(format!("constant '{}' is defined twice", constant.name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn titleize(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// `content-type` => `content_type`, with `type` renamed to `_type`.
+pub fn snake_name(name: &str) -> String {
+    match name {
+        "type" => "_type".to_string(),
+        name => name.replace('-', "_"),
+    }
+}
+
+/// `start-ok` => `StartOk`.
+pub fn camel_name(name: &str) -> String {
+    name.split('-').map(titleize).collect()
+}
+
+/// `FRAME-METHOD` => `FRAME_METHOD`.
+pub fn constant_name(name: &str) -> String {
+    name.replace('-', "_").to_uppercase()
+}
+
+/// Frame types and the frame end are octets, `FRAME-MIN-SIZE` is compared with
+/// `frame-max` and the rest are reply codes.
+fn constant_type(constant: &Constant) -> &'static str {
+    let name = constant_name(&constant.name);
+    if name == "FRAME_MIN_SIZE" || constant.value > u32::from(u16::MAX) {
+        "u32"
+    } else if name.starts_with("FRAME_") && constant.value <= u32::from(u8::MAX) {
+        "u8"
+    } else {
+        "u16"
+    }
+}
+
+/// Renders a `default-value` which differs from the default of the Rust type.
+fn default_expr(ty: &str, value: &Value, name: &str) -> Result<Option<String>> {
+    let invalid = || Error(format!("invalid default value {} for '{}'", value, name));
+    let expr = match ty {
+        "bit" => {
+            match value.as_bool().ok_or_else(invalid)? {
+                true => Some("true".to_string()),
+                false => None,
+            }
+        }
+        "octet" | "short" | "long" | "longlong" | "timestamp" => {
+            let max = match ty {
+                "octet" => u64::from(u8::MAX),
+                "short" => u64::from(u16::MAX),
+                "long" => u64::from(u32::MAX),
+                _ => u64::MAX,
+            };
+            match value.as_u64().filter(|&number| number <= max).ok_or_else(invalid)? {
+                0 => None,
+                number => Some(number.to_string()),
+            }
+        }
+        "shortstr" | "longstr" => {
+            match value.as_str().ok_or_else(invalid)? {
+                "" => None,
+                string => Some(format!("{:?}.to_string()", string)),
+            }
+        }
+        "table" => {
+            match value.as_object() {
+                Some(table) if table.is_empty() => None,
+                _ => return Err(invalid()),
+            }
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(expr)
+}
+
+fn fields(spec: &Spec, arguments: &[Argument], defaults: bool) -> Result<Vec<String>> {
+    arguments.iter()
+        .map(|argument| {
+            let ty = spec.argument_type(argument)?;
+            let default = match argument.default_value {
+                Some(ref value) if defaults => default_expr(ty, value, &argument.name)?,
+                _ => None,
+            };
+            Ok(match default {
+                Some(default) => format!("{} => {} = {}", snake_name(&argument.name), ty, default),
+                None => format!("{} => {}", snake_name(&argument.name), ty),
+            })
+        })
+        .collect()
+}
+
+/// Generates `protocol.rs`.
+pub fn protocol(spec: &Spec) -> Result<String> {
+    spec.validate()?;
+    let mut out = String::new();
+    writeln!(out,
+             "// Generated by amq-proto-codegen from the {} {}-{}-{} spec. Do not edit.",
+             spec.name,
+             spec.major_version,
+             spec.minor_version,
+             spec.revision)?;
+    writeln!(out)?;
+    writeln!(out, "/// Default port from the spec")?;
+    writeln!(out, "pub const PORT: u16 = {};", spec.port)?;
+    writeln!(out)?;
+    writeln!(out, "/// Constants from the spec.")?;
+    writeln!(out, "pub mod constants {{")?;
+    for constant in &spec.constants {
+        if let Some(ref class) = constant.class {
+            writeln!(out, "    /// A {}.", class)?;
+        }
+        writeln!(out,
+                 "    pub const {}: {} = {};",
+                 constant_name(&constant.name),
+                 constant_type(constant),
+                 constant.value)?;
+    }
+    writeln!(out, "}}")?;
+    for class in &spec.classes {
+        writeln!(out)?;
+        writeln!(out, "#[allow(missing_copy_implementations)]")?;
+        writeln!(out, "pub mod {} {{", class.name)?;
+        if !class.properties.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "    // properties struct for {}", class.name)?;
+            writeln!(out, "    ::amq_proto::properties_struct!({}Properties,", titleize(&class.name))?;
+            writeln!(out, "        {}", fields(spec, &class.properties, false)?.join(",\n        "))?;
+            writeln!(out, "    );")?;
+        }
+        for method in &class.methods {
+            writeln!(out)?;
+            writeln!(out, "    // Method {}:{}", method.id, method.name)?;
+            writeln!(out,
+                     "    ::amq_proto::method_struct!({}, \"{}.{}\", {}, {}, {},",
+                     camel_name(&method.name),
+                     class.name,
+                     method.name,
+                     class.id,
+                     method.id,
+                     method.synchronous)?;
+            let fields = fields(spec, &method.arguments, true)?;
+            if !fields.is_empty() {
+                writeln!(out, "        {}", fields.join(",\n        "))?;
+            }
+            writeln!(out, "    );")?;
+        }
+        writeln!(out, "}}")?;
+    }
+    writeln!(out)?;
+    writeln!(out, "::amq_proto::any_method!(")?;
+    let variants: Vec<_> = spec.classes
+        .iter()
+        .flat_map(|class| {
+            class.methods.iter().map(move |method| {
+                format!("    ({}, {}) => {}{}({}::{})",
+                        class.id,
+                        method.id,
+                        camel_name(&class.name),
+                        camel_name(&method.name),
+                        class.name,
+                        camel_name(&method.name))
+            })
+        })
+        .collect();
+    writeln!(out, "{}", variants.join(",\n"))?;
+    writeln!(out, ");")?;
+    reflection(spec, &mut out)?;
+    Ok(out)
+}
+
+/// `longlong` => `LongLong`, the `WireType` variant of a wire type. `Spec::argument_type`
+/// only returns the types in `TYPES`.
+fn wire_type_variant(ty: &str) -> &'static str {
+    match ty {
+        "bit" => "Bit",
+        "octet" => "Octet",
+        "short" => "Short",
+        "long" => "Long",
+        "longlong" => "LongLong",
+        "shortstr" => "ShortStr",
+        "longstr" => "LongStr",
+        "table" => "Table",
+        "timestamp" => "Timestamp",
+        ty => panic!("unknown wire type '{}'", ty),
+    }
+}
+
+/// Renders a `default-value` as a `DefaultValue`.
+fn default_value(ty: &str, value: &Value, name: &str) -> Result<String> {
+    // Checks that the value suits the type.
+    default_expr(ty, value, name)?;
+    Ok(match *value {
+        Value::Bool(bit) => format!("DefaultValue::Bit({})", bit),
+        Value::Number(ref number) => format!("DefaultValue::Number({})", number),
+        Value::String(ref string) => format!("DefaultValue::Str({:?})", string),
+        _ => "DefaultValue::EmptyTable".to_string(),
+    })
+}
+
+fn field_infos(out: &mut String, spec: &Spec, arguments: &[Argument], indent: &str) -> Result<()> {
+    if arguments.is_empty() {
+        writeln!(out, "&[],")?;
+        return Ok(());
+    }
+    writeln!(out, "&[")?;
+    for argument in arguments {
+        let ty = spec.argument_type(argument)?;
+        let default = match argument.default_value {
+            Some(ref value) => format!("Some({})", default_value(ty, value, &argument.name)?),
+            None => "None".to_string(),
+        };
+        writeln!(out,
+                 "{}    FieldInfo {{ name: \"{}\", spec_name: \"{}\", wire_type: \
+                  WireType::{}, default: {} }},",
+                 indent,
+                 snake_name(&argument.name),
+                 argument.name,
+                 wire_type_variant(ty),
+                 default)?;
+    }
+    writeln!(out, "{}],", indent)?;
+    Ok(())
+}
+
+fn reflection(spec: &Spec, out: &mut String) -> Result<()> {
+    writeln!(out)?;
+    writeln!(out,
+             "use ::amq_proto::reflection::{{ClassInfo, DefaultValue, FieldInfo, MethodInfo, \
+              WireType}};")?;
+    writeln!(out)?;
+    writeln!(out, "/// The classes of the spec, see `amq_proto::reflection`.")?;
+    writeln!(out, "pub static CLASSES: &[ClassInfo] = &[")?;
+    for class in &spec.classes {
+        writeln!(out, "    ClassInfo {{")?;
+        writeln!(out, "        id: {},", class.id)?;
+        writeln!(out, "        name: \"{}\",", class.name)?;
+        write!(out, "        properties: ")?;
+        field_infos(out, spec, &class.properties, "        ")?;
+        writeln!(out, "        methods: &[")?;
+        for method in &class.methods {
+            writeln!(out, "            MethodInfo {{")?;
+            writeln!(out, "                class_id: {},", class.id)?;
+            writeln!(out, "                id: {},", method.id)?;
+            writeln!(out, "                name: \"{}\",", method.name)?;
+            writeln!(out, "                full_name: \"{}.{}\",", class.name, method.name)?;
+            writeln!(out, "                synchronous: {},", method.synchronous)?;
+            writeln!(out, "                content: {},", method.content)?;
+            write!(out, "                fields: ")?;
+            field_infos(out, spec, &method.arguments, "                ")?;
+            writeln!(out, "            }},")?;
+        }
+        writeln!(out, "        ],")?;
+        writeln!(out, "    }},")?;
+    }
+    writeln!(out, "];")?;
+    Ok(())
+}
+
+fn method_match<F>(out: &mut String,
+                   signature: &str,
+                   spec: &Spec,
+                   arm: F,
+                   fallback: &str)
+                   -> fmt::Result
+    where F: Fn(&Class, &MethodSpec) -> Option<String>
+{
+    writeln!(out, "{} {{", signature)?;
+    writeln!(out, "    match (method_frame.class_id, method_frame.method_id) {{")?;
+    for class in &spec.classes {
+        for method in &class.methods {
+            if let Some(value) = arm(class, method) {
+                writeln!(out, "        ({}, {}) => {},", class.id, method.id, value)?;
+            }
+        }
+    }
+    writeln!(out, "        {}", fallback)?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")
+}
+
+/// Generates `method_frame_methods.rs`, which is included into `framing.rs`.
+pub fn method_frame_methods(spec: &Spec) -> Result<String> {
+    spec.validate()?;
+    let mut out = String::new();
+    writeln!(out, "// Generated by amq-proto-codegen. Do not edit.")?;
+    writeln!(out)?;
+    method_match(&mut out,
+                 "fn method_name(method_frame: &MethodFrame) -> &'static str",
+                 spec,
+                 |class, method| Some(format!("\"{}.{}\"", class.name, method.name)),
+                 "(_, _) => \"UNKNOWN\"")?;
+    writeln!(out)?;
+    method_match(&mut out,
+                 "fn method_carries_content(method_frame: &MethodFrame) -> bool",
+                 spec,
+                 |_, method| if method.content { Some("true".to_string()) } else { None },
+                 "(_, _) => false")?;
+    writeln!(out)?;
+    method_match(&mut out,
+                 "fn method_is_synchronous(method_frame: &MethodFrame) -> bool",
+                 spec,
+                 |_, method| if method.synchronous { Some("true".to_string()) } else { None },
+                 "(_, _) => false")?;
+    writeln!(out)?;
+    method_match(&mut out,
+                 "fn fmt_method(method_frame: &MethodFrame, f: &mut fmt::Formatter) -> fmt::Result",
+                 spec,
+                 |class, method| {
+                     Some(format!("fmt_decoded::<protocol::{}::{}>(method_frame, f)",
+                                  class.name,
+                                  camel_name(&method.name)))
+                 },
+                 "(class_id, method_id) => {\n            write!(f, \"UNKNOWN{{class_id={}, \
+                  method_id={}, size={}}}\", class_id, method_id, \
+                  method_frame.arguments.inner().len())\n        }")?;
+    Ok(out)
+}
+
+/// Writes `protocol.rs` and `method_frame_methods.rs` to `out_dir`.
+pub fn generate<P: AsRef<Path>>(spec: &Spec, out_dir: P) -> Result<()> {
+    let out_dir = out_dir.as_ref();
+    fs::create_dir_all(out_dir)?;
+    fs::write(out_dir.join("protocol.rs"), protocol(spec)?)?;
+    fs::write(out_dir.join("method_frame_methods.rs"), method_frame_methods(spec)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SPEC: &str = r#"{
+        "name": "AMQP", "major-version": 0, "minor-version": 9, "revision": 1, "port": 5672,
+        "domains": [["queue-name", "shortstr"], ["bit", "bit"]],
+        "constants": [{"name": "FRAME-END", "value": 206},
+                      {"name": "NOT-FOUND", "value": 404, "class": "soft-error"}],
+        "classes": [{
+            "id": 50, "name": "queue",
+            "methods": [{"id": 10, "name": "declare", "synchronous": true, "arguments": [
+                {"domain": "queue-name", "name": "queue", "default-value": "q"},
+                {"type": "bit", "name": "durable", "default-value": false},
+                {"type": "table", "name": "arguments", "default-value": {}}]},
+                        {"id": 11, "name": "declare-ok"}]
+        }]
+    }"#;
+
+    #[test]
+    fn test_names() {
+        assert_eq!(camel_name("start-ok"), "StartOk");
+        assert_eq!(camel_name("get-empty"), "GetEmpty");
+        assert_eq!(snake_name("type"), "_type");
+        assert_eq!(snake_name("consumer-tag"), "consumer_tag");
+        assert_eq!(constant_name("frame-min-size"), "FRAME_MIN_SIZE");
+    }
+
+    #[test]
+    fn test_protocol() {
+        let spec = Spec::from_json(SPEC).unwrap();
+        let protocol = protocol(&spec).unwrap();
+        assert!(protocol.contains("    pub const FRAME_END: u8 = 206;\n"));
+        assert!(protocol.contains("    /// A soft-error.\n    pub const NOT_FOUND: u16 = 404;\n"));
+        assert!(protocol.contains("    ::amq_proto::method_struct!(Declare, \"queue.declare\", 50, 10, true,\n        \
+                                   queue => shortstr = \"q\".to_string(),\n        \
+                                   durable => bit,\n        arguments => table\n    );\n"));
+        assert!(protocol.contains("    ::amq_proto::method_struct!(DeclareOk, \"queue.declare-ok\", 50, 11, \
+                                   false,\n    );\n"));
+
+        assert!(protocol.contains("                fields: &[\n                    FieldInfo { name: \
+                                   \"queue\", spec_name: \"queue\", wire_type: WireType::ShortStr, \
+                                   default: Some(DefaultValue::Str(\"q\")) },\n"));
+        assert!(protocol.contains("                    FieldInfo { name: \"arguments\", spec_name: \
+                                   \"arguments\", wire_type: WireType::Table, default: \
+                                   Some(DefaultValue::EmptyTable) },\n"));
+
+        let methods = method_frame_methods(&spec).unwrap();
+        assert!(methods.contains("        (50, 11) => \"queue.declare-ok\",\n"));
+        assert!(methods.contains("fn method_is_synchronous(method_frame: &MethodFrame) -> bool {\n    \
+                                  match (method_frame.class_id, method_frame.method_id) {\n        \
+                                  (50, 10) => true,\n        (_, _) => false\n"));
+    }
+
+    /// `tests/generated.rs` in `amq-proto` compiles this output, regenerate it with
+    /// `cargo run -p amq-proto-codegen -- tests/fixtures/stream-spec.json tests/fixtures`
+    /// and rename `protocol.rs` to `stream_protocol.rs`.
+    #[test]
+    fn test_stream_fixture() {
+        let spec = Spec::from_json(include_str!("../../tests/fixtures/stream-spec.json")).unwrap();
+        assert_eq!(protocol(&spec).unwrap(), include_str!("../../tests/fixtures/stream_protocol.rs"));
+    }
+
+    #[test]
+    fn test_merge_extension() {
+        let mut spec = Spec::from_json(SPEC).unwrap();
+        let extension = Extension::from_json(r#"{
+            "domains": [["queue-name", "shortstr"], ["priority", "octet"]],
+            "constants": [{"name": "QUEUE-LIMIT", "value": 1000}],
+            "classes": [
+                {"id": 50, "name": "queue", "methods": [
+                    {"id": 100, "name": "promote", "arguments": [
+                        {"domain": "queue-name", "name": "queue"},
+                        {"domain": "priority", "name": "priority", "default-value": 5}]}]},
+                {"id": 200, "name": "stream", "methods": [{"id": 10, "name": "offset"}]}
+            ]
+        }"#)
+            .unwrap();
+        spec.merge(extension).unwrap();
+        assert_eq!(spec.classes.len(), 2);
+        assert_eq!(spec.classes[0].methods.len(), 3);
+
+        let protocol = protocol(&spec).unwrap();
+        assert!(protocol.contains("    pub const QUEUE_LIMIT: u16 = 1000;\n"));
+        assert!(protocol.contains("    ::amq_proto::method_struct!(Promote, \"queue.promote\", 50, 100, false,\n        \
+                                   queue => shortstr,\n        priority => octet = 5\n    );\n"));
+        assert!(protocol.contains("pub mod stream {\n"));
+        assert!(protocol.contains("::amq_proto::any_method!(\n    (50, 10) => QueueDeclare(queue::Declare),\n"));
+        assert!(protocol.contains("    (200, 10) => StreamOffset(stream::Offset)\n);\n"));
+        let methods = method_frame_methods(&spec).unwrap();
+        assert!(methods.contains("        (50, 100) => \"queue.promote\",\n"));
+
+        let mut clash = Extension::default();
+        clash.classes.push(spec.classes[0].clone());
+        clash.classes[0].methods.truncate(1);
+        assert_eq!(spec.clone().merge(clash.clone()).unwrap_err().to_string(),
+                   "methods 'queue.declare' and 'queue.declare' have the same id 10");
+        clash.classes[0].id = 51;
+        assert_eq!(spec.clone().merge(clash).unwrap_err().to_string(),
+                   "class 'queue' has id 50, not 51");
+        let domain = Extension { domains: vec![("queue-name".to_string(), "longstr".to_string())],
+                                 ..Default::default() };
+        assert!(spec.merge(domain).is_err());
+    }
+
+    #[test]
+    fn test_invalid_specs() {
+        let mut spec = Spec::from_json(SPEC).unwrap();
+        spec.classes[0].methods[1].id = 10;
+        assert_eq!(protocol(&spec).unwrap_err().to_string(),
+                   "methods 'queue.declare' and 'queue.declare-ok' have the same id 10");
+
+        let mut spec = Spec::from_json(SPEC).unwrap();
+        spec.classes[0].methods[0].arguments[0].domain = Some("nope".to_string());
+        assert!(protocol(&spec).is_err());
+
+        let mut spec = Spec::from_json(SPEC).unwrap();
+        spec.classes[0].methods[0].arguments[1].default_value = Some(Value::from(3));
+        assert_eq!(protocol(&spec).unwrap_err().to_string(),
+                   "invalid default value 3 for 'durable'");
+        assert!(Spec::from_json("{}").is_err());
+    }
+}
@@ -0,0 +1,46 @@
+//! `amq-proto-codegen [--extension EXTENSION]... SPEC OUT_DIR` writes `protocol.rs` and
+//! `method_frame_methods.rs` generated from the spec JSON file `SPEC`, merged with the
+//! given extension specs, to `OUT_DIR`.
+
+use std::env;
+use std::process;
+
+use amq_proto_codegen::{Extension, Result, Spec};
+
+const USAGE: &str = "usage: amq-proto-codegen [--extension EXTENSION]... SPEC OUT_DIR";
+
+fn run(spec: &str, extensions: &[String], out_dir: &str) -> Result<()> {
+    let mut spec = Spec::from_file(spec)?;
+    for extension in extensions {
+        spec.merge(Extension::from_file(extension)?)?;
+    }
+    amq_proto_codegen::generate(&spec, out_dir)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut extensions = vec![];
+    let mut positional = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--extension" => {
+                match args.next() {
+                    Some(extension) => extensions.push(extension),
+                    None => {
+                        eprintln!("{}", USAGE);
+                        process::exit(2);
+                    }
+                }
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() != 2 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    if let Err(err) = run(&positional[0], &extensions, &positional[1]) {
+        eprintln!("amq-proto-codegen: {}", err);
+        process::exit(1);
+    }
+}
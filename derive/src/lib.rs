@@ -0,0 +1,207 @@
+//! `#[derive(AmqpMethod)]`, implementing `amq_proto::Method` for a struct whose fields
+//! are the method arguments, in wire order.
+//!
+//! ```ignore
+//! use amq_proto::{AmqpMethod, Table};
+//!
+//! /// Promotes a queue, an extension of our broker plugin.
+//! #[derive(Debug, Clone, PartialEq, AmqpMethod)]
+//! #[amqp(class = 50, method = 100, name = "queue.promote", synchronous)]
+//! pub struct Promote {
+//!     #[amqp(short)]
+//!     pub ticket: u16,
+//!     pub queue: String,
+//!     #[amqp(longstr)]
+//!     pub reason: String,
+//!     pub nowait: bool,
+//!     pub arguments: Table,
+//! }
+//! ```
+//!
+//! The wire type of a field is given by `#[amqp(octet)]`, `short`, `long`, `longlong`,
+//! `shortstr`, `longstr`, `table`, `timestamp` or `bit`. Without it, the type is
+//! inferred from the Rust type: `u8`, `u16`, `u32`, `u64`, `String` (a `shortstr`),
+//! `Table` and `bool`. Consecutive `bit` fields are packed into octets.
+//!
+//! `name` defaults to the struct name in kebab case and `synchronous` to false.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitInt, LitStr, Type};
+
+const WIRE_TYPES: &[&str] = &["octet", "short", "long", "longlong", "shortstr", "longstr",
+                              "table", "timestamp", "bit"];
+
+#[proc_macro_derive(AmqpMethod, attributes(amqp))]
+pub fn derive_amqp_method(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+struct MethodAttributes {
+    class_id: LitInt,
+    method_id: LitInt,
+    name: String,
+    synchronous: bool,
+}
+
+fn kebab_case(name: &str) -> String {
+    let mut kebab = String::new();
+    for (idx, c) in name.chars().enumerate() {
+        if c.is_uppercase() && idx > 0 {
+            kebab.push('-');
+        }
+        kebab.extend(c.to_lowercase());
+    }
+    kebab
+}
+
+fn method_attributes(input: &DeriveInput) -> syn::Result<MethodAttributes> {
+    let mut class_id = None;
+    let mut method_id = None;
+    let mut name = None;
+    let mut synchronous = false;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("amqp")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("class") {
+                class_id = Some(meta.value()?.parse::<LitInt>()?);
+            } else if meta.path.is_ident("method") {
+                method_id = Some(meta.value()?.parse::<LitInt>()?);
+            } else if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("synchronous") {
+                synchronous = true;
+            } else {
+                return Err(meta.error("expected `class`, `method`, `name` or `synchronous`"));
+            }
+            Ok(())
+        })?;
+    }
+    let missing = |what| {
+        syn::Error::new(Span::call_site(),
+                        format!("#[derive(AmqpMethod)] needs #[amqp({} = ...)]", what))
+    };
+    let class_id = class_id.ok_or_else(|| missing("class"))?;
+    let method_id = method_id.ok_or_else(|| missing("method"))?;
+    class_id.base10_parse::<u16>()?;
+    method_id.base10_parse::<u16>()?;
+    Ok(MethodAttributes {
+        class_id,
+        method_id,
+        name: name.unwrap_or_else(|| kebab_case(&input.ident.to_string())),
+        synchronous,
+    })
+}
+
+fn inferred_wire_type(ty: &Type) -> Option<&'static str> {
+    let ident = match *ty {
+        Type::Path(ref path) if path.qself.is_none() => &path.path.segments.last()?.ident,
+        _ => return None,
+    };
+    let wire_type = match ident.to_string().as_str() {
+        "u8" => "octet",
+        "u16" => "short",
+        "u32" => "long",
+        "u64" => "longlong",
+        "String" => "shortstr",
+        "Table" => "table",
+        "bool" => "bit",
+        _ => return None,
+    };
+    Some(wire_type)
+}
+
+fn field_wire_type(field: &syn::Field) -> syn::Result<String> {
+    let mut wire_type = None;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("amqp")) {
+        attr.parse_nested_meta(|meta| {
+            match meta.path.get_ident().map(|ident| ident.to_string()) {
+                Some(ref ident) if WIRE_TYPES.contains(&ident.as_str()) => {
+                    wire_type = Some(ident.clone());
+                    Ok(())
+                }
+                _ => Err(meta.error(format!("expected a wire type: {}", WIRE_TYPES.join(", ")))),
+            }
+        })?;
+    }
+    match wire_type {
+        Some(wire_type) => Ok(wire_type),
+        None => {
+            inferred_wire_type(&field.ty).map(str::to_string).ok_or_else(|| {
+                syn::Error::new_spanned(&field.ty,
+                                        "can't infer the wire type, add e.g. #[amqp(longstr)]")
+            })
+        }
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let attributes = method_attributes(&input)?;
+    let fields = match input.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => return Err(syn::Error::new_spanned(&input.ident, "AmqpMethod can only be derived for structs")),
+    };
+    let mut names = vec![];
+    let mut wire_types = vec![];
+    match *fields {
+        Fields::Named(ref named) => {
+            for field in &named.named {
+                names.push(field.ident.clone().expect("named fields have names"));
+                wire_types.push(field_wire_type(field)?);
+            }
+        }
+        Fields::Unit => {}
+        Fields::Unnamed(_) => {
+            return Err(syn::Error::new_spanned(&input.ident,
+                                               "AmqpMethod needs named fields or a unit struct"))
+        }
+    }
+    let readers: Vec<_> = wire_types.iter().map(|ty| format_ident!("read_{}", ty)).collect();
+    let writers: Vec<_> = wire_types.iter().map(|ty| format_ident!("write_{}", ty)).collect();
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let MethodAttributes { class_id, method_id, name, synchronous } = attributes;
+    let construct = match *fields {
+        Fields::Unit => quote!(#ident),
+        _ => quote!(#ident { #(#names: reader.#readers()?,)* }),
+    };
+    Ok(quote! {
+        impl #impl_generics ::amq_proto::Method for #ident #ty_generics #where_clause {
+            const ID: u16 = #method_id;
+            const CLASS_ID: u16 = #class_id;
+            const SYNCHRONOUS: bool = #synchronous;
+
+            fn decode(method_frame: ::amq_proto::MethodFrame) -> ::amq_proto::Result<Self> {
+                Self::decode_with_limits(method_frame, &::amq_proto::DecodeLimits::default())
+            }
+
+            fn decode_with_limits(method_frame: ::amq_proto::MethodFrame,
+                                  limits: &::amq_proto::DecodeLimits)
+                                  -> ::amq_proto::Result<Self> {
+                if (method_frame.class_id, method_frame.method_id) != (#class_id, #method_id) {
+                    return Err(::amq_proto::ErrorKind::Protocol(
+                        "Unexpected method class and id".to_string()).into());
+                }
+                let data = method_frame.arguments.into_inner();
+                #[allow(unused_mut, unused_variables)]
+                let mut reader = ::amq_proto::ArgumentsReader::with_limits(&data, limits);
+                Ok(#construct)
+            }
+
+            fn encode(&self) -> ::amq_proto::Result<::amq_proto::EncodedMethod> {
+                #[allow(unused_mut)]
+                let mut writer = ::amq_proto::ArgumentsWriter::new();
+                #(writer.#writers(&self.#names)?;)*
+                Ok(::amq_proto::EncodedMethod::new(writer.as_bytes()))
+            }
+
+            fn name(&self) -> &'static str {
+                #name
+            }
+        }
+    })
+}
@@ -0,0 +1,158 @@
+//! Decodes a captured AMQP byte stream (one direction of a connection) into a trace
+//! of frames.
+//!
+//! ```sh
+//! amq-proto-dump [--hex | --pcap [--port N]] [--json] [--body-limit N | --full-body] [FILE]
+//! ```
+//!
+//! Reads raw bytes (or a hex dump with `--hex`) from `FILE` or stdin. Malformed
+//! frames are reported with their byte offset and make the exit status 1.
+//!
+//! With the `pcap` feature, `--pcap` reads a pcap or pcapng capture instead and prints
+//! a timeline of every AMQP connection to `--port` (5672 by default).
+
+extern crate amq_proto;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+use amq_proto::{parse_hex, StreamDecoder, StreamEvent, DEFAULT_BODY_LIMIT};
+
+const USAGE: &str = "usage: amq-proto-dump [--hex | --pcap [--port N]] [--json] \
+                     [--body-limit N | --full-body] [FILE]";
+
+struct Options {
+    hex: bool,
+    pcap: bool,
+    /// Only with `--pcap`.
+    port: Option<u16>,
+    json: bool,
+    body_limit: Option<usize>,
+    path: Option<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        hex: false,
+        pcap: false,
+        port: None,
+        json: false,
+        body_limit: Some(DEFAULT_BODY_LIMIT),
+        path: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--hex" => options.hex = true,
+            "--json" => options.json = true,
+            "--pcap" if cfg!(feature = "pcap") => options.pcap = true,
+            "--port" => {
+                let port = args.next().ok_or("--port needs a value")?;
+                options.port = Some(port.parse().map_err(|_| format!("invalid port '{}'", port))?);
+            }
+            "--full-body" => options.body_limit = None,
+            "--body-limit" => {
+                let limit = args.next().ok_or("--body-limit needs a value")?;
+                options.body_limit =
+                    Some(limit.parse().map_err(|_| format!("invalid body limit '{}'", limit))?);
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'\n{}", arg, USAGE)),
+            _ if options.path.is_none() => options.path = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    if options.port.is_some() && !options.pcap {
+        return Err(format!("--port needs --pcap\n{}", USAGE));
+    }
+    Ok(options)
+}
+
+fn read_input(options: &Options) -> Result<Vec<u8>, String> {
+    let mut data = vec![];
+    let read = match options.path {
+        Some(ref path) => {
+            File::open(path).and_then(|mut file| file.read_to_end(&mut data))
+                .map_err(|err| format!("{}: {}", path, err))
+        }
+        None => io::stdin().read_to_end(&mut data).map_err(|err| err.to_string()),
+    };
+    read?;
+    if options.hex && !options.pcap {
+        let text = String::from_utf8(data).map_err(|_| "hex input is not text".to_string())?;
+        return parse_hex(&text).map_err(|err| err.to_string());
+    }
+    Ok(data)
+}
+
+fn write_line(line: &str) {
+    if writeln!(io::stdout(), "{}", line).is_err() {
+        process::exit(2);
+    }
+}
+
+/// Returns whether there were malformed frames.
+fn dump_stream(options: &Options, data: &[u8]) -> bool {
+    let mut malformed = false;
+    for item in StreamDecoder::new(data) {
+        malformed |= matches!(item.event, StreamEvent::Malformed(_));
+        write_line(&if options.json {
+            item.to_json(options.body_limit)
+        } else {
+            item.to_text(options.body_limit)
+        });
+    }
+    malformed
+}
+
+#[cfg(feature = "pcap")]
+fn dump_capture(options: &Options, data: &[u8]) -> bool {
+    use amq_proto::pcap::CaptureReader;
+
+    let reader = options.port.map_or_else(CaptureReader::new, CaptureReader::with_port);
+    let connections = reader.read(data).unwrap_or_else(|err| {
+        eprintln!("amq-proto-dump: {}", err);
+        process::exit(2);
+    });
+    let mut malformed = false;
+    for connection in connections {
+        if !options.json {
+            write_line(&format!("# {} -> {}", connection.client, connection.server));
+        }
+        for entry in connection.timeline() {
+            malformed |= matches!(entry.item.event, StreamEvent::Malformed(_));
+            if options.json {
+                write_line(&entry.to_json(&connection, options.body_limit));
+            } else {
+                write_line(&entry.to_text(options.body_limit));
+            }
+        }
+    }
+    malformed
+}
+
+#[cfg(not(feature = "pcap"))]
+fn dump_capture(_options: &Options, _data: &[u8]) -> bool {
+    unreachable!("--pcap needs the pcap feature")
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        process::exit(2);
+    });
+    let data = read_input(&options).unwrap_or_else(|message| {
+        eprintln!("amq-proto-dump: {}", message);
+        process::exit(2);
+    });
+    let malformed = if options.pcap {
+        dump_capture(&options, &data)
+    } else {
+        dump_stream(&options, &data)
+    };
+    if malformed {
+        process::exit(1);
+    }
+}
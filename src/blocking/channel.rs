@@ -0,0 +1,244 @@
+use std::sync::{mpsc, Arc, Condvar};
+
+use crate::client_core::{expect_get_reply, expect_reply};
+use crate::consumer::Delivery;
+use crate::content::{GetMessage, Incoming, Returned};
+use crate::method::Method;
+use crate::protocol::{basic, channel, confirm, exchange, queue, tx};
+use crate::protocol::basic::BasicProperties;
+use crate::error::*;
+use super::connection::Inner;
+
+/// The deliveries of a consumer started with `SyncChannel::basic_consume`.
+/// The receiver is disconnected once the consumer is cancelled.
+#[derive(Debug)]
+pub struct Subscription {
+    pub consumer_tag: String,
+    pub deliveries: mpsc::Receiver<Delivery>,
+}
+
+/// An open channel of a `SyncConnection`.
+///
+/// Synchronous methods always wait for the server's reply, so their `nowait` flag is ignored.
+pub struct SyncChannel {
+    id: u16,
+    inner: Arc<Inner>,
+    events: mpsc::Receiver<Incoming>,
+    confirmed: Arc<Condvar>,
+}
+
+impl SyncChannel {
+    pub(crate) fn new(id: u16,
+                      inner: Arc<Inner>,
+                      events: mpsc::Receiver<Incoming>,
+                      confirmed: Arc<Condvar>)
+                      -> Self {
+        SyncChannel {
+            id,
+            inner,
+            events,
+            confirmed,
+        }
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub(crate) fn open(&mut self) -> Result<()> {
+        let _: channel::OpenOk = self.rpc(&channel::Open { out_of_band: "".to_string() })?;
+        Ok(())
+    }
+
+    fn send<M: Method>(&self, method: &M) -> Result<()> {
+        self.inner.send_method(self.id, method)
+    }
+
+    fn next_event(&mut self) -> Result<Incoming> {
+        match self.events.recv() {
+            Ok(event) => Ok(event),
+            Err(_) => Err(self.inner.core.channel_error(self.id)),
+        }
+    }
+
+    /// Sends a synchronous method and waits for its reply.
+    pub fn rpc<M: Method, R: Method>(&mut self, method: &M) -> Result<R> {
+        self.send(method)?;
+        expect_reply(self.next_event()?)
+    }
+
+    pub fn exchange_declare(&mut self, mut declare: exchange::Declare) -> Result<()> {
+        declare.nowait = false;
+        let _: exchange::DeclareOk = self.rpc(&declare)?;
+        Ok(())
+    }
+
+    pub fn exchange_delete(&mut self, mut delete: exchange::Delete) -> Result<()> {
+        delete.nowait = false;
+        let _: exchange::DeleteOk = self.rpc(&delete)?;
+        Ok(())
+    }
+
+    pub fn exchange_bind(&mut self, mut bind: exchange::Bind) -> Result<()> {
+        bind.nowait = false;
+        let _: exchange::BindOk = self.rpc(&bind)?;
+        Ok(())
+    }
+
+    pub fn exchange_unbind(&mut self, mut unbind: exchange::Unbind) -> Result<()> {
+        unbind.nowait = false;
+        let _: exchange::UnbindOk = self.rpc(&unbind)?;
+        Ok(())
+    }
+
+    pub fn queue_declare(&mut self, mut declare: queue::Declare) -> Result<queue::DeclareOk> {
+        declare.nowait = false;
+        self.rpc(&declare)
+    }
+
+    pub fn queue_bind(&mut self, mut bind: queue::Bind) -> Result<()> {
+        bind.nowait = false;
+        let _: queue::BindOk = self.rpc(&bind)?;
+        Ok(())
+    }
+
+    pub fn queue_unbind(&mut self, unbind: queue::Unbind) -> Result<()> {
+        let _: queue::UnbindOk = self.rpc(&unbind)?;
+        Ok(())
+    }
+
+    /// Returns the number of purged messages.
+    pub fn queue_purge(&mut self, mut purge: queue::Purge) -> Result<u32> {
+        purge.nowait = false;
+        let purge_ok: queue::PurgeOk = self.rpc(&purge)?;
+        Ok(purge_ok.message_count)
+    }
+
+    /// Returns the number of deleted messages.
+    pub fn queue_delete(&mut self, mut delete: queue::Delete) -> Result<u32> {
+        delete.nowait = false;
+        let delete_ok: queue::DeleteOk = self.rpc(&delete)?;
+        Ok(delete_ok.message_count)
+    }
+
+    pub fn basic_qos(&mut self, qos: basic::Qos) -> Result<()> {
+        let _: basic::QosOk = self.rpc(&qos)?;
+        Ok(())
+    }
+
+    pub fn basic_consume(&mut self, mut consume: basic::Consume) -> Result<Subscription> {
+        consume.nowait = false;
+        let (deliveries_tx, deliveries) = mpsc::channel();
+        self.inner.core.start_consume(self.id,
+                               Box::new(move |delivery: Delivery| {
+                                   let _ = deliveries_tx.send(delivery);
+                               }))?;
+        match self.rpc::<_, basic::ConsumeOk>(&consume) {
+            Ok(consume_ok) => {
+                Ok(Subscription {
+                    consumer_tag: consume_ok.consumer_tag,
+                    deliveries,
+                })
+            }
+            Err(err) => {
+                self.inner.core.cancel_consume(self.id);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn basic_cancel(&mut self, consumer_tag: &str) -> Result<()> {
+        let cancel = basic::Cancel {
+            consumer_tag: consumer_tag.to_string(),
+            nowait: false,
+        };
+        let _: basic::CancelOk = self.rpc(&cancel)?;
+        Ok(())
+    }
+
+    pub fn basic_publish(&mut self,
+                         publish: basic::Publish,
+                         properties: BasicProperties,
+                         body: &[u8])
+                         -> Result<()> {
+        let frames = self.inner.core.publish(self.id, &publish, &properties, body)?;
+        self.inner.write_frames(&frames)
+    }
+
+    /// Returns `None` when the queue is empty.
+    pub fn basic_get(&mut self, get: basic::Get) -> Result<Option<GetMessage>> {
+        self.send(&get)?;
+        expect_get_reply(self.next_event()?)
+    }
+
+    pub fn basic_ack(&mut self, delivery_tag: u64, multiple: bool) -> Result<()> {
+        self.send(&basic::Ack {
+            delivery_tag,
+            multiple,
+        })
+    }
+
+    pub fn basic_nack(&mut self, delivery_tag: u64, multiple: bool, requeue: bool) -> Result<()> {
+        self.send(&basic::Nack {
+            delivery_tag,
+            multiple,
+            requeue,
+        })
+    }
+
+    pub fn basic_reject(&mut self, delivery_tag: u64, requeue: bool) -> Result<()> {
+        self.send(&basic::Reject {
+            delivery_tag,
+            requeue,
+        })
+    }
+
+    pub fn basic_recover(&mut self, requeue: bool) -> Result<()> {
+        let _: basic::RecoverOk = self.rpc(&basic::Recover { requeue })?;
+        Ok(())
+    }
+
+    /// Takes the messages returned by the server since the last call.
+    pub fn take_returns(&mut self) -> Vec<Returned> {
+        self.inner.core.take_returns(self.id)
+    }
+
+    /// Puts the channel into publisher confirm mode.
+    pub fn confirm_select(&mut self) -> Result<()> {
+        let _: confirm::SelectOk = self.rpc(&confirm::Select { nowait: false })?;
+        self.inner.core.confirm_select_ok(self.id)
+    }
+
+    /// Blocks until all messages published so far are confirmed.
+    /// Returns false if any of them was nacked by the server.
+    pub fn wait_for_confirms(&mut self) -> Result<bool> {
+        self.inner.core.wait_for_confirms(self.id, &self.confirmed)
+    }
+
+    pub fn tx_select(&mut self) -> Result<()> {
+        let _: tx::SelectOk = self.rpc(&tx::Select)?;
+        Ok(())
+    }
+
+    pub fn tx_commit(&mut self) -> Result<()> {
+        let _: tx::CommitOk = self.rpc(&tx::Commit)?;
+        Ok(())
+    }
+
+    pub fn tx_rollback(&mut self) -> Result<()> {
+        let _: tx::RollbackOk = self.rpc(&tx::Rollback)?;
+        Ok(())
+    }
+
+    pub fn close(mut self) -> Result<()> {
+        let close = channel::Close {
+            reply_code: 200,
+            reply_text: "Goodbye".to_string(),
+            class_id: 0,
+            method_id: 0,
+        };
+        let result: Result<channel::CloseOk> = self.rpc(&close);
+        self.inner.core.remove_channel(self.id);
+        result.map(|_| ())
+    }
+}
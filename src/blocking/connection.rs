@@ -0,0 +1,288 @@
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::client_core::{self, encode_frames, ChannelSlot, ClientCore};
+use crate::content::Incoming;
+use crate::framing::{Frame, FrameType, MethodFrame, ProtocolHeader};
+use crate::method::Method;
+use crate::handshake::{Handshake, Negotiated, Step};
+use crate::peer_properties::{Capability, ClientProperties, ServerProperties};
+use crate::protocol::connection;
+use crate::uri::AmqpUri;
+use crate::error::*;
+use super::channel::SyncChannel;
+
+type BoxedWriter = Box<dyn Write + Send>;
+
+fn write_frames(writer: &mut BoxedWriter, frames: &[Frame]) -> Result<()> {
+    writer.write_all(&encode_frames(frames)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the next method on channel 0, skipping heartbeats.
+fn read_method<R: Read>(reader: &mut R) -> Result<MethodFrame> {
+    loop {
+        let frame = Frame::decode(reader)?;
+        if frame.frame_type == FrameType::HEARTBEAT {
+            continue;
+        }
+        if frame.channel != 0 {
+            return Err(ErrorKind::Protocol(format!("Unexpected frame on channel {} during \
+                                                    handshake",
+                                                   frame.channel))
+                .into());
+        }
+        return MethodFrame::decode(&frame);
+    }
+}
+
+/// Where the reader thread hands over what it read for a channel.
+pub(crate) struct Slot {
+    events: mpsc::Sender<Incoming>,
+    confirmed: Arc<Condvar>,
+}
+
+impl ChannelSlot for Slot {
+    fn send(&self, incoming: Incoming) {
+        let _ = self.events.send(incoming);
+    }
+
+    fn notify(&self) {
+        self.confirmed.notify_all();
+    }
+}
+
+pub(crate) struct Inner {
+    writer: Mutex<BoxedWriter>,
+    pub core: ClientCore<Slot>,
+}
+
+impl Inner {
+    pub fn write_frames(&self, frames: &[Frame]) -> Result<()> {
+        write_frames(&mut self.writer.lock().unwrap(), frames)
+    }
+
+    pub fn send_method<M: Method>(&self, channel: u16, method: &M) -> Result<()> {
+        self.write_frames(&[method.to_frame(channel)?])
+    }
+}
+
+/// A blocking AMQP connection over any `Read` and `Write` halves of a stream.
+///
+/// Incoming frames are read by a background thread, and heartbeats are sent by
+/// another one when the server asks for them.
+pub struct SyncConnection {
+    inner: Arc<Inner>,
+    events: mpsc::Receiver<MethodFrame>,
+    server_properties: ServerProperties,
+    capabilities: Vec<Capability>,
+    tune: connection::TuneOk,
+    reader: Option<JoinHandle<()>>,
+    heartbeat: Option<(mpsc::Sender<()>, JoinHandle<()>)>,
+    stream: Option<TcpStream>,
+}
+
+impl SyncConnection {
+    /// Performs the handshake using the credentials, vhost and tuning parameters of `uri`.
+    pub fn open(stream: TcpStream, uri: &AmqpUri) -> Result<SyncConnection> {
+        SyncConnection::open_with_properties(stream, uri, ClientProperties::new())
+    }
+
+    /// Like `open`, applying the `connection_timeout` of `uri` to the handshake.
+    pub fn open_with_properties(stream: TcpStream,
+                                uri: &AmqpUri,
+                                client_properties: ClientProperties)
+                                -> Result<SyncConnection> {
+        stream.set_read_timeout(uri.connection_timeout)?;
+        let reader = stream.try_clone()?;
+        let writer = stream.try_clone()?;
+        let mut connection = SyncConnection::from_parts_with(reader, writer, uri, client_properties,
+                                                             || stream.set_read_timeout(None))?;
+        connection.stream = Some(stream);
+        Ok(connection)
+    }
+
+    /// Opens a connection over separate reading and writing halves.
+    ///
+    /// Without a `TcpStream` to shut down, the reader thread only stops once the
+    /// server closes the connection.
+    pub fn from_parts<R, W>(reader: R,
+                            writer: W,
+                            uri: &AmqpUri,
+                            client_properties: ClientProperties)
+                            -> Result<SyncConnection>
+        where R: Read + Send + 'static,
+              W: Write + Send + 'static
+    {
+        SyncConnection::from_parts_with(reader, writer, uri, client_properties, || Ok(()))
+    }
+
+    fn from_parts_with<R, W, F>(reader: R,
+                                writer: W,
+                                uri: &AmqpUri,
+                                client_properties: ClientProperties,
+                                handshake_done: F)
+                                -> Result<SyncConnection>
+        where R: Read + Send + 'static,
+              W: Write + Send + 'static,
+              F: FnOnce() -> ::std::io::Result<()>
+    {
+        let mut reader = BufReader::new(reader);
+        let mut writer: BoxedWriter = Box::new(writer);
+        writer.write_all(&ProtocolHeader::default().encode())?;
+        writer.flush()?;
+
+        let mut handshake = Handshake::new(uri, client_properties);
+        let negotiated = loop {
+            match handshake.handle(read_method(&mut reader)?)? {
+                Step::Send(frames) => write_frames(&mut writer, &frames)?,
+                Step::Done(negotiated) => break negotiated,
+            }
+        };
+        handshake_done()?;
+        let core = ClientCore::new(&negotiated);
+        let Negotiated { server_properties, capabilities, tune: tune_ok } = negotiated;
+        let inner = Arc::new(Inner {
+            writer: Mutex::new(writer),
+            core,
+        });
+        let (events_tx, events) = mpsc::channel();
+        let reader = {
+            let inner = inner.clone();
+            thread::Builder::new().name("amqp-reader".to_string())
+                .spawn(move || read_loop(reader, inner, events_tx))?
+        };
+        let heartbeat = if tune_ok.heartbeat > 0 {
+            let (stop, stopped) = mpsc::channel();
+            let inner = inner.clone();
+            let heartbeat = tune_ok.heartbeat;
+            let handle = thread::Builder::new().name("amqp-heartbeat".to_string())
+                .spawn(move || heartbeat_loop(inner, heartbeat, stopped))?;
+            Some((stop, handle))
+        } else {
+            None
+        };
+        Ok(SyncConnection {
+            inner,
+            events,
+            server_properties,
+            capabilities,
+            tune: tune_ok,
+            reader: Some(reader),
+            heartbeat,
+            stream: None,
+        })
+    }
+
+    pub fn server_properties(&self) -> &ServerProperties {
+        &self.server_properties
+    }
+
+    /// Capabilities supported by both this client and the server.
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    /// The negotiated `channel_max`, `frame_max` and `heartbeat`.
+    pub fn tune(&self) -> &connection::TuneOk {
+        &self.tune
+    }
+
+    /// True while the server has blocked publishing with `connection.blocked`.
+    pub fn is_blocked(&self) -> bool {
+        self.inner.core.is_blocked()
+    }
+
+    pub fn open_channel(&self) -> Result<SyncChannel> {
+        let (events_tx, events) = mpsc::channel();
+        let confirmed = Arc::new(Condvar::new());
+        let id = self.inner.core.open_channel(Slot {
+                events: events_tx,
+                confirmed: confirmed.clone(),
+            })?;
+        let mut channel = SyncChannel::new(id, self.inner.clone(), events, confirmed);
+        if let Err(err) = channel.open() {
+            self.inner.core.remove_channel(id);
+            return Err(err);
+        }
+        Ok(channel)
+    }
+
+    /// Closes the connection with `reply-success`, waits for `connection.close-ok`
+    /// and stops the background threads.
+    pub fn close(mut self) -> Result<()> {
+        let close = connection::Close {
+            reply_code: 200,
+            reply_text: "Goodbye".to_string(),
+            class_id: 0,
+            method_id: 0,
+        };
+        self.inner.send_method(0, &close)?;
+        while let Ok(method_frame) = self.events.recv() {
+            if method_frame.method_name() == "connection.close-ok" {
+                self.inner.core.shutdown(Some((200, "Goodbye".to_string())));
+                // The reader thread stops right after `close-ok`.
+                if let Some(reader) = self.reader.take() {
+                    let _ = reader.join();
+                }
+                return Ok(());
+            }
+        }
+        Err(self.inner.core.closed_error())
+    }
+}
+
+impl Drop for SyncConnection {
+    fn drop(&mut self) {
+        if let Some((stop, handle)) = self.heartbeat.take() {
+            drop(stop);
+            let _ = handle.join();
+        }
+        if let Some(ref stream) = self.stream {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        self.inner.core.shutdown(None);
+    }
+}
+
+fn heartbeat_loop(inner: Arc<Inner>, heartbeat: u16, stopped: mpsc::Receiver<()>) {
+    let (heartbeat_frame, period) = client_core::heartbeat(heartbeat);
+    while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(period) {
+        if inner.write_frames(::std::slice::from_ref(&heartbeat_frame)).is_err() {
+            break;
+        }
+    }
+}
+
+fn read_loop<R: Read>(mut reader: R, inner: Arc<Inner>, events: mpsc::Sender<MethodFrame>) {
+    loop {
+        let result = match Frame::decode(&mut reader) {
+            Ok(frame) => handle_frame(&inner, &events, frame),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => {
+                debug!("Connection reader stopped: {}", err);
+                break;
+            }
+        }
+    }
+    inner.core.shutdown(None);
+}
+
+/// Handles one incoming frame, returning false once the connection is closed.
+fn handle_frame(inner: &Inner, events: &mpsc::Sender<MethodFrame>, frame: Frame) -> Result<bool> {
+    let handled = inner.core.handle_frame(frame)?;
+    if !handled.send.is_empty() {
+        inner.write_frames(&handled.send)?;
+    }
+    for method_frame in handled.connection {
+        let _ = events.send(method_frame);
+    }
+    Ok(!handled.closed)
+}
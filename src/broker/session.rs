@@ -0,0 +1,415 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::codec::{AmqpMessage, FrameReader};
+use crate::content::{ContentAssembler, Incoming};
+use crate::framing::{DecodeLimits, Frame, FrameType, MethodFrame, ProtocolHeader};
+use crate::method::{expect_method_with_limits, Method};
+use crate::peer_properties::{Capability, ServerProperties};
+use crate::protocol::{basic, channel, confirm, connection, exchange, queue};
+use crate::protocol::constants::FRAME_MIN_SIZE;
+use crate::protocol::basic::BasicProperties;
+use crate::table::{Table, TableEntry};
+use crate::error::*;
+use super::state::*;
+
+const CHANNEL_MAX: u16 = 2047;
+const FRAME_MAX: u32 = 131072;
+
+/// Serves one client connection until it is closed by either side.
+pub async fn serve<S>(state: Arc<Mutex<State>>, stream: S) -> Result<()>
+    where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    let (read_half, mut write_half) = io::split(stream);
+    let (sender, mut outgoing) = mpsc::unbounded_channel::<Vec<u8>>();
+    let writer = tokio::spawn(async move {
+        while let Some(bytes) = outgoing.recv().await {
+            write_half.write_all(&bytes).await?;
+        }
+        write_half.shutdown().await
+    });
+    let mut session = Session {
+        state: state.clone(),
+        reader: FrameReader::new(read_half),
+        sender,
+        connection: 0,
+        assemblers: HashMap::new(),
+        closing: HashSet::new(),
+        limits: DecodeLimits::default(),
+    };
+    let result = session.run().await;
+    state.lock().unwrap().remove_connection(session.connection);
+    // The writer stops once every sender, including the one held by the state, is gone.
+    drop(session);
+    let _ = writer.await;
+    result
+}
+
+struct Session<R> {
+    state: Arc<Mutex<State>>,
+    reader: FrameReader<R>,
+    sender: mpsc::UnboundedSender<Vec<u8>>,
+    connection: u64,
+    assemblers: HashMap<u16, ContentAssembler>,
+    /// Channels closed by the server, waiting for `channel.close-ok`.
+    closing: HashSet<u16>,
+    /// The limits of the assemblers, also applied to the arguments of every method.
+    limits: DecodeLimits,
+}
+
+impl<R: AsyncRead + Unpin> Session<R> {
+    fn send_method<M: Method>(&self, channel: u16, method: &M) -> Result<()> {
+        let _ = self.sender.send(method.to_frame(channel)?.encode()?);
+        Ok(())
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        match self.reader.read_message().await? {
+            AmqpMessage::ProtocolHeader(ref header) if *header == ProtocolHeader::default() => {}
+            _ => {
+                let _ = self.sender.send(ProtocolHeader::default().encode().to_vec());
+                return Err(ErrorKind::Protocol("Unsupported protocol header".to_string()).into());
+            }
+        }
+        self.handshake().await?;
+        loop {
+            let frame = self.reader.read_frame().await?;
+            let closed = {
+                let state = self.state.clone();
+                let mut state = state.lock().unwrap();
+                self.handle_frame(&mut state, frame)
+            };
+            match closed {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err((exception, class_id, method_id)) => {
+                    return self.close_connection(exception, class_id, method_id).await
+                }
+            }
+        }
+    }
+
+    async fn handshake(&mut self) -> Result<()> {
+        let mut capabilities = Table::new();
+        for capability in &[Capability::PublisherConfirms,
+                            Capability::ConsumerCancelNotify,
+                            Capability::BasicNack] {
+            capabilities.insert(capability.name().to_string(), TableEntry::Bool(true));
+        }
+        let mut server_properties = Table::new();
+        server_properties.insert("product".to_string(),
+                                 TableEntry::LongString("amq-proto test broker".to_string()));
+        server_properties.insert("capabilities".to_string(),
+                                 TableEntry::FieldTable(capabilities));
+        self.send_method(0,
+                         &connection::Start {
+                             version_major: 0,
+                             version_minor: 9,
+                             server_properties,
+                             mechanisms: "PLAIN AMQPLAIN".to_string(),
+                             locales: "en_US".to_string(),
+                         })?;
+        let start_ok: connection::StartOk = expect_method_with_limits(self.reader.read_method().await?, &self.limits)?;
+        let client_properties = ServerProperties::from_table(&start_ok.client_properties);
+        self.send_method(0,
+                         &connection::Tune {
+                             channel_max: CHANNEL_MAX,
+                             frame_max: FRAME_MAX,
+                             heartbeat: 0,
+                         })?;
+        let tune_ok: connection::TuneOk = expect_method_with_limits(self.reader.read_method().await?, &self.limits)?;
+        let frame_max = match tune_ok.frame_max {
+            0 => FRAME_MAX,
+            frame_max if frame_max < FRAME_MIN_SIZE => {
+                return Err(ErrorKind::Protocol(format!("frame_max {} is too small", frame_max))
+                    .into())
+            }
+            frame_max => frame_max.min(FRAME_MAX),
+        };
+        self.reader.set_frame_max(frame_max);
+        let _: connection::Open = expect_method_with_limits(self.reader.read_method().await?, &self.limits)?;
+        self.send_method(0, &connection::OpenOk { known_hosts: "".to_string() })?;
+        self.connection = self.state
+            .lock()
+            .unwrap()
+            .add_connection(self.sender.clone(),
+                            frame_max,
+                            client_properties.supports(Capability::ConsumerCancelNotify));
+        Ok(())
+    }
+
+    /// Sends `connection.close` and waits for `connection.close-ok`, dropping other frames.
+    async fn close_connection(&mut self,
+                              exception: Exception,
+                              class_id: u16,
+                              method_id: u16)
+                              -> Result<()> {
+        self.send_method(0,
+                         &connection::Close {
+                             reply_code: exception.code,
+                             reply_text: exception.text,
+                             class_id,
+                             method_id,
+                         })?;
+        loop {
+            let frame = self.reader.read_frame().await?;
+            if frame.channel == 0 && frame.frame_type == FrameType::METHOD &&
+               MethodFrame::decode(&frame)?.method_name() == "connection.close-ok" {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handles one frame, returning true once the client closed the connection.
+    /// Connection exceptions are returned together with the offending method's ids.
+    fn handle_frame(&mut self,
+                    state: &mut State,
+                    frame: Frame)
+                    -> ::std::result::Result<bool, (Exception, u16, u16)> {
+        if frame.frame_type == FrameType::HEARTBEAT {
+            return Ok(false);
+        }
+        let channel = frame.channel;
+        if channel == 0 {
+            let method_frame = MethodFrame::decode(&frame)
+                .map_err(|err| (Exception::connection(UNEXPECTED_FRAME, err.to_string()), 0, 0))?;
+            return match method_frame.method_name() {
+                "connection.close" => {
+                    let _ = self.send_method(0, &connection::CloseOk);
+                    Ok(true)
+                }
+                name => {
+                    Err((Exception::connection(COMMAND_INVALID,
+                                               format!("unexpected method {}", name)),
+                         method_frame.class_id,
+                         method_frame.method_id))
+                }
+            };
+        }
+
+        if self.closing.contains(&channel) {
+            if let Ok(method_frame) = MethodFrame::decode(&frame) {
+                match method_frame.method_name() {
+                    "channel.close-ok" => {
+                        self.closing.remove(&channel);
+                    }
+                    "channel.close" => {
+                        self.closing.remove(&channel);
+                        let _ = self.send_method(channel, &channel::CloseOk);
+                    }
+                    _ => {}
+                }
+            }
+            return Ok(false);
+        }
+
+        if !state.has_channel(self.connection, channel) {
+            let method_frame = MethodFrame::decode(&frame)
+                .map_err(|err| (Exception::connection(CHANNEL_ERROR, err.to_string()), 0, 0))?;
+            let ids = (method_frame.class_id, method_frame.method_id);
+            if method_frame.method_name() != "channel.open" || channel > CHANNEL_MAX {
+                return Err((Exception::connection(CHANNEL_ERROR,
+                                                  format!("channel {} is not open", channel)),
+                            ids.0,
+                            ids.1));
+            }
+            state.open_channel(self.connection, channel)
+                .map_err(|exception| (exception, ids.0, ids.1))?;
+            let _ = self.send_method(channel, &channel::OpenOk { channel_id: "".to_string() });
+            return Ok(false);
+        }
+
+        let limits = self.limits;
+        let incoming = self.assemblers
+            .entry(channel)
+            .or_insert_with(|| ContentAssembler::with_limits(limits))
+            .push(&frame)
+            .map_err(|err| (Exception::connection(UNEXPECTED_FRAME, err.to_string()), 0, 0))?;
+        let (method_frame, content) = match incoming {
+            None => return Ok(false),
+            Some(Incoming::Method(method_frame)) => (method_frame, None),
+            Some(Incoming::Content(content)) => {
+                let content = *content;
+                (content.method, Some((content.properties, content.body)))
+            }
+        };
+        let ids = (method_frame.class_id, method_frame.method_id);
+        if method_frame.method_name() == "channel.close" {
+            state.close_channel(self.connection, channel);
+            self.assemblers.remove(&channel);
+            let _ = self.send_method(channel, &channel::CloseOk);
+            return Ok(false);
+        }
+        match handle_method(state, &self.limits, self.connection, channel, method_frame, content) {
+            Ok(()) => Ok(false),
+            Err(exception) => {
+                if exception.connection {
+                    return Err((exception, ids.0, ids.1));
+                }
+                state.close_channel(self.connection, channel);
+                self.assemblers.remove(&channel);
+                self.closing.insert(channel);
+                let _ = self.send_method(channel,
+                                         &channel::Close {
+                                             reply_code: exception.code,
+                                             reply_text: exception.text,
+                                             class_id: ids.0,
+                                             method_id: ids.1,
+                                         });
+                Ok(false)
+            }
+        }
+    }
+}
+
+fn decode<M: Method>(method_frame: MethodFrame, limits: &DecodeLimits) -> Handled<M> {
+    M::decode_with_limits(method_frame, limits).map_err(|err| Exception::connection(SYNTAX_ERROR, err.to_string()))
+}
+
+/// Handles a method received on an open channel, sending its reply.
+fn handle_method(state: &mut State,
+                 limits: &DecodeLimits,
+                 connection: u64,
+                 channel: u16,
+                 method_frame: MethodFrame,
+                 content: Option<(BasicProperties, Vec<u8>)>)
+                 -> Handled<()> {
+    match method_frame.method_name() {
+        "channel.flow" => {
+            let flow: channel::Flow = decode(method_frame, limits)?;
+            state.send_method(connection, channel, &channel::FlowOk { active: flow.active });
+        }
+        "channel.flow-ok" => {}
+        "exchange.declare" => {
+            let declare: exchange::Declare = decode(method_frame, limits)?;
+            state.exchange_declare(&declare)?;
+            if !declare.nowait {
+                state.send_method(connection, channel, &exchange::DeclareOk);
+            }
+        }
+        "exchange.delete" => {
+            let delete: exchange::Delete = decode(method_frame, limits)?;
+            state.exchange_delete(&delete)?;
+            if !delete.nowait {
+                state.send_method(connection, channel, &exchange::DeleteOk);
+            }
+        }
+        "queue.declare" => {
+            let declare: queue::Declare = decode(method_frame, limits)?;
+            let declare_ok = state.queue_declare(connection, channel, &declare)?;
+            if !declare.nowait {
+                state.send_method(connection, channel, &declare_ok);
+            }
+        }
+        "queue.bind" => {
+            let bind: queue::Bind = decode(method_frame, limits)?;
+            state.queue_bind(connection, channel, &bind)?;
+            if !bind.nowait {
+                state.send_method(connection, channel, &queue::BindOk);
+            }
+        }
+        "queue.unbind" => {
+            let unbind: queue::Unbind = decode(method_frame, limits)?;
+            state.queue_unbind(connection, channel, &unbind)?;
+            state.send_method(connection, channel, &queue::UnbindOk);
+        }
+        "queue.purge" => {
+            let purge: queue::Purge = decode(method_frame, limits)?;
+            let message_count = state.queue_purge(connection, channel, &purge)?;
+            if !purge.nowait {
+                state.send_method(connection, channel, &queue::PurgeOk { message_count });
+            }
+        }
+        "queue.delete" => {
+            let delete: queue::Delete = decode(method_frame, limits)?;
+            let message_count = state.queue_delete(connection, channel, &delete)?;
+            if !delete.nowait {
+                state.send_method(connection, channel, &queue::DeleteOk { message_count });
+            }
+        }
+        "basic.qos" => {
+            let qos: basic::Qos = decode(method_frame, limits)?;
+            state.basic_qos(connection, channel, &qos)?;
+            state.send_method(connection, channel, &basic::QosOk);
+        }
+        "basic.consume" => {
+            let consume: basic::Consume = decode(method_frame, limits)?;
+            let (queue, consumer_tag) = state.basic_consume(connection, channel, &consume)?;
+            if !consume.nowait {
+                state.send_method(connection, channel, &basic::ConsumeOk { consumer_tag });
+            }
+            state.dispatch(&queue);
+        }
+        "basic.cancel" => {
+            let cancel: basic::Cancel = decode(method_frame, limits)?;
+            state.basic_cancel(connection, channel, &cancel.consumer_tag);
+            if !cancel.nowait {
+                state.send_method(connection,
+                                  channel,
+                                  &basic::CancelOk { consumer_tag: cancel.consumer_tag });
+            }
+        }
+        "basic.publish" => {
+            let (properties, body) = content.unwrap_or_default();
+            let publish: basic::Publish = decode(method_frame, limits)?;
+            state.basic_publish(connection, channel, &publish, properties, body)?;
+        }
+        "basic.get" => {
+            let get: basic::Get = decode(method_frame, limits)?;
+            match state.basic_get(connection, channel, &get)? {
+                Some((get_ok, message)) => state.send_content(connection, channel, &get_ok, &message),
+                None => {
+                    state.send_method(connection,
+                                      channel,
+                                      &basic::GetEmpty { cluster_id: "".to_string() })
+                }
+            }
+        }
+        "basic.ack" => {
+            let ack: basic::Ack = decode(method_frame, limits)?;
+            state.basic_ack(connection, channel, ack.delivery_tag, ack.multiple)?;
+        }
+        "basic.nack" => {
+            let nack: basic::Nack = decode(method_frame, limits)?;
+            state.basic_nack(connection,
+                            channel,
+                            nack.delivery_tag,
+                            nack.multiple,
+                            nack.requeue)?;
+        }
+        "basic.reject" => {
+            let reject: basic::Reject = decode(method_frame, limits)?;
+            state.basic_nack(connection, channel, reject.delivery_tag, false, reject.requeue)?;
+        }
+        "basic.recover-async" => state.basic_recover(connection, channel)?,
+        "basic.recover" => {
+            state.basic_recover(connection, channel)?;
+            state.send_method(connection, channel, &basic::RecoverOk);
+        }
+        "confirm.select" => {
+            let select: confirm::Select = decode(method_frame, limits)?;
+            state.confirm_select(connection, channel)?;
+            if !select.nowait {
+                state.send_method(connection, channel, &confirm::SelectOk);
+            }
+        }
+        "channel.open" => {
+            return Err(Exception::connection(CHANNEL_ERROR,
+                                             format!("channel {} is already open", channel)))
+        }
+        name @ "exchange.bind" |
+        name @ "exchange.unbind" |
+        name @ "tx.select" |
+        name @ "tx.commit" |
+        name @ "tx.rollback" => {
+            return Err(Exception::connection(NOT_IMPLEMENTED, format!("{} is not supported", name)))
+        }
+        name => {
+            return Err(Exception::connection(COMMAND_INVALID, format!("unexpected method {}", name)))
+        }
+    }
+    Ok(())
+}
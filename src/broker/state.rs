@@ -0,0 +1,923 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use tokio::sync::mpsc;
+
+use crate::content::encode_content;
+use crate::headers::HeadersMatcher;
+use crate::framing::Frame;
+use crate::method::Method;
+use crate::protocol::{basic, exchange, queue};
+use crate::protocol::basic::BasicProperties;
+use crate::table::Table;
+use crate::topic::{TopicPattern, TopicTrie};
+
+pub use crate::protocol::constants::{ACCESS_REFUSED, CHANNEL_ERROR, COMMAND_INVALID, NOT_ALLOWED,
+                                     NOT_FOUND, NOT_IMPLEMENTED, NO_ROUTE, PRECONDITION_FAILED,
+                                     RESOURCE_LOCKED, SYNTAX_ERROR, UNEXPECTED_FRAME};
+
+/// An AMQP exception raised while handling a client method.
+/// Channel exceptions close the channel, connection exceptions the whole connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exception {
+    pub code: u16,
+    pub text: String,
+    pub connection: bool,
+}
+
+impl Exception {
+    pub fn channel<S: Into<String>>(code: u16, text: S) -> Self {
+        Exception {
+            code,
+            text: text.into(),
+            connection: false,
+        }
+    }
+
+    pub fn connection<S: Into<String>>(code: u16, text: S) -> Self {
+        Exception {
+            code,
+            text: text.into(),
+            connection: true,
+        }
+    }
+}
+
+pub type Handled<T> = ::std::result::Result<T, Exception>;
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub exchange: String,
+    pub routing_key: String,
+    pub properties: BasicProperties,
+    pub body: Vec<u8>,
+    pub redelivered: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExchangeKind {
+    Direct,
+    Fanout,
+    Topic,
+    Headers,
+}
+
+impl ExchangeKind {
+    fn from_name(name: &str) -> Option<ExchangeKind> {
+        match name {
+            "direct" => Some(ExchangeKind::Direct),
+            "fanout" => Some(ExchangeKind::Fanout),
+            "topic" => Some(ExchangeKind::Topic),
+            "headers" => Some(ExchangeKind::Headers),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            ExchangeKind::Direct => "direct",
+            ExchangeKind::Fanout => "fanout",
+            ExchangeKind::Topic => "topic",
+            ExchangeKind::Headers => "headers",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Binding {
+    queue: String,
+    routing_key: String,
+    arguments: Table,
+}
+
+#[derive(Debug)]
+struct Exchange {
+    kind: ExchangeKind,
+    bindings: Vec<Binding>,
+    /// The queues of a topic exchange's bindings, keyed by pattern.
+    topics: TopicTrie<String>,
+}
+
+impl Exchange {
+    fn new(kind: ExchangeKind) -> Self {
+        Exchange {
+            kind,
+            bindings: vec![],
+            topics: TopicTrie::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct QueueConsumer {
+    tag: String,
+    connection: u64,
+    channel: u16,
+    no_ack: bool,
+    exclusive: bool,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// The connection owning an exclusive queue.
+    exclusive: Option<u64>,
+    auto_delete: bool,
+    messages: VecDeque<Message>,
+    consumers: Vec<QueueConsumer>,
+    next_consumer: usize,
+}
+
+#[derive(Debug)]
+struct Unacked {
+    queue: String,
+    message: Message,
+}
+
+#[derive(Debug, Default)]
+struct ChannelState {
+    next_delivery_tag: u64,
+    unacked: BTreeMap<u64, Unacked>,
+    prefetch_count: u16,
+    /// Sequence number of the last published message once in confirm mode.
+    publish_seq: Option<u64>,
+    last_queue: String,
+}
+
+impl ChannelState {
+    fn has_capacity(&self) -> bool {
+        self.prefetch_count == 0 || self.unacked.len() < self.prefetch_count as usize
+    }
+
+    fn take_unacked(&mut self, delivery_tag: u64, multiple: bool) -> Handled<Vec<Unacked>> {
+        if multiple {
+            // Tag 0 takes everything, like the highest tag, which has no successor to split at.
+            let rest = match delivery_tag.checked_add(1) {
+                Some(next) if delivery_tag > 0 => self.unacked.split_off(&next),
+                _ => BTreeMap::new(),
+            };
+            let taken = ::std::mem::replace(&mut self.unacked, rest);
+            Ok(taken.into_values().collect())
+        } else {
+            match self.unacked.remove(&delivery_tag) {
+                Some(unacked) => Ok(vec![unacked]),
+                None => {
+                    Err(Exception::channel(PRECONDITION_FAILED,
+                                           format!("unknown delivery tag {}", delivery_tag)))
+                }
+            }
+        }
+    }
+}
+
+struct ConnectionState {
+    sender: mpsc::UnboundedSender<Vec<u8>>,
+    frame_max: u32,
+    cancel_notify: bool,
+    channels: HashMap<u16, ChannelState>,
+}
+
+/// Exchanges, queues and the channels of all connections of a broker.
+pub struct State {
+    exchanges: HashMap<String, Exchange>,
+    queues: HashMap<String, Queue>,
+    connections: HashMap<u64, ConnectionState>,
+    next_id: u64,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let mut exchanges = HashMap::new();
+        for &(name, kind) in &[("", ExchangeKind::Direct),
+                               ("amq.direct", ExchangeKind::Direct),
+                               ("amq.fanout", ExchangeKind::Fanout),
+                               ("amq.topic", ExchangeKind::Topic),
+                               ("amq.headers", ExchangeKind::Headers),
+                               ("amq.match", ExchangeKind::Headers)] {
+            exchanges.insert(name.to_string(), Exchange::new(kind));
+        }
+        State {
+            exchanges,
+            queues: HashMap::new(),
+            connections: HashMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl State {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub fn add_connection(&mut self,
+                          sender: mpsc::UnboundedSender<Vec<u8>>,
+                          frame_max: u32,
+                          cancel_notify: bool)
+                          -> u64 {
+        let id = self.next_id();
+        self.connections.insert(id,
+                                ConnectionState {
+                                    sender,
+                                    frame_max,
+                                    cancel_notify,
+                                    channels: HashMap::new(),
+                                });
+        id
+    }
+
+    /// Closes all channels of a connection and deletes its exclusive queues.
+    pub fn remove_connection(&mut self, connection: u64) {
+        let channels: Vec<u16> = match self.connections.get(&connection) {
+            Some(state) => state.channels.keys().cloned().collect(),
+            None => return,
+        };
+        for channel in channels {
+            self.close_channel(connection, channel);
+        }
+        self.connections.remove(&connection);
+        let exclusive: Vec<String> = self.queues
+            .iter()
+            .filter(|&(_, queue)| queue.exclusive == Some(connection))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in exclusive {
+            self.delete_queue(&name);
+        }
+    }
+
+    pub fn send(&self, connection: u64, frames: &[Frame]) {
+        if let Some(state) = self.connections.get(&connection) {
+            let mut buffer = vec![];
+            for frame in frames {
+                match frame.encode() {
+                    Ok(encoded) => buffer.extend_from_slice(&encoded),
+                    Err(err) => {
+                        debug!("Failed to encode a frame: {}", err);
+                        return;
+                    }
+                }
+            }
+            let _ = state.sender.send(buffer);
+        }
+    }
+
+    pub fn send_method<M: Method>(&self, connection: u64, channel: u16, method: &M) {
+        match method.to_frame(channel) {
+            Ok(frame) => self.send(connection, &[frame]),
+            Err(err) => debug!("Failed to encode {}: {}", method.name(), err),
+        }
+    }
+
+    pub fn send_content<M: Method>(&self,
+                                   connection: u64,
+                                   channel: u16,
+                                   method: &M,
+                                   message: &Message) {
+        let frame_max = match self.connections.get(&connection) {
+            Some(state) => state.frame_max,
+            None => return,
+        };
+        match encode_content(channel,
+                             method,
+                             &message.properties,
+                             &message.body,
+                             frame_max) {
+            Ok(frames) => self.send(connection, &frames),
+            Err(err) => debug!("Failed to encode {}: {}", method.name(), err),
+        }
+    }
+
+    fn channel_mut(&mut self, connection: u64, channel: u16) -> Handled<&mut ChannelState> {
+        self.connections
+            .get_mut(&connection)
+            .and_then(|state| state.channels.get_mut(&channel))
+            .ok_or_else(|| Exception::connection(CHANNEL_ERROR, format!("channel {} is not open", channel)))
+    }
+
+    pub fn has_channel(&self, connection: u64, channel: u16) -> bool {
+        self.connections
+            .get(&connection)
+            .is_some_and(|state| state.channels.contains_key(&channel))
+    }
+
+    pub fn open_channel(&mut self, connection: u64, channel: u16) -> Handled<()> {
+        let state = self.connections
+            .get_mut(&connection)
+            .ok_or_else(|| Exception::connection(CHANNEL_ERROR, "connection is closed"))?;
+        if state.channels.contains_key(&channel) {
+            return Err(Exception::connection(CHANNEL_ERROR,
+                                             format!("channel {} is already open", channel)));
+        }
+        state.channels.insert(channel, ChannelState::default());
+        Ok(())
+    }
+
+    /// Cancels the consumers of a channel and requeues its unacknowledged messages.
+    pub fn close_channel(&mut self, connection: u64, channel: u16) {
+        let state = match self.connections
+            .get_mut(&connection)
+            .and_then(|state| state.channels.remove(&channel)) {
+            Some(state) => state,
+            None => return,
+        };
+        let mut auto_delete = vec![];
+        for (name, queue) in &mut self.queues {
+            let consumers = queue.consumers.len();
+            queue.consumers
+                .retain(|consumer| consumer.connection != connection || consumer.channel != channel);
+            if queue.auto_delete && consumers > 0 && queue.consumers.is_empty() {
+                auto_delete.push(name.clone());
+            }
+        }
+        for name in auto_delete {
+            self.delete_queue(&name);
+        }
+        self.requeue(state.unacked.into_values().collect());
+    }
+
+    /// Puts messages back at the head of their queues, in their original order.
+    fn requeue(&mut self, unacked: Vec<Unacked>) {
+        let mut requeued = vec![];
+        for Unacked { queue, mut message } in unacked.into_iter().rev() {
+            if let Some(queue_state) = self.queues.get_mut(&queue) {
+                message.redelivered = true;
+                queue_state.messages.push_front(message);
+                if !requeued.contains(&queue) {
+                    requeued.push(queue);
+                }
+            }
+        }
+        for queue in requeued {
+            self.dispatch(&queue);
+        }
+    }
+
+    pub fn exchange_declare(&mut self, declare: &exchange::Declare) -> Handled<()> {
+        if let Some(exchange) = self.exchanges.get(&declare.exchange) {
+            if !declare.passive && exchange.kind.name() != declare._type {
+                return Err(Exception::channel(PRECONDITION_FAILED,
+                                              format!("inequivalent arg 'type' for exchange \
+                                                       '{}': received '{}' but current is '{}'",
+                                                      declare.exchange,
+                                                      declare._type,
+                                                      exchange.kind.name())));
+            }
+            return Ok(());
+        }
+        if declare.passive {
+            return Err(Exception::channel(NOT_FOUND, format!("no exchange '{}'", declare.exchange)));
+        }
+        if declare.exchange.starts_with("amq.") {
+            return Err(Exception::channel(ACCESS_REFUSED,
+                                          format!("exchange name '{}' contains reserved prefix \
+                                                   'amq.'",
+                                                  declare.exchange)));
+        }
+        let kind = ExchangeKind::from_name(&declare._type).ok_or_else(|| {
+                Exception::connection(COMMAND_INVALID,
+                                      format!("unknown exchange type '{}'", declare._type))
+            })?;
+        self.exchanges.insert(declare.exchange.clone(), Exchange::new(kind));
+        Ok(())
+    }
+
+    pub fn exchange_delete(&mut self, delete: &exchange::Delete) -> Handled<()> {
+        if delete.exchange.is_empty() || delete.exchange.starts_with("amq.") {
+            return Err(Exception::channel(ACCESS_REFUSED,
+                                          format!("cannot delete exchange '{}'", delete.exchange)));
+        }
+        match self.exchanges.get(&delete.exchange) {
+            None => {
+                Err(Exception::channel(NOT_FOUND, format!("no exchange '{}'", delete.exchange)))
+            }
+            Some(exchange) if delete.if_unused && !exchange.bindings.is_empty() => {
+                Err(Exception::channel(PRECONDITION_FAILED,
+                                       format!("exchange '{}' in use", delete.exchange)))
+            }
+            Some(_) => {
+                self.exchanges.remove(&delete.exchange);
+                Ok(())
+            }
+        }
+    }
+
+    /// Resolves an empty queue name to the last queue declared on the channel.
+    fn queue_name(&mut self, connection: u64, channel: u16, name: &str) -> Handled<String> {
+        if !name.is_empty() {
+            return Ok(name.to_string());
+        }
+        let channel = self.channel_mut(connection, channel)?;
+        if channel.last_queue.is_empty() {
+            Err(Exception::channel(NOT_FOUND, "no previously declared queue"))
+        } else {
+            Ok(channel.last_queue.clone())
+        }
+    }
+
+    fn queue_mut(&mut self, connection: u64, name: &str) -> Handled<&mut Queue> {
+        match self.queues.get_mut(name) {
+            None => Err(Exception::channel(NOT_FOUND, format!("no queue '{}'", name))),
+            Some(queue) if queue.exclusive.is_some_and(|owner| owner != connection) => {
+                Err(Exception::channel(RESOURCE_LOCKED,
+                                       format!("cannot obtain exclusive access to locked queue \
+                                                '{}'",
+                                               name)))
+            }
+            Some(queue) => Ok(queue),
+        }
+    }
+
+    pub fn queue_declare(&mut self,
+                         connection: u64,
+                         channel: u16,
+                         declare: &queue::Declare)
+                         -> Handled<queue::DeclareOk> {
+        let name = if declare.queue.is_empty() && !declare.passive {
+            format!("amq.gen-{}", self.next_id())
+        } else {
+            self.queue_name(connection, channel, &declare.queue)?
+        };
+        if !self.queues.contains_key(&name) {
+            if declare.passive {
+                return Err(Exception::channel(NOT_FOUND, format!("no queue '{}'", name)));
+            }
+            if declare.queue.starts_with("amq.") {
+                return Err(Exception::channel(ACCESS_REFUSED,
+                                              format!("queue name '{}' contains reserved prefix \
+                                                       'amq.'",
+                                                      name)));
+            }
+            self.queues.insert(name.clone(),
+                               Queue {
+                                   exclusive: if declare.exclusive {
+                                       Some(connection)
+                                   } else {
+                                       None
+                                   },
+                                   auto_delete: declare.auto_delete,
+                                   ..Default::default()
+                               });
+        }
+        let queue = self.queue_mut(connection, &name)?;
+        let declare_ok = queue::DeclareOk {
+            queue: name.clone(),
+            message_count: queue.messages.len() as u32,
+            consumer_count: queue.consumers.len() as u32,
+        };
+        self.channel_mut(connection, channel)?.last_queue = name;
+        Ok(declare_ok)
+    }
+
+    fn binding(&mut self,
+               connection: u64,
+               channel: u16,
+               queue: &str,
+               exchange: &str,
+               routing_key: &str,
+               arguments: &Table)
+               -> Handled<Binding> {
+        let queue = self.queue_name(connection, channel, queue)?;
+        self.queue_mut(connection, &queue)?;
+        if exchange.is_empty() {
+            return Err(Exception::channel(ACCESS_REFUSED,
+                                          "operation not permitted on the default exchange"));
+        }
+        if !self.exchanges.contains_key(exchange) {
+            return Err(Exception::channel(NOT_FOUND, format!("no exchange '{}'", exchange)));
+        }
+        Ok(Binding {
+            queue,
+            routing_key: routing_key.to_string(),
+            arguments: arguments.clone(),
+        })
+    }
+
+    pub fn queue_bind(&mut self, connection: u64, channel: u16, bind: &queue::Bind) -> Handled<()> {
+        let binding = self.binding(connection,
+                     channel,
+                     &bind.queue,
+                     &bind.exchange,
+                     &bind.routing_key,
+                     &bind.arguments)?;
+        let exchange = self.exchanges.get_mut(&bind.exchange).unwrap();
+        if exchange.bindings.contains(&binding) {
+            return Ok(());
+        }
+        if exchange.kind == ExchangeKind::Topic {
+            let pattern = TopicPattern::parse(&binding.routing_key)
+                .map_err(|err| Exception::channel(PRECONDITION_FAILED, err.to_string()))?;
+            exchange.topics.insert(&pattern, binding.queue.clone());
+        }
+        if exchange.kind == ExchangeKind::Headers {
+            HeadersMatcher::new(&binding.arguments)
+                .map_err(|err| Exception::channel(PRECONDITION_FAILED, err.to_string()))?;
+        }
+        exchange.bindings.push(binding);
+        Ok(())
+    }
+
+    pub fn queue_unbind(&mut self,
+                        connection: u64,
+                        channel: u16,
+                        unbind: &queue::Unbind)
+                        -> Handled<()> {
+        let binding = self.binding(connection,
+                     channel,
+                     &unbind.queue,
+                     &unbind.exchange,
+                     &unbind.routing_key,
+                     &unbind.arguments)?;
+        let exchange = self.exchanges.get_mut(&unbind.exchange).unwrap();
+        if let Some(position) = exchange.bindings.iter().position(|other| *other == binding) {
+            exchange.bindings.remove(position);
+            if let Ok(pattern) = TopicPattern::parse(&binding.routing_key) {
+                exchange.topics.remove(&pattern, &binding.queue);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of purged messages.
+    pub fn queue_purge(&mut self, connection: u64, channel: u16, purge: &queue::Purge) -> Handled<u32> {
+        let name = self.queue_name(connection, channel, &purge.queue)?;
+        let queue = self.queue_mut(connection, &name)?;
+        let count = queue.messages.len() as u32;
+        queue.messages.clear();
+        Ok(count)
+    }
+
+    /// Returns the number of deleted messages.
+    pub fn queue_delete(&mut self,
+                        connection: u64,
+                        channel: u16,
+                        delete: &queue::Delete)
+                        -> Handled<u32> {
+        let name = self.queue_name(connection, channel, &delete.queue)?;
+        let queue = self.queue_mut(connection, &name)?;
+        if delete.if_unused && !queue.consumers.is_empty() {
+            return Err(Exception::channel(PRECONDITION_FAILED,
+                                          format!("queue '{}' in use", name)));
+        }
+        if delete.if_empty && !queue.messages.is_empty() {
+            return Err(Exception::channel(PRECONDITION_FAILED,
+                                          format!("queue '{}' is not empty", name)));
+        }
+        Ok(self.delete_queue(&name))
+    }
+
+    /// Deletes a queue and its bindings, notifying its consumers with `basic.cancel`
+    /// where the client supports it.
+    fn delete_queue(&mut self, name: &str) -> u32 {
+        let queue = match self.queues.remove(name) {
+            Some(queue) => queue,
+            None => return 0,
+        };
+        for exchange in self.exchanges.values_mut() {
+            exchange.bindings.retain(|binding| binding.queue != name);
+            exchange.topics.retain(|queue| queue != name);
+        }
+        for consumer in &queue.consumers {
+            if self.connections.get(&consumer.connection).is_some_and(|state| state.cancel_notify) {
+                let cancel = basic::Cancel {
+                    consumer_tag: consumer.tag.clone(),
+                    nowait: true,
+                };
+                self.send_method(consumer.connection, consumer.channel, &cancel);
+            }
+        }
+        queue.messages.len() as u32
+    }
+
+    pub fn basic_qos(&mut self, connection: u64, channel: u16, qos: &basic::Qos) -> Handled<()> {
+        self.channel_mut(connection, channel)?.prefetch_count = qos.prefetch_count;
+        self.dispatch_all();
+        Ok(())
+    }
+
+    /// Adds a consumer, returning its queue and consumer tag.
+    /// Deliveries start with `dispatch` once `basic.consume-ok` was sent.
+    pub fn basic_consume(&mut self,
+                         connection: u64,
+                         channel: u16,
+                         consume: &basic::Consume)
+                         -> Handled<(String, String)> {
+        let name = self.queue_name(connection, channel, &consume.queue)?;
+        let tag = if consume.consumer_tag.is_empty() {
+            format!("amq.ctag-{}", self.next_id())
+        } else {
+            consume.consumer_tag.clone()
+        };
+        let reused = self.queues.values().any(|queue| {
+            queue.consumers.iter().any(|consumer| {
+                consumer.connection == connection && consumer.channel == channel &&
+                consumer.tag == tag
+            })
+        });
+        if reused {
+            return Err(Exception::connection(NOT_ALLOWED,
+                                             format!("attempt to reuse consumer tag '{}'", tag)));
+        }
+        let queue = self.queue_mut(connection, &name)?;
+        if queue.consumers.iter().any(|consumer| consumer.exclusive) ||
+           consume.exclusive && !queue.consumers.is_empty() {
+            return Err(Exception::channel(ACCESS_REFUSED,
+                                          format!("queue '{}' in exclusive use", name)));
+        }
+        queue.consumers.push(QueueConsumer {
+            tag: tag.clone(),
+            connection,
+            channel,
+            no_ack: consume.no_ack,
+            exclusive: consume.exclusive,
+        });
+        Ok((name, tag))
+    }
+
+    pub fn basic_cancel(&mut self, connection: u64, channel: u16, consumer_tag: &str) {
+        let mut auto_delete = None;
+        for (name, queue) in &mut self.queues {
+            let position = queue.consumers.iter().position(|consumer| {
+                consumer.connection == connection && consumer.channel == channel &&
+                consumer.tag == consumer_tag
+            });
+            if let Some(position) = position {
+                queue.consumers.remove(position);
+                if queue.auto_delete && queue.consumers.is_empty() {
+                    auto_delete = Some(name.clone());
+                }
+                break;
+            }
+        }
+        if let Some(name) = auto_delete {
+            self.delete_queue(&name);
+        }
+    }
+
+    /// Routes a published message to its queues, returning it with `basic.return` if it
+    /// is `mandatory` and unroutable, and confirming it in confirm mode.
+    pub fn basic_publish(&mut self,
+                         connection: u64,
+                         channel: u16,
+                         publish: &basic::Publish,
+                         properties: BasicProperties,
+                         body: Vec<u8>)
+                         -> Handled<()> {
+        if publish.immediate {
+            return Err(Exception::connection(NOT_IMPLEMENTED, "immediate=true"));
+        }
+        let message = Message {
+            exchange: publish.exchange.clone(),
+            routing_key: publish.routing_key.clone(),
+            properties,
+            body,
+            redelivered: false,
+        };
+        let queues = self.route(&message)?;
+        if queues.is_empty() && publish.mandatory {
+            let reply = basic::Return {
+                reply_code: NO_ROUTE,
+                reply_text: "NO_ROUTE".to_string(),
+                exchange: message.exchange.clone(),
+                routing_key: message.routing_key.clone(),
+            };
+            self.send_content(connection, channel, &reply, &message);
+        }
+        for name in &queues {
+            self.queues.get_mut(name).unwrap().messages.push_back(message.clone());
+        }
+        let publish_seq = {
+            let state = self.channel_mut(connection, channel)?;
+            if let Some(ref mut seq) = state.publish_seq {
+                *seq += 1;
+            }
+            state.publish_seq
+        };
+        if let Some(delivery_tag) = publish_seq {
+            let ack = basic::Ack {
+                delivery_tag,
+                multiple: false,
+            };
+            self.send_method(connection, channel, &ack);
+        }
+        for name in &queues {
+            self.dispatch(name);
+        }
+        Ok(())
+    }
+
+    fn route(&self, message: &Message) -> Handled<Vec<String>> {
+        let exchange = self.exchanges
+            .get(&message.exchange)
+            .ok_or_else(|| {
+                Exception::channel(NOT_FOUND, format!("no exchange '{}'", message.exchange))
+            })?;
+        if message.exchange.is_empty() {
+            return Ok(if self.queues.contains_key(&message.routing_key) {
+                vec![message.routing_key.clone()]
+            } else {
+                vec![]
+            });
+        }
+        let matched: Vec<&String> = match exchange.kind {
+            ExchangeKind::Direct => {
+                exchange.bindings
+                    .iter()
+                    .filter(|binding| binding.routing_key == message.routing_key)
+                    .map(|binding| &binding.queue)
+                    .collect()
+            }
+            ExchangeKind::Fanout => exchange.bindings.iter().map(|binding| &binding.queue).collect(),
+            ExchangeKind::Topic => exchange.topics.matches(&message.routing_key),
+            ExchangeKind::Headers => {
+                exchange.bindings
+                    .iter()
+                    .filter(|binding| {
+                        HeadersMatcher::new(&binding.arguments)
+                            .is_ok_and(|matcher| matcher.matches_properties(&message.properties))
+                    })
+                    .map(|binding| &binding.queue)
+                    .collect()
+            }
+        };
+        let mut queues: Vec<String> = vec![];
+        for queue in matched {
+            if !queues.contains(queue) {
+                queues.push(queue.clone());
+            }
+        }
+        Ok(queues)
+    }
+
+    /// Hands the messages of a queue to its consumers in round-robin order,
+    /// as far as their channels' prefetch limits allow.
+    pub fn dispatch(&mut self, name: &str) {
+        loop {
+            let queue = match self.queues.get_mut(name) {
+                Some(queue) => queue,
+                None => return,
+            };
+            if queue.messages.is_empty() || queue.consumers.is_empty() {
+                return;
+            }
+            let connections = &mut self.connections;
+            let count = queue.consumers.len();
+            let chosen = (0..count).map(|offset| (queue.next_consumer + offset) % count).find(|&index| {
+                let consumer = &queue.consumers[index];
+                connections.get(&consumer.connection)
+                    .and_then(|state| state.channels.get(&consumer.channel))
+                    .is_some_and(|channel| consumer.no_ack || channel.has_capacity())
+            });
+            let index = match chosen {
+                Some(index) => index,
+                None => return,
+            };
+            queue.next_consumer = (index + 1) % count;
+            let message = queue.messages.pop_front().unwrap();
+            let consumer = &queue.consumers[index];
+            let (connection, channel) = (consumer.connection, consumer.channel);
+            let channel_state = connections.get_mut(&connection)
+                .unwrap()
+                .channels
+                .get_mut(&channel)
+                .unwrap();
+            channel_state.next_delivery_tag += 1;
+            let deliver = basic::Deliver {
+                consumer_tag: consumer.tag.clone(),
+                delivery_tag: channel_state.next_delivery_tag,
+                redelivered: message.redelivered,
+                exchange: message.exchange.clone(),
+                routing_key: message.routing_key.clone(),
+            };
+            let no_ack = consumer.no_ack;
+            self.send_content(connection, channel, &deliver, &message);
+            if !no_ack {
+                self.channel_mut(connection, channel)
+                    .unwrap()
+                    .unacked
+                    .insert(deliver.delivery_tag,
+                            Unacked {
+                                queue: name.to_string(),
+                                message,
+                            });
+            }
+        }
+    }
+
+    fn dispatch_all(&mut self) {
+        let names: Vec<String> = self.queues.keys().cloned().collect();
+        for name in names {
+            self.dispatch(&name);
+        }
+    }
+
+    /// Takes the next message off a queue, returning `None` when it is empty.
+    pub fn basic_get(&mut self,
+                     connection: u64,
+                     channel: u16,
+                     get: &basic::Get)
+                     -> Handled<Option<(basic::GetOk, Message)>> {
+        let name = self.queue_name(connection, channel, &get.queue)?;
+        let queue = self.queue_mut(connection, &name)?;
+        let message = match queue.messages.pop_front() {
+            Some(message) => message,
+            None => return Ok(None),
+        };
+        let message_count = queue.messages.len() as u32;
+        let channel_state = self.channel_mut(connection, channel)?;
+        channel_state.next_delivery_tag += 1;
+        let get_ok = basic::GetOk {
+            delivery_tag: channel_state.next_delivery_tag,
+            redelivered: message.redelivered,
+            exchange: message.exchange.clone(),
+            routing_key: message.routing_key.clone(),
+            message_count,
+        };
+        if !get.no_ack {
+            channel_state.unacked.insert(get_ok.delivery_tag,
+                                         Unacked {
+                                             queue: name,
+                                             message: message.clone(),
+                                         });
+        }
+        Ok(Some((get_ok, message)))
+    }
+
+    pub fn basic_ack(&mut self,
+                     connection: u64,
+                     channel: u16,
+                     delivery_tag: u64,
+                     multiple: bool)
+                     -> Handled<()> {
+        self.channel_mut(connection, channel)?.take_unacked(delivery_tag, multiple)?;
+        self.dispatch_all();
+        Ok(())
+    }
+
+    /// Handles `basic.nack` and `basic.reject`. Messages which are not requeued are dropped.
+    pub fn basic_nack(&mut self,
+                      connection: u64,
+                      channel: u16,
+                      delivery_tag: u64,
+                      multiple: bool,
+                      requeue: bool)
+                      -> Handled<()> {
+        let unacked = self.channel_mut(connection, channel)?.take_unacked(delivery_tag, multiple)?;
+        if requeue {
+            self.requeue(unacked);
+        }
+        self.dispatch_all();
+        Ok(())
+    }
+
+    /// Redelivers all unacknowledged messages of a channel.
+    pub fn basic_recover(&mut self, connection: u64, channel: u16) -> Handled<()> {
+        let unacked = self.channel_mut(connection, channel)?.take_unacked(0, true)?;
+        self.requeue(unacked);
+        Ok(())
+    }
+
+    pub fn confirm_select(&mut self, connection: u64, channel: u16) -> Handled<()> {
+        let state = self.channel_mut(connection, channel)?;
+        state.publish_seq.get_or_insert(0);
+        Ok(())
+    }
+
+    /// The number of ready messages and consumers of a queue.
+    pub fn queue_info(&self, name: &str) -> Option<(usize, usize)> {
+        self.queues.get(name).map(|queue| (queue.messages.len(), queue.consumers.len()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unacked(state: &mut ChannelState, delivery_tag: u64) {
+        state.unacked.insert(delivery_tag,
+                             Unacked {
+                                 queue: "q".to_string(),
+                                 message: Message {
+                                     exchange: "".to_string(),
+                                     routing_key: "q".to_string(),
+                                     properties: BasicProperties::default(),
+                                     body: vec![],
+                                     redelivered: false,
+                                 },
+                             });
+    }
+
+    #[test]
+    fn test_take_unacked() {
+        let mut state = ChannelState::default();
+        for delivery_tag in 1..4 {
+            unacked(&mut state, delivery_tag);
+        }
+        assert_eq!(state.take_unacked(2, true).unwrap().len(), 2);
+        assert!(state.take_unacked(2, false).is_err());
+        unacked(&mut state, 4);
+        assert_eq!(state.take_unacked(u64::MAX, true).unwrap().len(), 2);
+        assert!(state.unacked.is_empty());
+        unacked(&mut state, 5);
+        assert_eq!(state.take_unacked(0, true).unwrap().len(), 1);
+    }
+}
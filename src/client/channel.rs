@@ -0,0 +1,259 @@
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, Notify};
+
+use crate::client_core::{expect_get_reply, expect_reply};
+use crate::consumer::Delivery;
+use crate::content::{GetMessage, Incoming, Returned};
+use crate::method::Method;
+use crate::protocol::{basic, channel, confirm, exchange, queue, tx};
+use crate::protocol::basic::BasicProperties;
+use crate::error::*;
+use super::connection::Inner;
+
+/// The deliveries of a consumer started with `Channel::basic_consume`.
+/// The receiver is closed once the consumer is cancelled.
+#[derive(Debug)]
+pub struct Subscription {
+    pub consumer_tag: String,
+    pub deliveries: mpsc::UnboundedReceiver<Delivery>,
+}
+
+/// An open channel of a `Connection`.
+///
+/// Synchronous methods always wait for the server's reply, so their `nowait` flag is ignored.
+pub struct Channel {
+    id: u16,
+    inner: Arc<Inner>,
+    events: mpsc::UnboundedReceiver<Incoming>,
+    confirmed: Arc<Notify>,
+}
+
+impl Channel {
+    pub(crate) fn new(id: u16,
+                      inner: Arc<Inner>,
+                      events: mpsc::UnboundedReceiver<Incoming>,
+                      confirmed: Arc<Notify>)
+                      -> Self {
+        Channel {
+            id,
+            inner,
+            events,
+            confirmed,
+        }
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub(crate) async fn open(&mut self) -> Result<()> {
+        let _: channel::OpenOk = self.rpc(&channel::Open { out_of_band: "".to_string() }).await?;
+        Ok(())
+    }
+
+    async fn send<M: Method>(&self, method: &M) -> Result<()> {
+        self.inner.send_method(self.id, method).await
+    }
+
+    async fn next_event(&mut self) -> Result<Incoming> {
+        match self.events.recv().await {
+            Some(event) => Ok(event),
+            None => Err(self.inner.core.channel_error(self.id)),
+        }
+    }
+
+    /// Sends a synchronous method and waits for its reply.
+    pub async fn rpc<M: Method, R: Method>(&mut self, method: &M) -> Result<R> {
+        self.send(method).await?;
+        expect_reply(self.next_event().await?)
+    }
+
+    pub async fn exchange_declare(&mut self, mut declare: exchange::Declare) -> Result<()> {
+        declare.nowait = false;
+        let _: exchange::DeclareOk = self.rpc(&declare).await?;
+        Ok(())
+    }
+
+    pub async fn exchange_delete(&mut self, mut delete: exchange::Delete) -> Result<()> {
+        delete.nowait = false;
+        let _: exchange::DeleteOk = self.rpc(&delete).await?;
+        Ok(())
+    }
+
+    pub async fn exchange_bind(&mut self, mut bind: exchange::Bind) -> Result<()> {
+        bind.nowait = false;
+        let _: exchange::BindOk = self.rpc(&bind).await?;
+        Ok(())
+    }
+
+    pub async fn exchange_unbind(&mut self, mut unbind: exchange::Unbind) -> Result<()> {
+        unbind.nowait = false;
+        let _: exchange::UnbindOk = self.rpc(&unbind).await?;
+        Ok(())
+    }
+
+    pub async fn queue_declare(&mut self, mut declare: queue::Declare) -> Result<queue::DeclareOk> {
+        declare.nowait = false;
+        self.rpc(&declare).await
+    }
+
+    pub async fn queue_bind(&mut self, mut bind: queue::Bind) -> Result<()> {
+        bind.nowait = false;
+        let _: queue::BindOk = self.rpc(&bind).await?;
+        Ok(())
+    }
+
+    pub async fn queue_unbind(&mut self, unbind: queue::Unbind) -> Result<()> {
+        let _: queue::UnbindOk = self.rpc(&unbind).await?;
+        Ok(())
+    }
+
+    /// Returns the number of purged messages.
+    pub async fn queue_purge(&mut self, mut purge: queue::Purge) -> Result<u32> {
+        purge.nowait = false;
+        let purge_ok: queue::PurgeOk = self.rpc(&purge).await?;
+        Ok(purge_ok.message_count)
+    }
+
+    /// Returns the number of deleted messages.
+    pub async fn queue_delete(&mut self, mut delete: queue::Delete) -> Result<u32> {
+        delete.nowait = false;
+        let delete_ok: queue::DeleteOk = self.rpc(&delete).await?;
+        Ok(delete_ok.message_count)
+    }
+
+    pub async fn basic_qos(&mut self, qos: basic::Qos) -> Result<()> {
+        let _: basic::QosOk = self.rpc(&qos).await?;
+        Ok(())
+    }
+
+    pub async fn basic_consume(&mut self, mut consume: basic::Consume) -> Result<Subscription> {
+        consume.nowait = false;
+        let (deliveries_tx, deliveries) = mpsc::unbounded_channel();
+        self.inner.core.start_consume(self.id,
+                               Box::new(move |delivery: Delivery| {
+                                   let _ = deliveries_tx.send(delivery);
+                               }))?;
+        match self.rpc::<_, basic::ConsumeOk>(&consume).await {
+            Ok(consume_ok) => {
+                Ok(Subscription {
+                    consumer_tag: consume_ok.consumer_tag,
+                    deliveries,
+                })
+            }
+            Err(err) => {
+                self.inner.core.cancel_consume(self.id);
+                Err(err)
+            }
+        }
+    }
+
+    pub async fn basic_cancel(&mut self, consumer_tag: &str) -> Result<()> {
+        let cancel = basic::Cancel {
+            consumer_tag: consumer_tag.to_string(),
+            nowait: false,
+        };
+        let _: basic::CancelOk = self.rpc(&cancel).await?;
+        Ok(())
+    }
+
+    pub async fn basic_publish(&mut self,
+                               publish: basic::Publish,
+                               properties: BasicProperties,
+                               body: &[u8])
+                               -> Result<()> {
+        let frames = self.inner.core.publish(self.id, &publish, &properties, body)?;
+        self.inner.write_frames(&frames).await
+    }
+
+    /// Returns `None` when the queue is empty.
+    pub async fn basic_get(&mut self, get: basic::Get) -> Result<Option<GetMessage>> {
+        self.send(&get).await?;
+        expect_get_reply(self.next_event().await?)
+    }
+
+    pub async fn basic_ack(&mut self, delivery_tag: u64, multiple: bool) -> Result<()> {
+        self.send(&basic::Ack {
+                delivery_tag,
+                multiple,
+            })
+            .await
+    }
+
+    pub async fn basic_nack(&mut self,
+                            delivery_tag: u64,
+                            multiple: bool,
+                            requeue: bool)
+                            -> Result<()> {
+        self.send(&basic::Nack {
+                delivery_tag,
+                multiple,
+                requeue,
+            })
+            .await
+    }
+
+    pub async fn basic_reject(&mut self, delivery_tag: u64, requeue: bool) -> Result<()> {
+        self.send(&basic::Reject {
+                delivery_tag,
+                requeue,
+            })
+            .await
+    }
+
+    pub async fn basic_recover(&mut self, requeue: bool) -> Result<()> {
+        let _: basic::RecoverOk = self.rpc(&basic::Recover { requeue }).await?;
+        Ok(())
+    }
+
+    /// Takes the messages returned by the server since the last call.
+    pub fn take_returns(&mut self) -> Vec<Returned> {
+        self.inner.core.take_returns(self.id)
+    }
+
+    /// Puts the channel into publisher confirm mode.
+    pub async fn confirm_select(&mut self) -> Result<()> {
+        let _: confirm::SelectOk = self.rpc(&confirm::Select { nowait: false }).await?;
+        self.inner.core.confirm_select_ok(self.id)
+    }
+
+    /// Waits until all messages published so far are confirmed.
+    /// Returns false if any of them was nacked by the server.
+    pub async fn wait_for_confirms(&mut self) -> Result<bool> {
+        loop {
+            let notified = self.confirmed.notified();
+            if let Some(all_acked) = self.inner.core.take_confirms(self.id)? {
+                return Ok(all_acked);
+            }
+            notified.await;
+        }
+    }
+
+    pub async fn tx_select(&mut self) -> Result<()> {
+        let _: tx::SelectOk = self.rpc(&tx::Select).await?;
+        Ok(())
+    }
+
+    pub async fn tx_commit(&mut self) -> Result<()> {
+        let _: tx::CommitOk = self.rpc(&tx::Commit).await?;
+        Ok(())
+    }
+
+    pub async fn tx_rollback(&mut self) -> Result<()> {
+        let _: tx::RollbackOk = self.rpc(&tx::Rollback).await?;
+        Ok(())
+    }
+
+    pub async fn close(mut self) -> Result<()> {
+        let close = channel::Close {
+            reply_code: 200,
+            reply_text: "Goodbye".to_string(),
+            class_id: 0,
+            method_id: 0,
+        };
+        let result: Result<channel::CloseOk> = self.rpc(&close).await;
+        self.inner.core.remove_channel(self.id);
+        result.map(|_| ())
+    }
+}
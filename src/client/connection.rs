@@ -0,0 +1,233 @@
+use std::sync::Arc;
+
+use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Mutex as AsyncMutex, Notify};
+use tokio::task::JoinHandle;
+
+use crate::client_core::{self, encode_frames, ChannelSlot, ClientCore};
+use crate::codec::FrameReader;
+use crate::content::Incoming;
+use crate::framing::{Frame, MethodFrame, ProtocolHeader};
+use crate::method::Method;
+use crate::handshake::{Handshake, Negotiated, Step};
+use crate::peer_properties::{Capability, ClientProperties, ServerProperties};
+use crate::protocol::connection;
+use crate::uri::AmqpUri;
+use crate::error::*;
+use super::channel::Channel;
+
+pub(crate) type BoxedWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+pub(crate) async fn write_frames(writer: &mut BoxedWriter, frames: &[Frame]) -> Result<()> {
+    writer.write_all(&encode_frames(frames)?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Where the reader task hands over what it read for a channel.
+pub(crate) struct Slot {
+    events: mpsc::UnboundedSender<Incoming>,
+    confirmed: Arc<Notify>,
+}
+
+impl ChannelSlot for Slot {
+    fn send(&self, incoming: Incoming) {
+        let _ = self.events.send(incoming);
+    }
+
+    fn notify(&self) {
+        self.confirmed.notify_one();
+    }
+}
+
+pub(crate) struct Inner {
+    writer: AsyncMutex<BoxedWriter>,
+    pub core: ClientCore<Slot>,
+}
+
+impl Inner {
+    pub async fn write_frames(&self, frames: &[Frame]) -> Result<()> {
+        let mut writer = self.writer.lock().await;
+        write_frames(&mut writer, frames).await
+    }
+
+    pub async fn send_method<M: Method>(&self, channel: u16, method: &M) -> Result<()> {
+        self.write_frames(&[method.to_frame(channel)?]).await
+    }
+}
+
+/// An AMQP connection over any `AsyncRead + AsyncWrite` stream.
+///
+/// Incoming frames are read by a background task, so the connection must be used
+/// from within a tokio runtime.
+pub struct Connection {
+    inner: Arc<Inner>,
+    events: mpsc::UnboundedReceiver<MethodFrame>,
+    server_properties: ServerProperties,
+    capabilities: Vec<Capability>,
+    tune: connection::TuneOk,
+    reader: JoinHandle<()>,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl Connection {
+    /// Performs the handshake using the credentials, vhost and tuning parameters of `uri`.
+    pub async fn open<S>(stream: S, uri: &AmqpUri) -> Result<Connection>
+        where S: AsyncRead + AsyncWrite + Send + 'static
+    {
+        Connection::open_with_properties(stream, uri, ClientProperties::new()).await
+    }
+
+    pub async fn open_with_properties<S>(stream: S,
+                                         uri: &AmqpUri,
+                                         client_properties: ClientProperties)
+                                         -> Result<Connection>
+        where S: AsyncRead + AsyncWrite + Send + 'static
+    {
+        let (read_half, write_half) = io::split(stream);
+        let mut reader = FrameReader::new(read_half);
+        let mut writer: BoxedWriter = Box::new(write_half);
+        writer.write_all(&ProtocolHeader::default().encode()).await?;
+
+        let mut handshake = Handshake::new(uri, client_properties);
+        let negotiated = loop {
+            match handshake.handle(reader.read_method().await?)? {
+                Step::Send(frames) => write_frames(&mut writer, &frames).await?,
+                Step::Done(negotiated) => break negotiated,
+            }
+        };
+        let core = ClientCore::new(&negotiated);
+        let Negotiated { server_properties, capabilities, tune: tune_ok } = negotiated;
+        reader.set_frame_max(tune_ok.frame_max);
+        let inner = Arc::new(Inner {
+            writer: AsyncMutex::new(writer),
+            core,
+        });
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let reader = tokio::spawn(read_loop(reader, inner.clone(), events_tx));
+        let heartbeat = if tune_ok.heartbeat > 0 {
+            Some(tokio::spawn(heartbeat_loop(inner.clone(), tune_ok.heartbeat)))
+        } else {
+            None
+        };
+        Ok(Connection {
+            inner,
+            events,
+            server_properties,
+            capabilities,
+            tune: tune_ok,
+            reader,
+            heartbeat,
+        })
+    }
+
+    pub fn server_properties(&self) -> &ServerProperties {
+        &self.server_properties
+    }
+
+    /// Capabilities supported by both this client and the server.
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    /// The negotiated `channel_max`, `frame_max` and `heartbeat`.
+    pub fn tune(&self) -> &connection::TuneOk {
+        &self.tune
+    }
+
+    /// True while the server has blocked publishing with `connection.blocked`.
+    pub fn is_blocked(&self) -> bool {
+        self.inner.core.is_blocked()
+    }
+
+    pub async fn open_channel(&self) -> Result<Channel> {
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let confirmed = Arc::new(Notify::new());
+        let id = self.inner.core.open_channel(Slot {
+                events: events_tx,
+                confirmed: confirmed.clone(),
+            })?;
+        let mut channel = Channel::new(id, self.inner.clone(), events, confirmed);
+        if let Err(err) = channel.open().await {
+            self.inner.core.remove_channel(id);
+            return Err(err);
+        }
+        Ok(channel)
+    }
+
+    /// Closes the connection with `reply-success` and waits for `connection.close-ok`.
+    pub async fn close(mut self) -> Result<()> {
+        let close = connection::Close {
+            reply_code: 200,
+            reply_text: "Goodbye".to_string(),
+            class_id: 0,
+            method_id: 0,
+        };
+        self.inner.send_method(0, &close).await?;
+        while let Some(method_frame) = self.events.recv().await {
+            if method_frame.method_name() == "connection.close-ok" {
+                self.inner.core.shutdown(Some((200, "Goodbye".to_string())));
+                return Ok(());
+            }
+        }
+        Err(self.inner.core.closed_error())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+        if let Some(ref heartbeat) = self.heartbeat {
+            heartbeat.abort();
+        }
+        self.inner.core.shutdown(None);
+    }
+}
+
+async fn heartbeat_loop(inner: Arc<Inner>, heartbeat: u16) {
+    let (heartbeat_frame, period) = client_core::heartbeat(heartbeat);
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        if inner.write_frames(::std::slice::from_ref(&heartbeat_frame)).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn read_loop<R>(mut reader: FrameReader<R>,
+                      inner: Arc<Inner>,
+                      events: mpsc::UnboundedSender<MethodFrame>)
+    where R: AsyncRead + Unpin
+{
+    loop {
+        let result = match reader.read_frame().await {
+            Ok(frame) => handle_frame(&inner, &events, frame).await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => {
+                debug!("Connection reader stopped: {}", err);
+                break;
+            }
+        }
+    }
+    inner.core.shutdown(None);
+}
+
+/// Handles one incoming frame, returning false once the connection is closed.
+async fn handle_frame(inner: &Inner,
+                      events: &mpsc::UnboundedSender<MethodFrame>,
+                      frame: Frame)
+                      -> Result<bool> {
+    let handled = inner.core.handle_frame(frame)?;
+    if !handled.send.is_empty() {
+        inner.write_frames(&handled.send).await?;
+    }
+    for method_frame in handled.connection {
+        let _ = events.send(method_frame);
+    }
+    Ok(!handled.closed)
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+#[cfg(feature = "blocking")]
+use std::sync::Condvar;
+use std::time::Duration;
+
+use crate::confirms::ConfirmTracker;
+use crate::connection_state::{Action, ChannelState, ConnectionState};
+use crate::consumer::Consumer;
+use crate::content::{encode_content, GetMessage, Incoming, Returned};
+use crate::framing::{Frame, FramePayload, FrameType, MethodFrame};
+use crate::handshake::Negotiated;
+use crate::method::{expect_method, Method};
+use crate::protocol::basic::{self, BasicProperties};
+use crate::error::*;
+
+/// Where the reader hands over what it read for a channel: the async client
+/// uses tokio channels, the blocking client std ones.
+pub(crate) trait ChannelSlot {
+    /// Passes on a reply to a synchronous method or a `basic.get-ok` message.
+    fn send(&self, incoming: Incoming);
+
+    /// Wakes up whoever waits for confirms on the channel.
+    fn notify(&self);
+}
+
+/// What a reader has to do after the core handled a frame.
+pub(crate) struct Handled {
+    /// Frames to send to the server, in order.
+    pub send: Vec<Frame>,
+    /// Methods on channel 0 for the connection, e.g. `connection.close-ok`.
+    pub connection: Vec<MethodFrame>,
+    /// Set once the connection is closed and no more frames follow.
+    pub closed: bool,
+}
+
+/// The part of a client connection which doesn't depend on how frames are
+/// read and written: the `ConnectionState` and the slots of the open channels.
+pub(crate) struct ClientCore<S> {
+    state: Mutex<ConnectionState>,
+    channels: Mutex<HashMap<u16, S>>,
+    pub frame_max: u32,
+}
+
+impl<S: ChannelSlot> ClientCore<S> {
+    pub fn new(negotiated: &Negotiated) -> Self {
+        ClientCore {
+            state: Mutex::new(ConnectionState::new(negotiated)),
+            channels: Mutex::new(HashMap::new()),
+            frame_max: negotiated.tune.frame_max,
+        }
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.state.lock().unwrap().is_blocked()
+    }
+
+    pub fn closed_error(&self) -> Error {
+        self.state.lock().unwrap().closed_error()
+    }
+
+    pub fn channel_error(&self, id: u16) -> Error {
+        self.state.lock().unwrap().channel_error(id)
+    }
+
+    /// Allocates a channel id and registers the slot for it.
+    pub fn open_channel(&self, slot: S) -> Result<u16> {
+        // Holding on to the slots while allocating the id keeps a concurrent
+        // shutdown from missing the new slot.
+        let mut channels = self.channels.lock().unwrap();
+        let id = self.state.lock().unwrap().open_channel()?;
+        channels.insert(id, slot);
+        Ok(id)
+    }
+
+    pub fn remove_channel(&self, id: u16) {
+        self.state.lock().unwrap().remove_channel(id);
+        self.disconnect(id);
+    }
+
+    /// Wakes up whoever waits on the channel, for good.
+    fn disconnect(&self, id: u16) {
+        if let Some(slot) = self.channels.lock().unwrap().remove(&id) {
+            slot.notify();
+        }
+    }
+
+    pub fn shutdown(&self, reason: Option<(u16, String)>) {
+        self.state.lock().unwrap().shutdown(reason);
+        for (_, slot) in self.channels.lock().unwrap().drain() {
+            slot.notify();
+        }
+    }
+
+    /// Feeds a frame read from the server to the state and hands over what
+    /// it produced for the channels, leaving the I/O to the caller.
+    pub fn handle_frame(&self, frame: Frame) -> Result<Handled> {
+        let actions = self.state.lock().unwrap().handle_frame(frame)?;
+        let mut handled = Handled {
+            send: vec![],
+            connection: vec![],
+            closed: false,
+        };
+        for action in actions {
+            match action {
+                Action::Send(frame) => handled.send.push(frame),
+                Action::Connection(method_frame) => handled.connection.push(method_frame),
+                Action::Channel(id, incoming) => {
+                    if let Some(slot) = self.channels.lock().unwrap().get(&id) {
+                        slot.send(incoming);
+                    }
+                }
+                Action::Confirmed(id) => {
+                    if let Some(slot) = self.channels.lock().unwrap().get(&id) {
+                        slot.notify();
+                    }
+                }
+                Action::ChannelClosed(id) => self.disconnect(id),
+                Action::Closed => handled.closed = true,
+            }
+        }
+        Ok(handled)
+    }
+
+    /// Runs `f` on the state of the channel, unless the channel is gone with its connection.
+    pub fn with_channel<T, F>(&self, id: u16, f: F) -> Result<T>
+        where F: FnOnce(&mut ChannelState) -> T
+    {
+        let mut state = self.state.lock().unwrap();
+        match state.channel_mut(id) {
+            Some(channel) => Ok(f(channel)),
+            None => Err(state.channel_error(id)),
+        }
+    }
+
+    /// Queues the consumer of a `basic.consume` about to be sent.
+    pub fn start_consume(&self, id: u16, consumer: Box<dyn Consumer>) -> Result<()> {
+        self.with_channel(id, |channel| channel.consumers.start_consume(consumer))
+    }
+
+    /// Forgets the consumer of a `basic.consume` which failed, so it doesn't
+    /// get the tag of the next `basic.consume-ok`.
+    pub fn cancel_consume(&self, id: u16) {
+        let _ = self.with_channel(id, |channel| channel.consumers.cancel_pending());
+    }
+
+    /// Encodes a message to publish on the channel, counting it for confirms.
+    pub fn publish(&self,
+                   id: u16,
+                   publish: &basic::Publish,
+                   properties: &BasicProperties,
+                   body: &[u8])
+                   -> Result<Vec<Frame>> {
+        let frames = encode_content(id, publish, properties, body, self.frame_max)?;
+        self.with_channel(id, |channel| {
+                if let Some(ref mut confirms) = channel.confirms {
+                    confirms.published();
+                }
+            })?;
+        Ok(frames)
+    }
+
+    /// Starts tracking confirms, after `confirm.select-ok`.
+    pub fn confirm_select_ok(&self, id: u16) -> Result<()> {
+        self.with_channel(id, |channel| channel.confirms = Some(ConfirmTracker::new()))
+    }
+
+    pub fn take_returns(&self, id: u16) -> Vec<Returned> {
+        self.with_channel(id, |channel| ::std::mem::take(&mut channel.returns))
+            .unwrap_or_default()
+    }
+
+    /// See `ConnectionState::take_confirms`.
+    #[cfg(feature = "client")]
+    pub fn take_confirms(&self, id: u16) -> Result<Option<bool>> {
+        self.state.lock().unwrap().take_confirms(id)
+    }
+
+    /// Blocks until `take_confirms` has an answer, woken up by `confirmed`.
+    #[cfg(feature = "blocking")]
+    pub fn wait_for_confirms(&self, id: u16, confirmed: &Condvar) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(all_acked) = state.take_confirms(id)? {
+                return Ok(all_acked);
+            }
+            state = confirmed.wait(state).unwrap();
+        }
+    }
+}
+
+/// Decodes the reply to a synchronous method.
+pub(crate) fn expect_reply<R: Method>(incoming: Incoming) -> Result<R> {
+    match incoming {
+        Incoming::Method(method_frame) => expect_method(method_frame),
+        Incoming::Content(content) => expect_method(content.method),
+    }
+}
+
+/// Decodes the reply to `basic.get`, `None` when the queue is empty.
+pub(crate) fn expect_get_reply(incoming: Incoming) -> Result<Option<GetMessage>> {
+    match incoming {
+        Incoming::Content(content) => {
+            Ok(Some(GetMessage {
+                get_ok: expect_method(content.method)?,
+                properties: content.properties,
+                body: content.body,
+            }))
+        }
+        Incoming::Method(method_frame) => {
+            let _: basic::GetEmpty = expect_method(method_frame)?;
+            Ok(None)
+        }
+    }
+}
+
+pub(crate) fn encode_frames(frames: &[Frame]) -> Result<Vec<u8>> {
+    let mut buffer = vec![];
+    for frame in frames {
+        buffer.extend_from_slice(&frame.encode()?);
+    }
+    Ok(buffer)
+}
+
+/// The heartbeat frame and how often to send it for a negotiated `heartbeat` in seconds.
+pub(crate) fn heartbeat(heartbeat: u16) -> (Frame, Duration) {
+    let frame = Frame {
+        frame_type: FrameType::HEARTBEAT,
+        channel: 0,
+        payload: FramePayload::new(vec![]),
+    };
+    // Send twice per interval so a delayed write doesn't get us disconnected.
+    (frame, Duration::from_millis(u64::from(heartbeat) * 500))
+}
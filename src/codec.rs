@@ -0,0 +1,327 @@
+use std::io::Cursor;
+
+use byteorder::{BigEndian, ByteOrder};
+use bytes::{Buf, BufMut, BytesMut};
+#[cfg(any(feature = "client", feature = "broker"))]
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::framing::{AnyFrame, DecodeLimits, DecodeMode, Frame, ProtocolHeader, RawFrame};
+#[cfg(any(feature = "client", feature = "broker"))]
+use crate::framing::{FrameType, MethodFrame};
+use crate::error::*;
+
+/// Frame header (type, channel, size) plus the frame end octet.
+const FRAME_OVERHEAD: usize = 8;
+
+/// Everything that can appear on the wire: frames, and the protocol header
+/// sent by the client on connect (or by a server rejecting the requested version).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmqpMessage {
+    ProtocolHeader(ProtocolHeader),
+    Frame(Frame),
+    /// A frame of an unknown type, only decoded in `DecodeMode::Lenient`.
+    Unknown(RawFrame),
+}
+
+/// `tokio_util::codec` implementation for AMQP frames.
+///
+/// `frame_max` starts out unlimited and should be set to the value negotiated with
+/// `connection.tune-ok`, after which oversized frames are rejected in both directions.
+/// Unknown frame types are rejected unless the decode mode is set to lenient. Frames
+/// over `DecodeLimits::max_frame_size` are rejected whatever `frame_max` is.
+#[derive(Debug, Clone, Default)]
+pub struct AmqpCodec {
+    frame_max: u32,
+    decode_mode: DecodeMode,
+    limits: DecodeLimits,
+}
+
+impl AmqpCodec {
+    pub fn new() -> Self {
+        AmqpCodec::default()
+    }
+
+    /// Zero means no limit.
+    pub fn with_frame_max(frame_max: u32) -> Self {
+        AmqpCodec { frame_max, ..AmqpCodec::default() }
+    }
+
+    pub fn with_decode_mode(mut self, decode_mode: DecodeMode) -> Self {
+        self.decode_mode = decode_mode;
+        self
+    }
+
+    pub fn decode_mode(&self) -> DecodeMode {
+        self.decode_mode
+    }
+
+    pub fn set_decode_mode(&mut self, decode_mode: DecodeMode) {
+        self.decode_mode = decode_mode;
+    }
+
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+    }
+
+    pub fn frame_max(&self) -> u32 {
+        self.frame_max
+    }
+
+    pub fn set_frame_max(&mut self, frame_max: u32) {
+        self.frame_max = frame_max;
+    }
+
+    fn check_frame_size(&self, payload_size: usize) -> Result<()> {
+        if self.frame_max != 0 && payload_size + FRAME_OVERHEAD > self.frame_max as usize {
+            return Err(ErrorKind::Protocol(format!("Frame size {} exceeds frame_max {}",
+                                                   payload_size + FRAME_OVERHEAD,
+                                                   self.frame_max))
+                .into());
+        }
+        Ok(())
+    }
+}
+
+impl Decoder for AmqpCodec {
+    type Item = AmqpMessage;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<AmqpMessage>> {
+        // No frame type starts with 'A', so this can only be a protocol header.
+        if src.first() == Some(&b'A') {
+            if src.len() < 8 {
+                return Ok(None);
+            }
+            let header = ProtocolHeader::decode(&mut Cursor::new(&src[..8]))?;
+            src.advance(8);
+            return Ok(Some(AmqpMessage::ProtocolHeader(header)));
+        }
+        if src.len() < 7 {
+            return Ok(None);
+        }
+        let payload_size = BigEndian::read_u32(&src[3..7]);
+        self.limits.check_frame_size(payload_size)?;
+        let payload_size = payload_size as usize;
+        self.check_frame_size(payload_size)?;
+        let frame_size = payload_size + FRAME_OVERHEAD;
+        if src.len() < frame_size {
+            src.reserve(frame_size - src.len());
+            return Ok(None);
+        }
+        let frame = AnyFrame::decode_with_limits(&mut Cursor::new(&src[..frame_size]),
+                                                 self.decode_mode,
+                                                 &self.limits)?;
+        src.advance(frame_size);
+        Ok(Some(match frame {
+            AnyFrame::Frame(frame) => AmqpMessage::Frame(frame),
+            AnyFrame::Unknown(frame) => AmqpMessage::Unknown(frame),
+        }))
+    }
+}
+
+impl Encoder<Frame> for AmqpCodec {
+    type Error = Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<()> {
+        self.check_frame_size(frame.payload.inner().len())?;
+        dst.put_slice(&frame.encode()?);
+        Ok(())
+    }
+}
+
+impl Encoder<RawFrame> for AmqpCodec {
+    type Error = Error;
+
+    fn encode(&mut self, frame: RawFrame, dst: &mut BytesMut) -> Result<()> {
+        self.check_frame_size(frame.payload.inner().len())?;
+        dst.put_slice(&frame.encode()?);
+        Ok(())
+    }
+}
+
+impl Encoder<ProtocolHeader> for AmqpCodec {
+    type Error = Error;
+
+    fn encode(&mut self, header: ProtocolHeader, dst: &mut BytesMut) -> Result<()> {
+        dst.put_slice(&header.encode());
+        Ok(())
+    }
+}
+
+impl Encoder<AmqpMessage> for AmqpCodec {
+    type Error = Error;
+
+    fn encode(&mut self, message: AmqpMessage, dst: &mut BytesMut) -> Result<()> {
+        match message {
+            AmqpMessage::ProtocolHeader(header) => self.encode(header, dst),
+            AmqpMessage::Frame(frame) => self.encode(frame, dst),
+            AmqpMessage::Unknown(frame) => self.encode(frame, dst),
+        }
+    }
+}
+
+/// Reads frames from an `AsyncRead` using `AmqpCodec`.
+#[cfg(any(feature = "client", feature = "broker"))]
+pub(crate) struct FrameReader<R> {
+    reader: R,
+    buffer: BytesMut,
+    codec: AmqpCodec,
+}
+
+#[cfg(any(feature = "client", feature = "broker"))]
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        FrameReader {
+            reader,
+            buffer: BytesMut::new(),
+            codec: AmqpCodec::new(),
+        }
+    }
+
+    pub fn set_frame_max(&mut self, frame_max: u32) {
+        self.codec.set_frame_max(frame_max);
+    }
+
+    pub async fn read_message(&mut self) -> Result<AmqpMessage> {
+        loop {
+            if let Some(message) = self.codec.decode(&mut self.buffer)? {
+                return Ok(message);
+            }
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                return Err(ErrorKind::ConnectionClosed(0, "Connection reset".to_string()).into());
+            }
+        }
+    }
+
+    pub async fn read_frame(&mut self) -> Result<Frame> {
+        match self.read_message().await? {
+            AmqpMessage::Frame(frame) => Ok(frame),
+            AmqpMessage::ProtocolHeader(header) => {
+                Err(ErrorKind::Protocol(format!("Peer requires protocol {}-{}-{}",
+                                                header.major,
+                                                header.minor,
+                                                header.revision))
+                    .into())
+            }
+            AmqpMessage::Unknown(frame) => {
+                Err(ErrorKind::Protocol(format!("Unknown frame type {}", frame.frame_type_id)).into())
+            }
+        }
+    }
+
+    /// Reads the next method on channel 0, skipping heartbeats.
+    pub async fn read_method(&mut self) -> Result<MethodFrame> {
+        loop {
+            let frame = self.read_frame().await?;
+            if frame.frame_type == FrameType::HEARTBEAT {
+                continue;
+            }
+            if frame.channel != 0 {
+                return Err(ErrorKind::Protocol(format!("Unexpected frame on channel {} during \
+                                                        handshake",
+                                                       frame.channel))
+                    .into());
+            }
+            return MethodFrame::decode(&frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{duplex, AsyncWriteExt};
+    use tokio_util::codec::{Framed, FramedRead};
+    use crate::framing::{Frame, FramePayload, FrameType, ProtocolHeader};
+    use crate::protocol::basic;
+    use crate::method::Method;
+    use super::*;
+
+    fn body_frame(size: usize) -> Frame {
+        Frame {
+            frame_type: FrameType::BODY,
+            channel: 1,
+            payload: FramePayload::new(vec![0xAB; size]),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_header_and_frames_over_duplex() {
+        let (client, server) = duplex(64);
+        let mut client = Framed::new(client, AmqpCodec::new());
+        let mut server = Framed::new(server, AmqpCodec::new());
+
+        let publish = basic::Publish {
+            ticket: 0,
+            exchange: "x".to_string(),
+            routing_key: "k".to_string(),
+            mandatory: false,
+            immediate: false,
+        };
+        let method_frame = publish.to_frame(1).unwrap();
+        let writer = tokio::spawn(async move {
+            client.send(ProtocolHeader::default()).await.unwrap();
+            client.send(method_frame).await.unwrap();
+            // Larger than the duplex buffer, so it arrives in several reads.
+            client.send(body_frame(1000)).await.unwrap();
+        });
+
+        assert_eq!(server.next().await.unwrap().unwrap(),
+                   AmqpMessage::ProtocolHeader(ProtocolHeader::default()));
+        assert_eq!(server.next().await.unwrap().unwrap(),
+                   AmqpMessage::Frame(publish.to_frame(1).unwrap()));
+        assert_eq!(server.next().await.unwrap().unwrap(),
+                   AmqpMessage::Frame(body_frame(1000)));
+        writer.await.unwrap();
+        assert!(server.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_frames_over_frame_max() {
+        let (mut client, server) = duplex(4096);
+        let mut server = FramedRead::new(server, AmqpCodec::with_frame_max(1024));
+        client.write_all(&body_frame(2000).encode().unwrap()).await.unwrap();
+        assert!(server.next().await.unwrap().is_err());
+
+        let mut codec = AmqpCodec::with_frame_max(1024);
+        let mut buffer = BytesMut::new();
+        assert!(codec.encode(body_frame(1016), &mut buffer).is_ok());
+        assert!(codec.encode(body_frame(1017), &mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_invalid_frame_end() {
+        let mut encoded = body_frame(4).encode().unwrap();
+        *encoded.last_mut().unwrap() = 0;
+        let mut buffer = BytesMut::from(&encoded[..]);
+        assert!(AmqpCodec::new().decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_lenient_passes_unknown_frames_through() {
+        let raw = RawFrame {
+            frame_type_id: 42,
+            channel: 3,
+            payload: FramePayload::new(vec![1, 2, 3, 4]),
+        };
+        let encoded = raw.encode().unwrap();
+        assert!(AmqpCodec::new().decode(&mut BytesMut::from(&encoded[..])).is_err());
+
+        let mut codec = AmqpCodec::new().with_decode_mode(DecodeMode::Lenient);
+        let message = codec.decode(&mut BytesMut::from(&encoded[..])).unwrap().unwrap();
+        assert_eq!(message, AmqpMessage::Unknown(raw));
+        let mut buffer = BytesMut::new();
+        codec.encode(message, &mut buffer).unwrap();
+        assert_eq!(&buffer[..], &encoded[..]);
+    }
+}
@@ -2,27 +2,41 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use bit_vec::BitVec;
 use std::io::{Cursor, Read, Write};
 
-use table::{Table, decode_table, encode_table};
-use error::*;
+use crate::framing::DecodeLimits;
+use crate::table::{Table, DecodeBudget, decode_table_within, encode_table, shortstr_lossy};
+use crate::error::*;
 
+/// Reads method arguments and properties in wire order.
 #[derive(Debug)]
 pub struct ArgumentsReader<'data> {
     cursor: Cursor<&'data [u8]>,
     bits: BitVec,
     byte: u8,
     current_bit: u8,
+    budget: DecodeBudget,
 }
 
 impl<'data> ArgumentsReader<'data> {
     pub fn new(data: &'data [u8]) -> ArgumentsReader {
+        ArgumentsReader::with_limits(data, &DecodeLimits::default())
+    }
+
+    /// Enforces the table and `max_message_bytes` limits across all arguments.
+    pub fn with_limits(data: &'data [u8], limits: &DecodeLimits) -> ArgumentsReader<'data> {
         ArgumentsReader {
             cursor: Cursor::new(data),
             bits: BitVec::from_bytes(&[0]),
             byte: 0,
             current_bit: 0,
+            budget: DecodeBudget::new(*limits),
         }
     }
 
+    /// The number of bytes not read yet.
+    pub fn remaining(&self) -> usize {
+        self.cursor.get_ref().len().saturating_sub(self.cursor.position() as usize)
+    }
+
     pub fn read_octet(&mut self) -> Result<u8> {
         self.current_bit = 0;
         self.cursor.read_u8().map_err(From::from)
@@ -47,21 +61,20 @@ impl<'data> ArgumentsReader<'data> {
         self.current_bit = 0;
         let size = self.read_octet()? as usize;
         let mut buffer: Vec<u8> = vec![0u8; size];
-        self.cursor.read(&mut buffer[..])?;
-        Ok(String::from_utf8_lossy(&buffer[..]).to_string())
+        self.cursor.read_exact(&mut buffer[..])?;
+        shortstr_lossy(&buffer, "shortstr")
     }
 
     pub fn read_longstr(&mut self) -> Result<String> {
         self.current_bit = 0;
         let size = self.read_long()? as usize;
-        let mut buffer: Vec<u8> = vec![0u8; size];
-        self.cursor.read(&mut buffer[..])?;
+        let buffer = self.budget.read_bytes(&mut self.cursor, size)?;
         Ok(String::from_utf8_lossy(&buffer[..]).to_string())
     }
 
     pub fn read_table(&mut self) -> Result<Table> {
         self.current_bit = 0;
-        decode_table(&mut self.cursor).map(|(table, table_size)| table)
+        decode_table_within(&mut self.cursor, &mut self.budget).map(|(table, _)| table)
     }
 
     pub fn read_timestamp(&mut self) -> Result<u64> {
@@ -82,6 +95,7 @@ impl<'data> ArgumentsReader<'data> {
     }
 }
 
+/// Writes method arguments and properties in wire order.
 #[derive(Debug)]
 pub struct ArgumentsWriter {
     data: Vec<u8>,
@@ -89,6 +103,12 @@ pub struct ArgumentsWriter {
     current_bit: u8,
 }
 
+impl Default for ArgumentsWriter {
+    fn default() -> Self {
+        ArgumentsWriter::new()
+    }
+}
+
 impl ArgumentsWriter {
     pub fn new() -> Self {
         ArgumentsWriter {
@@ -120,6 +140,9 @@ impl ArgumentsWriter {
 
     pub fn write_shortstr(&mut self, data: &String) -> Result<()> {
         self.flush_bits()?;
+        if data.len() > u8::MAX as usize {
+            return Err(ErrorKind::Protocol(format!("shortstr of {} bytes is too long", data.len())).into());
+        }
         self.data.write_u8(data.len() as u8)?;
         self.data.write_all(data.as_bytes())?;
         Ok(())
@@ -143,12 +166,12 @@ impl ArgumentsWriter {
         self.write_longlong(data)
     }
 
-    // TODO: Flush bytes on all subsequent other type of data writes
+    /// Consecutive bits are packed into octets, starting with the least significant bit.
     pub fn write_bit(&mut self, data: &bool) -> Result<()> {
         self.bits.set(7 - self.current_bit as usize, *data);
         self.current_bit += 1;
-        if self.current_bit == 7 {
-            self.flush_bits();
+        if self.current_bit == 8 {
+            self.flush_bits()?;
         }
         Ok(())
     }
@@ -170,7 +193,13 @@ impl ArgumentsWriter {
     }
 }
 
+/// Logs the decoding of a generated method at debug level.
+pub fn log_decoding(method: &str) {
+    debug!("Decoding {}", method);
+}
 
+#[doc(hidden)]
+#[macro_export]
 macro_rules! map_type {
     (octet) => (u8);
     (long) => (u32);
@@ -178,11 +207,13 @@ macro_rules! map_type {
     (short) => (u16);
     (shortstr) => (String);
     (longstr) => (String);
-    (table) => (Table);
+    (table) => ($crate::Table);
     (timestamp) => (u64);
     (bit) => (bool);
 }
 
+#[doc(hidden)]
+#[macro_export]
 macro_rules! read_type {
     ($reader:expr, octet) => ($reader.read_octet());
     ($reader:expr, long) => ($reader.read_long());
@@ -195,6 +226,8 @@ macro_rules! read_type {
     ($reader:expr, bit) => ($reader.read_bit());
 }
 
+#[doc(hidden)]
+#[macro_export]
 macro_rules! write_type {
     ($writer:expr, octet, $data:expr) => ($writer.write_octet($data));
     ($writer:expr, long, $data:expr) => ($writer.write_long($data));
@@ -207,120 +240,335 @@ macro_rules! write_type {
     ($writer:expr, bit, $data:expr) => ($writer.write_bit($data));
 }
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! field_default {
+    () => (Default::default());
+    ($default:expr) => ($default);
+}
+
+/// A method struct implementing `Method`, e.g.
+/// `method_struct!(Ack, "basic.ack", 60, 80, false, delivery_tag => longlong, multiple => bit)`,
+/// with `name => type = default` for arguments with a default value. Used by the code
+/// `amq-proto-codegen` generates.
+#[macro_export]
 macro_rules! method_struct {
-    ($method_name:ident, $method_str:expr, $class_id:expr, $method_id:expr, ) => (
-        #[derive(Debug, PartialEq, Clone)]
+    ($method_name:ident, $method_str:expr, $class_id:expr, $method_id:expr, $synchronous:expr, ) => (
+        #[derive(Debug, Default, PartialEq, Clone)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub struct $method_name;
-        impl method::Method for $method_name {
+        impl $crate::Method for $method_name {
             const ID: u16 = $method_id;
             const CLASS_ID: u16 = $class_id;
+            const SYNCHRONOUS: bool = $synchronous;
 
-            fn decode(_method_frame: MethodFrame) -> Result<Self> where Self: Sized {
+            fn decode(_method_frame: $crate::MethodFrame) -> $crate::Result<Self> where Self: Sized {
                 Ok($method_name)
             }
 
-            fn encode(&self) -> Result<method::EncodedMethod> {
-                Ok(method::EncodedMethod::new(vec![]))
+            fn encode(&self) -> $crate::Result<$crate::EncodedMethod> {
+                Ok($crate::EncodedMethod::new(vec![]))
             }
 
             fn name(&self) -> &'static str {
                 $method_str
             }
         }
+
+        impl ::std::fmt::Display for $method_name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                f.write_str($method_str)
+            }
+        }
     );
-    ($method_name:ident, $method_str:expr, $class_id:expr, $method_id:expr, $($arg_name:ident => $ty:ident),+) => (
-        #[derive(Debug, PartialEq)]
+    ($method_name:ident, $method_str:expr, $class_id:expr, $method_id:expr, $synchronous:expr,
+     $($arg_name:ident => $ty:ident $(= $default:expr)?),+) => (
+        #[derive(Debug, PartialEq, Clone)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub struct $method_name {
-            $(pub $arg_name: map_type!($ty),)*
+            $(pub $arg_name: $crate::map_type!($ty),)*
+        }
+
+        /// Uses the `default-value`s of the spec, e.g. `"/"` for the `virtual_host` of
+        /// `connection.open`, and the default of the field's type for other arguments.
+        impl Default for $method_name {
+            fn default() -> Self {
+                $method_name {
+                    $($arg_name: $crate::field_default!($($default)?),)*
+                }
+            }
         }
 
-        impl method::Method for $method_name {
+        impl $crate::Method for $method_name {
             const ID: u16 = $method_id;
             const CLASS_ID: u16 = $class_id;
+            const SYNCHRONOUS: bool = $synchronous;
 
-            fn decode(method_frame: MethodFrame) -> Result<Self> where Self: Sized {
-                debug!("Decoding {}", $method_str);
+            fn decode(method_frame: $crate::MethodFrame) -> $crate::Result<Self> where Self: Sized {
+                <Self as $crate::Method>::decode_with_limits(method_frame, &Default::default())
+            }
+
+            fn decode_with_limits(method_frame: $crate::MethodFrame,
+                                  limits: &$crate::DecodeLimits)
+                                  -> $crate::Result<Self>
+                where Self: Sized
+            {
+                $crate::__private::log_decoding($method_str);
                 match (method_frame.class_id, method_frame.method_id) {
                     ($class_id, $method_id) => {},
-                    _ => return Err(ErrorKind::Protocol("Unexpected method method class and id".to_string()).into())
+                    _ => return Err($crate::ErrorKind::Protocol("Unexpected method class and id".to_string()).into())
                 }
                 let data = method_frame.arguments.into_inner();
-                let mut reader = ArgumentsReader::new(&data);
+                let mut reader = $crate::ArgumentsReader::with_limits(&data, limits);
                 Ok($method_name {
-                    $($arg_name: read_type!(reader, $ty)?,)*
+                    $($arg_name: $crate::read_type!(reader, $ty)?,)*
                 })
             }
 
-            fn encode(&self) -> Result<method::EncodedMethod> {
-                let mut writer = ArgumentsWriter::new();
-                $(write_type!(writer, $ty, &self.$arg_name)?;)*
-                Ok(method::EncodedMethod::new(writer.as_bytes()))
+            fn encode(&self) -> $crate::Result<$crate::EncodedMethod> {
+                let mut writer = $crate::ArgumentsWriter::new();
+                $($crate::write_type!(writer, $ty, &self.$arg_name)?;)*
+                Ok($crate::EncodedMethod::new(writer.as_bytes()))
             }
 
             fn name(&self) -> &'static str {
                 $method_str
             }
         }
+
+        impl ::std::fmt::Display for $method_name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                let mut fields = $crate::__private::FieldsDisplay::new(f, $method_str);
+                $(fields.field(stringify!($arg_name), &self.$arg_name);)*
+                fields.finish()
+            }
+        }
     )
 }
 
+/// A properties struct with an `Option` per property, in flag order. Used by the code
+/// `amq-proto-codegen` generates.
+#[macro_export]
 macro_rules! properties_struct {
     ($struct_name:ident, $($arg_name:ident => $ty:ident),+) => (
         #[derive(Debug, Default, PartialEq, Clone)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub struct $struct_name {
-            $(pub $arg_name: Option<map_type!($ty)>,)*
+            $(
+                #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+                pub $arg_name: Option<$crate::map_type!($ty)>,
+            )*
+            /// Properties the spec doesn't define, only kept by `DecodeMode::Lenient`.
+            #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+            pub unknown: Option<$crate::UnknownProperties>,
         }
 
         impl $struct_name {
-            pub fn decode(content_header_frame: ContentHeaderFrame) -> Result<$struct_name> {
-                let mut reader = ArgumentsReader::new(content_header_frame.properties.inner());
-                let properties_flags = BitVec::from_bytes(&[((content_header_frame.properties_flags >> 8) & 0xff) as u8,
-                    (content_header_frame.properties_flags & 0xff) as u8]);
+            /// Decodes the properties, failing if the flags reference properties the
+            /// spec doesn't define.
+            pub fn decode(content_header_frame: $crate::ContentHeaderFrame) -> $crate::Result<$struct_name> {
+                $struct_name::decode_with(content_header_frame, $crate::DecodeMode::Strict)
+            }
+
+            /// Like `decode`, keeping properties the spec doesn't define in `unknown`
+            /// in lenient mode.
+            pub fn decode_with(content_header_frame: $crate::ContentHeaderFrame,
+                               mode: $crate::DecodeMode)
+                               -> $crate::Result<$struct_name> {
+                $struct_name::decode_with_limits(content_header_frame, mode, &Default::default())
+            }
+
+            pub fn decode_with_limits(content_header_frame: $crate::ContentHeaderFrame,
+                                      mode: $crate::DecodeMode,
+                                      limits: &$crate::DecodeLimits)
+                                      -> $crate::Result<$struct_name> {
+                let flags = &content_header_frame.properties_flags;
+                let data = content_header_frame.properties.inner();
+                let mut reader = $crate::ArgumentsReader::with_limits(data, limits);
                 let mut idx = 0;
-                Ok($struct_name {
+                let mut properties = $struct_name {
                     $($arg_name: {
-                        idx = idx + 1;
-                        match properties_flags.get(idx - 1) {
-                            Some(flag) if flag => Some(read_type!(reader, $ty)?),
-                            Some(_) => None,
-                            None => return Err(ErrorKind::Protocol("Properties flags are not correct".to_owned()).into())
+                        idx += 1;
+                        if $crate::__private::property_flag(flags, idx - 1) {
+                            Some($crate::read_type!(reader, $ty)?)
+                        } else {
+                            None
                         }
                     },)*
-                })
+                    unknown: None,
+                };
+                let unknown_flags = $crate::__private::property_flags_from(flags, idx);
+                // A class needs fewer flag words than some encoders send.
+                let known_words = idx.div_ceil(15).max(1);
+                if unknown_flags.iter().any(|word| *word != 0) || unknown_flags.len() > known_words {
+                    if mode == $crate::DecodeMode::Strict {
+                        let flagged = (0..unknown_flags.len() * 15)
+                            .filter(|idx| $crate::__private::property_flag(&unknown_flags, *idx))
+                            .last();
+                        if let Some(last) = flagged {
+                            return Err($crate::ErrorKind::Protocol(format!(
+                                "Property flags reference property {}, but {} only defines {}",
+                                last + 1, stringify!($struct_name), idx)).into());
+                        }
+                    } else {
+                        properties.unknown = Some($crate::UnknownProperties {
+                            flags: unknown_flags,
+                            data: data[data.len() - reader.remaining()..].to_vec(),
+                        });
+                    }
+                }
+                Ok(properties)
             }
 
-            pub fn encode(self) -> Result<Vec<u8>> {
-                let mut writer = ArgumentsWriter::new();
+            pub fn encode(self) -> $crate::Result<Vec<u8>> {
+                let mut writer = $crate::ArgumentsWriter::new();
                 $(if let Some(prop) = self.$arg_name {
-                        write_type!(writer, $ty, &prop)?;
+                        $crate::write_type!(writer, $ty, &prop)?;
                 };)*
-                Ok(writer.as_bytes())
+                let mut data = writer.as_bytes();
+                if let Some(unknown) = self.unknown {
+                    data.extend_from_slice(&unknown.data);
+                }
+                Ok(data)
             }
 
-            pub fn flags(&self) -> u16 {
-                let mut bits = BitVec::from_elem(16, false);
-                let mut idx = 0;
-                $(
-                    bits.set(idx, self.$arg_name.is_some());
-                    idx = idx + 1;
-                )*
-                let flags : u16 = bits.to_bytes()[0] as u16;
-                (flags << 8 | bits.to_bytes()[1] as u16) as u16
+            /// The property flag words, with continuation bits.
+            pub fn flags(&self) -> Vec<u16> {
+                let mut flags = self.unknown.as_ref().map_or_else(Vec::new, |unknown| unknown.flags.clone());
+                for (idx, is_set) in [$(self.$arg_name.is_some()),*].iter().enumerate() {
+                    if *is_set {
+                        $crate::__private::set_property_flag(&mut flags, idx);
+                    }
+                }
+                $crate::__private::finish_property_flags(flags)
+            }
+        }
+
+        /// Lists the properties which are set, e.g. `{content_type="text/plain", delivery_mode=2}`.
+        impl ::std::fmt::Display for $struct_name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                let mut fields = $crate::__private::FieldsDisplay::new(f, "");
+                $(if let Some(ref value) = self.$arg_name {
+                    fields.field(stringify!($arg_name), value);
+                })*
+                if let Some(ref unknown) = self.unknown {
+                    fields.field("unknown", unknown);
+                }
+                fields.finish()
             }
         }
     );
 }
 
+/// The enum of all methods, see `protocol::AnyMethod`. Used by the code
+/// `amq-proto-codegen` generates.
+#[macro_export]
+macro_rules! any_method {
+    ($(($class_id:literal, $method_id:literal) => $variant:ident($($ty:ident)::+)),+) => (
+        /// Any method of the spec, for dispatching on methods decoded at runtime.
+        #[derive(Debug, PartialEq, Clone)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum AnyMethod {
+            $($variant($($ty)::+),)+
+            /// A method the spec doesn't define, only decoded in `DecodeMode::Lenient`.
+            Unknown($crate::MethodFrame),
+        }
+
+        impl AnyMethod {
+            /// Decodes the method identified by the class and method id of the frame,
+            /// failing on methods the spec doesn't define.
+            pub fn decode(method_frame: $crate::MethodFrame) -> $crate::Result<AnyMethod> {
+                AnyMethod::decode_with(method_frame, $crate::DecodeMode::Strict)
+            }
+
+            /// Like `decode`, keeping unknown methods as `AnyMethod::Unknown` in lenient mode.
+            pub fn decode_with(method_frame: $crate::MethodFrame,
+                               mode: $crate::DecodeMode)
+                               -> $crate::Result<AnyMethod> {
+                AnyMethod::decode_with_limits(method_frame, mode, &Default::default())
+            }
+
+            /// Like `decode_with`, with `limits` on the tables and strings of the arguments.
+            pub fn decode_with_limits(method_frame: $crate::MethodFrame,
+                                      mode: $crate::DecodeMode,
+                                      limits: &$crate::DecodeLimits)
+                                      -> $crate::Result<AnyMethod> {
+                use $crate::Method;
+                match (method_frame.class_id, method_frame.method_id) {
+                    $(($class_id, $method_id) => {
+                        $($ty)::+::decode_with_limits(method_frame, limits).map(AnyMethod::$variant)
+                    })+
+                    _ if mode == $crate::DecodeMode::Lenient => Ok(AnyMethod::Unknown(method_frame)),
+                    (class_id, method_id) => Err($crate::ErrorKind::Protocol(
+                        format!("Unknown method class {} method {}", class_id, method_id)).into()),
+                }
+            }
+
+            pub fn name(&self) -> &'static str {
+                use $crate::Method;
+                match *self {
+                    $(AnyMethod::$variant(ref method) => method.name(),)+
+                    AnyMethod::Unknown(_) => "UNKNOWN",
+                }
+            }
+
+            pub fn class_id(&self) -> u16 {
+                match *self {
+                    $(AnyMethod::$variant(_) => $class_id,)+
+                    AnyMethod::Unknown(ref method_frame) => method_frame.class_id,
+                }
+            }
+
+            pub fn method_id(&self) -> u16 {
+                match *self {
+                    $(AnyMethod::$variant(_) => $method_id,)+
+                    AnyMethod::Unknown(ref method_frame) => method_frame.method_id,
+                }
+            }
+
+            pub fn encode(&self) -> $crate::Result<$crate::EncodedMethod> {
+                use $crate::Method;
+                match *self {
+                    $(AnyMethod::$variant(ref method) => method.encode(),)+
+                    AnyMethod::Unknown(ref method_frame) => Ok(method_frame.arguments.clone()),
+                }
+            }
+
+            pub fn to_frame(&self, channel: u16) -> $crate::Result<$crate::Frame> {
+                use $crate::Method;
+                match *self {
+                    $(AnyMethod::$variant(ref method) => method.to_frame(channel),)+
+                    AnyMethod::Unknown(ref method_frame) => method_frame.to_frame(channel),
+                }
+            }
+        }
+
+        $(impl From<$($ty)::+> for AnyMethod {
+            fn from(method: $($ty)::+) -> AnyMethod {
+                AnyMethod::$variant(method)
+            }
+        })+
+
+        impl ::std::fmt::Display for AnyMethod {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                match *self {
+                    $(AnyMethod::$variant(ref method) => method.fmt(f),)+
+                    AnyMethod::Unknown(ref method_frame) => method_frame.fmt(f),
+                }
+            }
+        }
+    )
+}
+
 #[cfg(test)]
 mod test {
-    use bit_vec::BitVec;
-    use error::Result;
-    use framing::{MethodFrame, ContentHeaderFrame};
+    use crate::framing::{MethodFrame, ContentHeaderFrame, DecodeMode, UnknownProperties};
     use super::*;
-    use method::{self, Method, EncodedMethod};
+    use crate::method::{Method, EncodedMethod};
 
-    method_struct!(Foo, "test.foo", 1, 2, a => octet, b => shortstr, c => longstr, d => bit, e => bit, f => long);
-    method_struct!(FooNoFields, "test.foo_no_fields", 1, 2, );
+    method_struct!(Foo, "test.foo", 1, 2, false, a => octet, b => shortstr, c => longstr, d => bit, e => bit, f => long);
+    method_struct!(WithDefaults, "test.with_defaults", 1, 3, true,
+        a => octet = 9, b => shortstr = "PLAIN".to_string(), c => bit = true, d => long);
 
     properties_struct!(Test, a => octet, b => shortstr, c => longstr, d => bit, e => bit, f => long);
 
@@ -372,6 +620,28 @@ mod test {
         assert_eq!(Foo::decode(frame).unwrap(), f);
     }
 
+    #[test]
+    fn test_decoding_truncated() {
+        // "test" is cut short, which used to decode as "tes\0".
+        let frame = MethodFrame {
+            class_id: 1,
+            method_id: 2,
+            arguments: EncodedMethod::new(vec![1, 4, 116, 101, 115]),
+        };
+        assert!(Foo::decode(frame).is_err());
+        let mut reader = ArgumentsReader::new(&[0, 0, 0, 4, 98, 97]);
+        assert!(reader.read_longstr().is_err());
+    }
+
+    #[test]
+    fn test_shortstr_bounds() {
+        assert_eq!(ArgumentsReader::new(&[2, 0xff, b'a']).read_shortstr().unwrap(), "\u{fffd}a");
+        // Replacing the invalid bytes would make the string 3 times longer.
+        assert!(ArgumentsReader::new(&[200; 201]).read_shortstr().is_err());
+        assert!(ArgumentsWriter::new().write_shortstr(&"x".repeat(256)).is_err());
+        assert!(ArgumentsWriter::new().write_shortstr(&"x".repeat(255)).is_ok());
+    }
+
     #[test]
     fn test_decoding_wrong_ids() {
         let f = Foo {
@@ -385,16 +655,212 @@ mod test {
         let frame = MethodFrame {
             class_id: 42,
             method_id: 55,
-            arguments: EncodedMethod::new(vec![
-            1, // 1
-            4, // "test".len()
-            116, 101, 115, 116, // "test"
-            0, 0, 0, 3, // "bar".len()
-            98, 97, 114, // "bar"
-            2, // false, true => 0b00000010
-            0xDE, 0xAD, 0xBE, 0xEF, // 0xDEADBEEF
-        ]),
+            arguments: f.encode().unwrap(),
+        };
+        let err = Foo::decode(frame).unwrap_err();
+        assert_eq!(err.to_string(), "protocol error: 'Unexpected method class and id'");
+    }
+
+    /// `SYNCHRONOUS` read through a function, the constant itself would make the
+    /// assertions constant.
+    fn synchronous<M: Method>() -> bool {
+        M::SYNCHRONOUS
+    }
+
+    #[test]
+    fn test_spec_defaults() {
+        assert_eq!(WithDefaults::default(),
+                   WithDefaults {
+                       a: 9,
+                       b: "PLAIN".to_string(),
+                       c: true,
+                       d: 0,
+                   });
+        assert_eq!((synchronous::<WithDefaults>(), synchronous::<Foo>()), (true, false));
+
+        use crate::protocol::{basic, connection, constants};
+        assert_eq!(connection::Open::default().virtual_host, "/");
+        assert!(basic::Nack::default().requeue);
+        assert!(synchronous::<connection::Open>() && !synchronous::<basic::Publish>());
+        let frame = MethodFrame::decode(&connection::Open::default().to_frame(0).unwrap()).unwrap();
+        assert!(frame.is_synchronous());
+        assert_eq!(constants::FRAME_END, 0xCE);
+        assert_eq!(constants::REPLY_SUCCESS, 200);
+    }
+
+    #[test]
+    fn test_any_method() {
+        use crate::protocol::{basic, AnyMethod};
+        let ack = basic::Ack { delivery_tag: 3, multiple: true };
+        let frame = ack.to_frame(1).unwrap();
+        let method = AnyMethod::decode(MethodFrame::decode(&frame).unwrap()).unwrap();
+        assert_eq!(method, AnyMethod::BasicAck(ack.clone()));
+        assert_eq!(AnyMethod::from(ack), method);
+        assert_eq!((method.class_id(), method.method_id(), method.name()), (60, 80, "basic.ack"));
+        assert_eq!(method.to_frame(1).unwrap(), frame);
+        assert_eq!(method.to_string(), "basic.ack{delivery_tag=3, multiple=true}");
+        let unknown = MethodFrame { class_id: 60, method_id: 999, arguments: EncodedMethod::new(vec![]) };
+        assert!(AnyMethod::decode(unknown.clone()).is_err());
+    }
+
+    #[test]
+    fn test_any_method_lenient() {
+        use crate::framing::DecodeMode;
+        use crate::protocol::{basic, AnyMethod};
+        let unknown = MethodFrame { class_id: 60, method_id: 999, arguments: EncodedMethod::new(vec![1, 2, 3]) };
+        let frame = unknown.to_frame(5).unwrap();
+        let method = AnyMethod::decode_with(MethodFrame::decode(&frame).unwrap(), DecodeMode::Lenient).unwrap();
+        assert_eq!(method, AnyMethod::Unknown(unknown));
+        assert_eq!((method.class_id(), method.method_id(), method.name()), (60, 999, "UNKNOWN"));
+        assert_eq!(method.to_frame(5).unwrap().encode().unwrap(), frame.encode().unwrap());
+        let ack = basic::Ack { delivery_tag: 3, multiple: true }.to_frame(1).unwrap();
+        assert_eq!(AnyMethod::decode_with(MethodFrame::decode(&ack).unwrap(), DecodeMode::Lenient).unwrap(),
+                   AnyMethod::BasicAck(basic::Ack { delivery_tag: 3, multiple: true }));
+    }
+
+    #[test]
+    fn test_method_limits() {
+        use crate::framing::{DecodeLimits, DecodeMode};
+        use crate::method::expect_method_with_limits;
+        use crate::protocol::{queue, AnyMethod};
+        use crate::table::TableEntry;
+        let mut arguments = Table::new();
+        for key in &["x-expires", "x-max-length", "x-message-ttl"] {
+            arguments.insert(key.to_string(), TableEntry::LongInt(1000));
+        }
+        let declare = queue::Declare { queue: "work".to_string(), arguments, ..Default::default() };
+        let method_frame = MethodFrame::decode(&declare.to_frame(1).unwrap()).unwrap();
+        let limits = DecodeLimits { max_table_entries: 2, ..Default::default() };
+        assert!(queue::Declare::decode_with_limits(method_frame.clone(), &limits).is_err());
+        assert!(expect_method_with_limits::<queue::Declare>(method_frame.clone(), &limits).is_err());
+        assert!(AnyMethod::decode_with_limits(method_frame.clone(), DecodeMode::Strict, &limits).is_err());
+        let limits = DecodeLimits { max_message_bytes: 20, ..Default::default() };
+        assert!(queue::Declare::decode_with_limits(method_frame.clone(), &limits).is_err());
+        assert_eq!(queue::Declare::decode_with_limits(method_frame.clone(), &DecodeLimits::default())
+                       .unwrap(),
+                   declare);
+        assert_eq!(AnyMethod::decode_with_limits(method_frame, DecodeMode::Strict, &DecodeLimits::unlimited())
+                       .unwrap(),
+                   AnyMethod::QueueDeclare(declare));
+    }
+
+    properties_struct!(Many, p0 => octet, p1 => octet, p2 => octet, p3 => octet, p4 => octet,
+        p5 => octet, p6 => octet, p7 => octet, p8 => octet, p9 => octet, p10 => octet,
+        p11 => octet, p12 => octet, p13 => octet, p14 => octet, p15 => octet, p16 => shortstr);
+
+    fn content_header(properties_flags: Vec<u16>, properties: Vec<u8>) -> ContentHeaderFrame {
+        ContentHeaderFrame {
+            content_class: 1,
+            weight: 0,
+            body_size: 0,
+            properties_flags,
+            properties: crate::framing::EncodedProperties::new(properties),
+        }
+    }
+
+    #[test]
+    fn test_property_flag_continuation() {
+        let many = Many { p0: Some(1), p16: Some("x".to_string()), ..Default::default() };
+        assert_eq!(many.flags(), vec![0x8001, 0x4000]);
+        let header = content_header(many.flags(), many.clone().encode().unwrap());
+        assert_eq!(header.properties.inner(), &[1, 1, b'x']);
+        assert_eq!(Many::decode(header).unwrap(), many);
+        assert_eq!(Test { a: Some(1), ..Default::default() }.flags(), vec![0x8000]);
+    }
+
+    #[test]
+    fn test_unknown_properties() {
+        // `f` and an unknown property after it, in a continuation word.
+        let header = content_header(vec![0x0401, 0x2000], vec![0, 0, 0, 7, 0xAB, 0xCD]);
+        let err = Test::decode(header.clone()).unwrap_err();
+        assert_eq!(err.to_string(),
+                   "protocol error: 'Property flags reference property 18, but Test only defines 6'");
+
+        let test = Test::decode_with(header.clone(), DecodeMode::Lenient).unwrap();
+        assert_eq!(test.f, Some(7));
+        assert_eq!(test.unknown,
+                   Some(UnknownProperties { flags: vec![0, 0x2000], data: vec![0xAB, 0xCD] }));
+        assert_eq!(test.flags(), header.properties_flags);
+        assert_eq!(test.to_string(), "{f=7, unknown=2 bytes}");
+        assert_eq!(test.encode().unwrap(), header.properties.inner().to_vec());
+
+        // Flags without unknown properties are fine either way.
+        let header = content_header(vec![0x0401, 0x0000], vec![0, 0, 0, 7]);
+        assert_eq!(Test::decode(header.clone()).unwrap().f, Some(7));
+        let test = Test::decode_with(header.clone(), DecodeMode::Lenient).unwrap();
+        assert_eq!(test.flags(), header.properties_flags);
+    }
+
+    method_struct!(Flags, "test.flags", 1, 4, false,
+        a => bit, b => bit, c => bit, d => bit, e => bit, f => bit, g => bit, h => bit, i => bit,
+        j => octet);
+
+    #[test]
+    fn test_bit_packing() {
+        let flags = Flags { a: true, h: true, i: true, j: 7, ..Default::default() };
+        let encoded = flags.encode().unwrap();
+        // Eight bits fill an octet, the ninth starts a new one.
+        assert_eq!(encoded.inner(), &[0b1000_0001, 0b0000_0001, 7]);
+        let frame = MethodFrame { class_id: 1, method_id: 4, arguments: encoded };
+        assert_eq!(Flags::decode(frame).unwrap(), flags);
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive() {
+        use crate::AmqpMethod;
+
+        /// The same as `Foo`, with a timestamp.
+        #[derive(Debug, Clone, PartialEq, AmqpMethod)]
+        #[amqp(class = 1, method = 2, name = "test.foo", synchronous)]
+        struct Derived {
+            a: u8,
+            b: String,
+            #[amqp(longstr)]
+            c: String,
+            d: bool,
+            e: bool,
+            f: u32,
+            #[amqp(timestamp)]
+            g: u64,
+            h: Table,
+        }
+
+        #[derive(Debug, PartialEq, AmqpMethod)]
+        #[amqp(class = 1, method = 3)]
+        struct DeclareOk;
+
+        let derived = Derived {
+            a: 1,
+            b: "test".to_string(),
+            c: "bar".to_string(),
+            d: false,
+            e: true,
+            f: 0xDEADBEEF,
+            g: 5,
+            h: Table::new(),
+        };
+        let foo = Foo {
+            a: 1,
+            b: "test".to_string(),
+            c: "bar".to_string(),
+            d: false,
+            e: true,
+            f: 0xDEADBEEF,
         };
-        assert_eq!(Foo::decode(frame).is_err(), true);
+        let encoded = derived.encode().unwrap();
+        assert_eq!(&encoded.inner()[..foo.encode().unwrap().inner().len()],
+                   foo.encode().unwrap().inner());
+        let frame = MethodFrame { class_id: 1, method_id: 2, arguments: encoded };
+        let limits = crate::DecodeLimits { max_message_bytes: 2, ..Default::default() };
+        assert!(Derived::decode_with_limits(frame.clone(), &limits).is_err());
+        assert_eq!(Derived::decode(frame).unwrap(), derived);
+        assert_eq!((derived.name(), Derived::SYNCHRONOUS), ("test.foo", true));
+
+        let unit = DeclareOk.to_frame(1).unwrap();
+        assert_eq!(DeclareOk.name(), "declare-ok");
+        assert!(!synchronous::<DeclareOk>());
+        assert_eq!(DeclareOk::decode(MethodFrame::decode(&unit).unwrap()).unwrap(), DeclareOk);
+        assert!(Derived::decode(MethodFrame::decode(&unit).unwrap()).is_err());
     }
 }
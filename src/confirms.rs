@@ -0,0 +1,92 @@
+use std::collections::BTreeSet;
+
+/// Tracks the outstanding publisher confirms of a channel in confirm mode.
+///
+/// Every published message gets the next delivery tag, starting from 1, and is
+/// settled by the server's `basic.ack` or `basic.nack`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ConfirmTracker {
+    next_tag: u64,
+    unconfirmed: BTreeSet<u64>,
+    nacked: bool,
+}
+
+impl ConfirmTracker {
+    pub fn new() -> Self {
+        ConfirmTracker::default()
+    }
+
+    /// Records a published message, returning its delivery tag.
+    pub fn published(&mut self) -> u64 {
+        self.next_tag += 1;
+        self.unconfirmed.insert(self.next_tag);
+        self.next_tag
+    }
+
+    pub fn ack(&mut self, delivery_tag: u64, multiple: bool) {
+        self.settle(delivery_tag, multiple);
+    }
+
+    pub fn nack(&mut self, delivery_tag: u64, multiple: bool) {
+        self.settle(delivery_tag, multiple);
+        self.nacked = true;
+    }
+
+    /// With `multiple`, settles everything up to the delivery tag, or everything
+    /// outstanding for tag 0.
+    fn settle(&mut self, delivery_tag: u64, multiple: bool) {
+        if multiple {
+            self.unconfirmed = match delivery_tag.checked_add(1) {
+                Some(next) if delivery_tag > 0 => self.unconfirmed.split_off(&next),
+                _ => BTreeSet::new(),
+            };
+        } else {
+            self.unconfirmed.remove(&delivery_tag);
+        }
+    }
+
+    pub fn unconfirmed(&self) -> usize {
+        self.unconfirmed.len()
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.unconfirmed.is_empty()
+    }
+
+    /// Returns whether everything settled since the last call was acked, and resets it.
+    pub fn take_all_acked(&mut self) -> bool {
+        !::std::mem::replace(&mut self.nacked, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_confirms() {
+        let mut tracker = ConfirmTracker::new();
+        assert_eq!(tracker.published(), 1);
+        assert_eq!(tracker.published(), 2);
+        assert_eq!(tracker.published(), 3);
+        tracker.ack(2, true);
+        assert_eq!(tracker.unconfirmed(), 1);
+        tracker.nack(3, false);
+        assert!(tracker.is_settled());
+        assert!(!tracker.take_all_acked());
+        assert!(tracker.take_all_acked());
+    }
+
+    #[test]
+    fn test_settle_everything() {
+        let mut tracker = ConfirmTracker::new();
+        tracker.published();
+        tracker.published();
+        tracker.ack(0, true);
+        assert!(tracker.is_settled());
+        tracker.published();
+        tracker.nack(u64::MAX, true);
+        assert!(tracker.is_settled());
+        assert!(!tracker.take_all_acked());
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+
+use crate::confirms::ConfirmTracker;
+use crate::consumer::{ConsumerRegistry, Delivery};
+use crate::content::{Content, ContentAssembler, Incoming, Returned};
+use crate::framing::{Frame, FrameType, MethodFrame};
+use crate::handshake::Negotiated;
+use crate::method::Method;
+use crate::peer_properties::Capability;
+use crate::protocol::{basic, channel, connection};
+use crate::error::*;
+
+/// What the client has to do after handling a frame, in order.
+#[derive(Debug)]
+pub enum Action {
+    /// A frame to send to the server.
+    Send(Frame),
+    /// A method on channel 0 which isn't handled by the state, e.g. `connection.close-ok`.
+    Connection(MethodFrame),
+    /// A method or content for whoever waits on the channel: the reply to a
+    /// synchronous method or a `basic.get-ok` with its message.
+    Channel(u16, Incoming),
+    /// `basic.ack` or `basic.nack` settled messages published on the channel.
+    Confirmed(u16),
+    /// The server closed the channel, `ChannelState::closed` has the reason.
+    ChannelClosed(u16),
+    /// The connection is closed and no more frames follow.
+    Closed,
+}
+
+/// The state of a channel, from `ConnectionState::open_channel` until it is removed.
+pub struct ChannelState {
+    pub consumers: ConsumerRegistry,
+    /// Set once the channel is in confirm mode.
+    pub confirms: Option<ConfirmTracker>,
+    /// Messages the server returned, waiting to be taken.
+    pub returns: Vec<Returned>,
+    /// The reply code and text once the server closed the channel.
+    pub closed: Option<(u16, String)>,
+    assembler: ContentAssembler,
+}
+
+/// The client side of an open connection, independent of any I/O: feeds the
+/// frames read from the server to the channels, their consumers and confirms,
+/// and answers the methods the server expects a reply to.
+///
+/// A channel keeps its id after the server closed it, until it is removed.
+pub struct ConnectionState {
+    channels: HashMap<u16, ChannelState>,
+    closed: Option<(u16, String)>,
+    blocked: bool,
+    channel_max: u16,
+    cancel_notify: bool,
+}
+
+impl ConnectionState {
+    pub fn new(negotiated: &Negotiated) -> Self {
+        ConnectionState {
+            channels: HashMap::new(),
+            closed: None,
+            blocked: false,
+            channel_max: negotiated.tune.channel_max,
+            cancel_notify: negotiated.capabilities.contains(&Capability::ConsumerCancelNotify),
+        }
+    }
+
+    /// Allocates the lowest free channel id, to send `channel.open` on.
+    pub fn open_channel(&mut self) -> Result<u16> {
+        if self.closed.is_some() {
+            return Err(self.closed_error());
+        }
+        let channel_max = if self.channel_max == 0 {
+            u16::MAX
+        } else {
+            self.channel_max
+        };
+        let id = match (1..=channel_max).find(|id| !self.channels.contains_key(id)) {
+            Some(id) => id,
+            None => return Err(ErrorKind::Protocol("No free channel ids left".to_string()).into()),
+        };
+        self.channels.insert(id,
+                             ChannelState {
+                                 consumers: ConsumerRegistry::new(self.cancel_notify),
+                                 confirms: None,
+                                 returns: vec![],
+                                 closed: None,
+                                 assembler: ContentAssembler::new(),
+                             });
+        Ok(id)
+    }
+
+    /// Frees the id of a closed channel, cancelling its consumers.
+    pub fn remove_channel(&mut self, id: u16) {
+        if let Some(mut channel) = self.channels.remove(&id) {
+            channel.consumers.cancel_all();
+        }
+    }
+
+    pub fn channel(&self, id: u16) -> Option<&ChannelState> {
+        self.channels.get(&id)
+    }
+
+    pub fn channel_mut(&mut self, id: u16) -> Option<&mut ChannelState> {
+        self.channels.get_mut(&id)
+    }
+
+    /// Once every message published on the channel is confirmed, returns whether
+    /// all of them were acked and starts over. `None` while confirms are outstanding.
+    pub fn take_confirms(&mut self, id: u16) -> Result<Option<bool>> {
+        match self.channels.get_mut(&id) {
+            Some(&mut ChannelState { closed: None, ref mut confirms, .. }) => {
+                match *confirms {
+                    Some(ref mut confirms) if confirms.is_settled() => {
+                        Ok(Some(confirms.take_all_acked()))
+                    }
+                    Some(_) => Ok(None),
+                    None => {
+                        Err(ErrorKind::Protocol("Channel is not in confirm mode".to_string())
+                            .into())
+                    }
+                }
+            }
+            _ => Err(self.channel_error(id)),
+        }
+    }
+
+    /// True while the server has blocked publishing with `connection.blocked`.
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
+
+    /// The reply code and text once the connection is closed.
+    pub fn closed(&self) -> Option<&(u16, String)> {
+        self.closed.as_ref()
+    }
+
+    pub fn closed_error(&self) -> Error {
+        match self.closed {
+            Some((code, ref text)) => ErrorKind::ConnectionClosed(code, text.clone()).into(),
+            None => ErrorKind::ConnectionClosed(0, "Connection reset".to_string()).into(),
+        }
+    }
+
+    /// Why a channel can't be used anymore: closed by the server, or with its connection.
+    pub fn channel_error(&self, id: u16) -> Error {
+        match self.channels.get(&id).and_then(|channel| channel.closed.as_ref()) {
+            Some(&(code, ref text)) => ErrorKind::ChannelClosed(code, text.clone()).into(),
+            None => self.closed_error(),
+        }
+    }
+
+    /// Closes the connection, keeping the reason it was closed with first,
+    /// and removes all channels.
+    pub fn shutdown(&mut self, reason: Option<(u16, String)>) {
+        if self.closed.is_none() {
+            self.closed = Some(reason.unwrap_or_else(|| (0, "Connection reset".to_string())));
+        }
+        for (_, mut channel) in self.channels.drain() {
+            channel.consumers.cancel_all();
+        }
+    }
+
+    pub fn handle_frame(&mut self, frame: Frame) -> Result<Vec<Action>> {
+        if frame.frame_type == FrameType::HEARTBEAT {
+            return Ok(vec![]);
+        }
+        if frame.channel == 0 {
+            return self.handle_connection_method(MethodFrame::decode(&frame)?);
+        }
+        let id = frame.channel;
+        let channel = match self.channels.get_mut(&id) {
+            Some(channel) if channel.closed.is_none() => channel,
+            _ => {
+                debug!("Dropping frame for unknown or closed channel {}", id);
+                return Ok(vec![]);
+            }
+        };
+        match channel.assembler.push(&frame)? {
+            None => Ok(vec![]),
+            Some(Incoming::Content(content)) => channel.handle_content(id, content),
+            Some(Incoming::Method(method_frame)) => channel.handle_method(id, method_frame),
+        }
+    }
+
+    fn handle_connection_method(&mut self, method_frame: MethodFrame) -> Result<Vec<Action>> {
+        match method_frame.method_name() {
+            "connection.close" => {
+                let close = connection::Close::decode(method_frame.clone())?;
+                self.shutdown(Some((close.reply_code, close.reply_text)));
+                Ok(vec![Action::Send(connection::CloseOk.to_frame(0)?),
+                        Action::Connection(method_frame),
+                        Action::Closed])
+            }
+            "connection.close-ok" => {
+                self.shutdown(Some((200, "Goodbye".to_string())));
+                Ok(vec![Action::Connection(method_frame), Action::Closed])
+            }
+            "connection.blocked" => {
+                self.blocked = true;
+                Ok(vec![])
+            }
+            "connection.unblocked" => {
+                self.blocked = false;
+                Ok(vec![])
+            }
+            _ => Ok(vec![Action::Connection(method_frame)]),
+        }
+    }
+}
+
+impl ChannelState {
+    fn handle_content(&mut self, id: u16, content: Box<Content>) -> Result<Vec<Action>> {
+        match content.method.method_name() {
+            "basic.deliver" => {
+                let delivery = Delivery {
+                    deliver: basic::Deliver::decode(content.method)?,
+                    properties: content.properties,
+                    body: content.body,
+                };
+                self.consumers.deliver(delivery);
+                Ok(vec![])
+            }
+            "basic.return" => {
+                self.returns.push(Returned {
+                    reply: basic::Return::decode(content.method)?,
+                    properties: content.properties,
+                    body: content.body,
+                });
+                Ok(vec![])
+            }
+            _ => Ok(vec![Action::Channel(id, Incoming::Content(content))]),
+        }
+    }
+
+    fn handle_method(&mut self, id: u16, method_frame: MethodFrame) -> Result<Vec<Action>> {
+        match method_frame.method_name() {
+            "basic.ack" => {
+                let ack = basic::Ack::decode(method_frame)?;
+                if let Some(ref mut confirms) = self.confirms {
+                    confirms.ack(ack.delivery_tag, ack.multiple);
+                }
+                Ok(vec![Action::Confirmed(id)])
+            }
+            "basic.nack" => {
+                let nack = basic::Nack::decode(method_frame)?;
+                if let Some(ref mut confirms) = self.confirms {
+                    confirms.nack(nack.delivery_tag, nack.multiple);
+                }
+                Ok(vec![Action::Confirmed(id)])
+            }
+            "basic.cancel" => {
+                let cancel = basic::Cancel::decode(method_frame)?;
+                match self.consumers.server_cancel(&cancel)? {
+                    Some(cancel_ok) => Ok(vec![Action::Send(cancel_ok.to_frame(id)?)]),
+                    None => Ok(vec![]),
+                }
+            }
+            "channel.flow" => {
+                let flow = channel::Flow::decode(method_frame)?;
+                Ok(vec![Action::Send(channel::FlowOk { active: flow.active }.to_frame(id)?)])
+            }
+            "channel.close" => {
+                let close = channel::Close::decode(method_frame)?;
+                self.closed = Some((close.reply_code, close.reply_text));
+                self.consumers.cancel_all();
+                Ok(vec![Action::ChannelClosed(id), Action::Send(channel::CloseOk.to_frame(id)?)])
+            }
+            name => {
+                // Register consumers right away, so deliveries which immediately
+                // follow `basic.consume-ok` find their consumer.
+                if name == "basic.consume-ok" {
+                    let consume_ok = basic::ConsumeOk::decode(method_frame.clone())?;
+                    self.consumers.consume_ok(&consume_ok)?;
+                } else if name == "basic.cancel-ok" {
+                    let cancel_ok = basic::CancelOk::decode(method_frame.clone())?;
+                    self.consumers.cancel_ok(&cancel_ok);
+                }
+                Ok(vec![Action::Channel(id, Incoming::Method(method_frame))])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::channel;
+    use crate::consumer::Delivery;
+    use crate::content::encode_content;
+    use crate::peer_properties::ServerProperties;
+    use crate::protocol::basic::BasicProperties;
+    use super::*;
+
+    fn state() -> ConnectionState {
+        ConnectionState::new(&Negotiated {
+            server_properties: ServerProperties::from_start(&connection::Start::default()),
+            capabilities: vec![Capability::ConsumerCancelNotify],
+            tune: connection::TuneOk {
+                channel_max: 2,
+                frame_max: 4096,
+                heartbeat: 0,
+            },
+        })
+    }
+
+    fn handle<M: Method>(state: &mut ConnectionState, channel: u16, method: &M) -> Vec<Action> {
+        state.handle_frame(method.to_frame(channel).unwrap()).unwrap()
+    }
+
+    fn sent(actions: &[Action]) -> Vec<String> {
+        actions.iter()
+            .filter_map(|action| match *action {
+                Action::Send(ref frame) => {
+                    Some(MethodFrame::decode(frame).unwrap().method_name().to_string())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_channel_ids() {
+        let mut state = state();
+        assert_eq!(state.open_channel().unwrap(), 1);
+        assert_eq!(state.open_channel().unwrap(), 2);
+        assert!(state.open_channel().is_err());
+        state.remove_channel(1);
+        assert_eq!(state.open_channel().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_consume_and_confirm() {
+        let mut state = state();
+        let id = state.open_channel().unwrap();
+        let (tx, rx) = channel();
+        state.channel_mut(id)
+            .unwrap()
+            .consumers
+            .start_consume(Box::new(move |delivery: Delivery| tx.send(delivery.body).unwrap()));
+        match handle(&mut state, id, &basic::ConsumeOk { consumer_tag: "ctag".to_string() })[..] {
+            [Action::Channel(1, Incoming::Method(ref method_frame))] => {
+                assert_eq!(method_frame.method_name(), "basic.consume-ok")
+            }
+            ref other => panic!("Unexpected {:?}", other),
+        }
+        let deliver = basic::Deliver {
+            consumer_tag: "ctag".to_string(),
+            delivery_tag: 1,
+            redelivered: false,
+            exchange: "".to_string(),
+            routing_key: "q".to_string(),
+        };
+        for frame in encode_content(id, &deliver, &BasicProperties::default(), b"hello", 0).unwrap() {
+            assert!(state.handle_frame(frame).unwrap().is_empty());
+        }
+        assert_eq!(rx.try_recv().unwrap(), b"hello");
+
+        let mut confirms = ConfirmTracker::new();
+        confirms.published();
+        state.channel_mut(id).unwrap().confirms = Some(confirms);
+        let ack = basic::Ack {
+            delivery_tag: 1,
+            multiple: false,
+        };
+        match handle(&mut state, id, &ack)[..] {
+            [Action::Confirmed(1)] => {}
+            ref other => panic!("Unexpected {:?}", other),
+        }
+        assert!(state.channel(id).unwrap().confirms.as_ref().unwrap().is_settled());
+
+        let flow = channel::Flow { active: false };
+        assert_eq!(sent(&handle(&mut state, id, &flow)), vec!["channel.flow-ok"]);
+        let cancel = basic::Cancel {
+            consumer_tag: "ctag".to_string(),
+            nowait: false,
+        };
+        assert_eq!(sent(&handle(&mut state, id, &cancel)), vec!["basic.cancel-ok"]);
+        assert!(state.channel(id).unwrap().consumers.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_consumer() {
+        let mut state = state();
+        let id = state.open_channel().unwrap();
+        // The server confirms cancelling a consumer it doesn't know, e.g. a mistyped tag.
+        match handle(&mut state, id, &basic::CancelOk { consumer_tag: "typo".to_string() })[..] {
+            [Action::Channel(1, Incoming::Method(ref method_frame))] => {
+                assert_eq!(method_frame.method_name(), "basic.cancel-ok")
+            }
+            ref other => panic!("Unexpected {:?}", other),
+        }
+        let deliver = basic::Deliver {
+            consumer_tag: "unknown".to_string(),
+            delivery_tag: 1,
+            redelivered: false,
+            exchange: "".to_string(),
+            routing_key: "q".to_string(),
+        };
+        for frame in encode_content(id, &deliver, &BasicProperties::default(), b"hello", 0).unwrap() {
+            assert!(state.handle_frame(frame).unwrap().is_empty());
+        }
+        assert!(state.closed().is_none());
+    }
+
+    #[test]
+    fn test_server_closes() {
+        let mut state = state();
+        let id = state.open_channel().unwrap();
+        let close = channel::Close {
+            reply_code: 404,
+            reply_text: "NOT_FOUND".to_string(),
+            class_id: 50,
+            method_id: 10,
+        };
+        let actions = handle(&mut state, id, &close);
+        assert_eq!(sent(&actions), vec!["channel.close-ok"]);
+        match state.channel_error(id) {
+            Error(ErrorKind::ChannelClosed(404, _), _) => {}
+            other => panic!("Unexpected {:?}", other),
+        }
+        // The id stays taken until the channel is removed, and later frames are dropped.
+        assert_eq!(state.open_channel().unwrap(), 2);
+        assert!(handle(&mut state, id, &basic::QosOk).is_empty());
+
+        handle(&mut state, 0, &connection::Blocked { reason: "low memory".to_string() });
+        assert!(state.is_blocked());
+        let close = connection::Close {
+            reply_code: 320,
+            reply_text: "CONNECTION_FORCED".to_string(),
+            class_id: 0,
+            method_id: 0,
+        };
+        let actions = handle(&mut state, 0, &close);
+        assert_eq!(sent(&actions), vec!["connection.close-ok"]);
+        assert!(matches!(actions.last(), Some(Action::Closed)));
+        assert!(state.channel(2).is_none());
+        match state.open_channel() {
+            Err(Error(ErrorKind::ConnectionClosed(320, _), _)) => {}
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::protocol::basic::{self, BasicProperties};
+use crate::error::*;
+
+/// A fully assembled message delivered to a consumer:
+/// the `basic.deliver` method, its content header properties and the body.
+#[derive(Debug, PartialEq)]
+pub struct Delivery {
+    pub deliver: basic::Deliver,
+    pub properties: BasicProperties,
+    pub body: Vec<u8>,
+}
+
+pub trait Consumer: Send {
+    fn handle_delivery(&mut self, delivery: Delivery);
+
+    /// Called when the consumer was cancelled, either by the client (`basic.cancel-ok`)
+    /// or by the server (`basic.cancel`, e.g. when the queue was deleted).
+    fn handle_cancel(&mut self, _consumer_tag: &str) {}
+}
+
+impl<F> Consumer for F
+    where F: FnMut(Delivery) + Send
+{
+    fn handle_delivery(&mut self, delivery: Delivery) {
+        self(delivery)
+    }
+}
+
+/// Keeps track of the consumers of a single channel.
+///
+/// A consumer is registered with `start_consume` before `basic.consume` is sent
+/// and gets its consumer tag assigned by the matching `basic.consume-ok`.
+/// Since the server replies to synchronous methods in order, pending consumers
+/// are matched with `basic.consume-ok` replies in FIFO order.
+pub struct ConsumerRegistry {
+    pending: VecDeque<Box<dyn Consumer>>,
+    consumers: HashMap<String, Box<dyn Consumer>>,
+    cancel_notify: bool,
+}
+
+impl ConsumerRegistry {
+    /// `cancel_notify` should be set when both peers negotiated the
+    /// `consumer_cancel_notify` capability, otherwise a server-sent `basic.cancel`
+    /// is treated as a protocol error.
+    pub fn new(cancel_notify: bool) -> Self {
+        ConsumerRegistry {
+            pending: VecDeque::new(),
+            consumers: HashMap::new(),
+            cancel_notify,
+        }
+    }
+
+    /// Queues a consumer waiting for its `basic.consume-ok`.
+    pub fn start_consume(&mut self, consumer: Box<dyn Consumer>) {
+        self.pending.push_back(consumer);
+    }
+
+    /// Drops the newest pending consumer, when its `basic.consume` failed.
+    pub fn cancel_pending(&mut self) {
+        self.pending.pop_back();
+    }
+
+    /// Registers a consumer under a known tag, used for `basic.consume` with `nowait` set.
+    pub fn register(&mut self, consumer_tag: &str, consumer: Box<dyn Consumer>) -> Result<()> {
+        self.check_tag(consumer_tag)?;
+        self.consumers.insert(consumer_tag.to_string(), consumer);
+        Ok(())
+    }
+
+    /// Registers the oldest pending consumer under the tag of the `basic.consume-ok`.
+    /// It stays pending if the tag is invalid or already in use.
+    pub fn consume_ok(&mut self, consume_ok: &basic::ConsumeOk) -> Result<()> {
+        if self.pending.is_empty() {
+            return Err(ErrorKind::Protocol(format!("Unexpected basic.consume-ok for '{}'",
+                                                   consume_ok.consumer_tag))
+                .into());
+        }
+        self.check_tag(&consume_ok.consumer_tag)?;
+        let consumer = self.pending.pop_front().expect("pending consumers were checked");
+        self.consumers.insert(consume_ok.consumer_tag.clone(), consumer);
+        Ok(())
+    }
+
+    /// Routes a delivery to the consumer it was sent to. Deliveries for unknown
+    /// consumers are dropped, that's a problem of the channel and not of the connection.
+    pub fn deliver(&mut self, delivery: Delivery) {
+        match self.consumers.get_mut(&delivery.deliver.consumer_tag) {
+            Some(consumer) => consumer.handle_delivery(delivery),
+            None => {
+                debug!("Dropping delivery {} for unknown consumer '{}'",
+                       delivery.deliver.delivery_tag,
+                       delivery.deliver.consumer_tag)
+            }
+        }
+    }
+
+    /// Completes a client-initiated cancel. The server replies with `basic.cancel-ok`
+    /// even for consumer tags it doesn't know, so unknown tags are ignored.
+    pub fn cancel_ok(&mut self, cancel_ok: &basic::CancelOk) {
+        match self.consumers.remove(&cancel_ok.consumer_tag) {
+            Some(mut consumer) => consumer.handle_cancel(&cancel_ok.consumer_tag),
+            None => {
+                debug!("Ignoring basic.cancel-ok for unknown consumer '{}'",
+                       cancel_ok.consumer_tag)
+            }
+        }
+    }
+
+    /// Handles a server-initiated `basic.cancel`, returning the `basic.cancel-ok`
+    /// to send back unless the server asked for `nowait`. Unknown consumer tags are
+    /// ignored, the consumer may have been cancelled by the client in the meantime.
+    pub fn server_cancel(&mut self, cancel: &basic::Cancel) -> Result<Option<basic::CancelOk>> {
+        if !self.cancel_notify {
+            return Err(ErrorKind::Protocol("Received basic.cancel without consumer_cancel_notify \
+                                            capability"
+                    .to_string())
+                .into());
+        }
+        match self.consumers.remove(&cancel.consumer_tag) {
+            Some(mut consumer) => consumer.handle_cancel(&cancel.consumer_tag),
+            None => debug!("Ignoring basic.cancel for unknown consumer '{}'", cancel.consumer_tag),
+        }
+        if cancel.nowait {
+            Ok(None)
+        } else {
+            Ok(Some(basic::CancelOk { consumer_tag: cancel.consumer_tag.clone() }))
+        }
+    }
+
+    /// Cancels every consumer, e.g. when the channel is closed.
+    pub fn cancel_all(&mut self) {
+        self.pending.clear();
+        for (consumer_tag, mut consumer) in self.consumers.drain() {
+            consumer.handle_cancel(&consumer_tag);
+        }
+    }
+
+    pub fn contains(&self, consumer_tag: &str) -> bool {
+        self.consumers.contains_key(consumer_tag)
+    }
+
+    pub fn consumer_tags(&self) -> Vec<&str> {
+        self.consumers.keys().map(|tag| tag.as_str()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.consumers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.consumers.is_empty()
+    }
+
+    fn check_tag(&self, consumer_tag: &str) -> Result<()> {
+        if consumer_tag.is_empty() {
+            return Err(ErrorKind::Protocol("Consumer tag must not be empty".to_string()).into());
+        }
+        if self.consumers.contains_key(consumer_tag) {
+            return Err(ErrorKind::Protocol(format!("Consumer tag '{}' is already in use",
+                                                   consumer_tag))
+                .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::{channel, Sender};
+    use crate::protocol::basic::{self, BasicProperties};
+    use super::*;
+
+    fn delivery(consumer_tag: &str, delivery_tag: u64) -> Delivery {
+        Delivery {
+            deliver: basic::Deliver {
+                consumer_tag: consumer_tag.to_string(),
+                delivery_tag,
+                redelivered: false,
+                exchange: "".to_string(),
+                routing_key: "test".to_string(),
+            },
+            properties: BasicProperties::default(),
+            body: b"hello".to_vec(),
+        }
+    }
+
+    struct CancelRecorder(Sender<String>);
+
+    impl Consumer for CancelRecorder {
+        fn handle_delivery(&mut self, _delivery: Delivery) {}
+
+        fn handle_cancel(&mut self, consumer_tag: &str) {
+            self.0.send(consumer_tag.to_string()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_routes_deliveries_by_tag() {
+        let (tx_a, rx_a) = channel();
+        let (tx_b, rx_b) = channel();
+        let mut registry = ConsumerRegistry::new(true);
+        registry.start_consume(Box::new(move |d: Delivery| tx_a.send(d.deliver.delivery_tag).unwrap()));
+        registry.start_consume(Box::new(move |d: Delivery| tx_b.send(d.deliver.delivery_tag).unwrap()));
+        registry.consume_ok(&basic::ConsumeOk { consumer_tag: "a".to_string() }).unwrap();
+        registry.consume_ok(&basic::ConsumeOk { consumer_tag: "b".to_string() }).unwrap();
+
+        registry.deliver(delivery("b", 1));
+        registry.deliver(delivery("a", 2));
+        // Dropped, without reaching the other consumers.
+        registry.deliver(delivery("c", 3));
+        assert_eq!(rx_a.try_recv().unwrap(), 2);
+        assert_eq!(rx_b.try_recv().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_unexpected_consume_ok() {
+        let mut registry = ConsumerRegistry::new(true);
+        assert!(registry.consume_ok(&basic::ConsumeOk { consumer_tag: "a".to_string() }).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_consume_ok() {
+        let (tx, rx) = channel();
+        let mut registry = ConsumerRegistry::new(true);
+        registry.register("a", Box::new(|_: Delivery| {})).unwrap();
+        registry.start_consume(Box::new(move |d: Delivery| tx.send(d.deliver.delivery_tag).unwrap()));
+        assert!(registry.consume_ok(&basic::ConsumeOk { consumer_tag: "a".to_string() }).is_err());
+        // The consumer is still pending, for the next consume-ok.
+        registry.consume_ok(&basic::ConsumeOk { consumer_tag: "b".to_string() }).unwrap();
+        registry.deliver(delivery("b", 1));
+        assert_eq!(rx.try_recv().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_cancel_ok() {
+        let (tx, rx) = channel();
+        let mut registry = ConsumerRegistry::new(true);
+        registry.register("a", Box::new(CancelRecorder(tx))).unwrap();
+        registry.cancel_ok(&basic::CancelOk { consumer_tag: "a".to_string() });
+        assert!(registry.is_empty());
+        assert_eq!(rx.try_recv().unwrap(), "a");
+        // E.g. after `basic.cancel` with a mistyped tag, which the server confirms anyway.
+        registry.cancel_ok(&basic::CancelOk { consumer_tag: "a".to_string() });
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_server_cancel() {
+        let mut registry = ConsumerRegistry::new(true);
+        registry.register("a", Box::new(|_: Delivery| {})).unwrap();
+        let cancel = basic::Cancel {
+            consumer_tag: "a".to_string(),
+            nowait: false,
+        };
+        assert_eq!(registry.server_cancel(&cancel).unwrap(),
+                   Some(basic::CancelOk { consumer_tag: "a".to_string() }));
+        assert!(registry.is_empty());
+        // E.g. when the client cancelled the consumer at the same time.
+        assert_eq!(registry.server_cancel(&cancel).unwrap(),
+                   Some(basic::CancelOk { consumer_tag: "a".to_string() }));
+    }
+
+    #[test]
+    fn test_server_cancel_requires_capability() {
+        let mut registry = ConsumerRegistry::new(false);
+        registry.register("a", Box::new(|_: Delivery| {})).unwrap();
+        let cancel = basic::Cancel {
+            consumer_tag: "a".to_string(),
+            nowait: true,
+        };
+        assert!(registry.server_cancel(&cancel).is_err());
+        assert!(registry.contains("a"));
+    }
+}
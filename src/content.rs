@@ -0,0 +1,259 @@
+use crate::framing::{ContentHeaderFrame, DecodeLimits, DecodeMode, EncodedProperties, Frame,
+                     FramePayload, FrameType, MethodFrame};
+use crate::method::Method;
+use crate::protocol::basic::{self, BasicProperties};
+use crate::error::*;
+
+/// A content-carrying method (`basic.publish`, `basic.deliver`, ...)
+/// together with its properties and body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Content {
+    pub method: MethodFrame,
+    pub properties: BasicProperties,
+    pub body: Vec<u8>,
+}
+
+/// A message returned by `basic.get`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GetMessage {
+    pub get_ok: basic::GetOk,
+    pub properties: BasicProperties,
+    pub body: Vec<u8>,
+}
+
+/// An unroutable `mandatory` message sent back with `basic.return`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Returned {
+    pub reply: basic::Return,
+    pub properties: BasicProperties,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum Incoming {
+    Method(MethodFrame),
+    Content(Box<Content>),
+}
+
+#[derive(Debug)]
+enum State {
+    Idle,
+    Header(MethodFrame),
+    Body(Box<Content>, u64),
+}
+
+/// Assembles the method, header and body frames of a single channel into messages.
+///
+/// Methods without content are passed through immediately, content-carrying methods
+/// are returned once the header and all body frames have arrived. Messages over the
+/// `DecodeLimits` are rejected as soon as their header arrives.
+#[derive(Debug)]
+pub struct ContentAssembler {
+    state: State,
+    limits: DecodeLimits,
+}
+
+impl Default for ContentAssembler {
+    fn default() -> Self {
+        ContentAssembler::with_limits(DecodeLimits::default())
+    }
+}
+
+impl ContentAssembler {
+    pub fn new() -> Self {
+        ContentAssembler::default()
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> Self {
+        ContentAssembler {
+            state: State::Idle,
+            limits,
+        }
+    }
+
+    /// True while a content-carrying method is waiting for its header or body frames.
+    pub fn in_progress(&self) -> bool {
+        !matches!(self.state, State::Idle)
+    }
+
+    pub fn push(&mut self, frame: &Frame) -> Result<Option<Incoming>> {
+        let state = ::std::mem::replace(&mut self.state, State::Idle);
+        match (state, frame.frame_type) {
+            (State::Idle, FrameType::METHOD) => {
+                let method_frame = MethodFrame::decode(frame)?;
+                if method_frame.carries_content() {
+                    self.state = State::Header(method_frame);
+                    Ok(None)
+                } else {
+                    Ok(Some(Incoming::Method(method_frame)))
+                }
+            }
+            (State::Header(method), FrameType::HEADERS) => {
+                let header = ContentHeaderFrame::decode(frame)?;
+                let body_size = header.body_size;
+                if body_size > self.limits.max_body_size {
+                    return Err(ErrorKind::LimitExceeded(format!("body of {} bytes exceeds {}",
+                                                                body_size,
+                                                                self.limits.max_body_size))
+                        .into());
+                }
+                let message_bytes = method.arguments.inner().len() + header.properties.inner().len();
+                if message_bytes > self.limits.max_message_bytes {
+                    return Err(ErrorKind::LimitExceeded(format!("method and header of {} bytes \
+                                                                 exceed {}",
+                                                                message_bytes,
+                                                                self.limits.max_message_bytes))
+                        .into());
+                }
+                let content = Box::new(Content {
+                    method,
+                    properties: BasicProperties::decode_with_limits(header,
+                                                                    DecodeMode::Strict,
+                                                                    &self.limits)?,
+                    body: Vec::new(),
+                });
+                self.body(content, body_size)
+            }
+            (State::Body(mut content, body_size), FrameType::BODY) => {
+                content.body.extend_from_slice(frame.payload.inner());
+                self.body(content, body_size)
+            }
+            (state, frame_type) => {
+                Err(ErrorKind::Protocol(format!("Unexpected {:?} frame while in {:?}",
+                                                frame_type,
+                                                state))
+                    .into())
+            }
+        }
+    }
+
+    fn body(&mut self, content: Box<Content>, body_size: u64) -> Result<Option<Incoming>> {
+        let received = content.body.len() as u64;
+        if received > body_size {
+            Err(ErrorKind::Protocol(format!("Received {} body bytes, expected {}",
+                                            received,
+                                            body_size))
+                .into())
+        } else if received == body_size {
+            Ok(Some(Incoming::Content(content)))
+        } else {
+            self.state = State::Body(content, body_size);
+            Ok(None)
+        }
+    }
+}
+
+/// Encodes a content-carrying method into its method, header and body frames,
+/// splitting the body so no frame exceeds `frame_max` (zero means no limit).
+pub fn encode_content<M: Method>(channel: u16,
+                                 method: &M,
+                                 properties: &BasicProperties,
+                                 body: &[u8],
+                                 frame_max: u32)
+                                 -> Result<Vec<Frame>> {
+    let header = ContentHeaderFrame {
+        content_class: M::CLASS_ID,
+        weight: 0,
+        body_size: body.len() as u64,
+        properties_flags: properties.flags(),
+        properties: EncodedProperties::new(properties.clone().encode()?),
+    };
+    let mut frames = vec![method.to_frame(channel)?,
+                          Frame {
+                              frame_type: FrameType::HEADERS,
+                              channel,
+                              payload: FramePayload::new(header.encode()?),
+                          }];
+    let chunk_size = if frame_max == 0 {
+        body.len().max(1)
+    } else if frame_max <= 8 {
+        return Err(ErrorKind::Protocol(format!("frame_max {} is too small", frame_max)).into());
+    } else {
+        frame_max as usize - 8
+    };
+    for chunk in body.chunks(chunk_size) {
+        frames.push(Frame {
+            frame_type: FrameType::BODY,
+            channel,
+            payload: FramePayload::new(chunk.to_vec()),
+        });
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::framing::FrameType;
+    use crate::method::Method;
+    use crate::protocol::basic::{self, BasicProperties};
+    use super::*;
+
+    #[test]
+    fn test_encode_and_assemble() {
+        let publish = basic::Publish {
+            exchange: "x".to_string(),
+            routing_key: "k".to_string(),
+            ..Default::default()
+        };
+        let properties = BasicProperties {
+            content_type: Some("text/plain".to_string()),
+            delivery_mode: Some(2),
+            ..Default::default()
+        };
+        let body: Vec<u8> = (0..100).collect();
+        let frames = encode_content(3, &publish, &properties, &body, 48).unwrap();
+        // method, header and 100 bytes in 40 byte chunks
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames[4].frame_type, FrameType::BODY);
+
+        let mut assembler = ContentAssembler::new();
+        let mut result = None;
+        for frame in &frames {
+            assert!(result.is_none());
+            result = assembler.push(frame).unwrap();
+        }
+        match result {
+            Some(Incoming::Content(content)) => {
+                assert_eq!(basic::Publish::decode(content.method).unwrap(), publish);
+                assert_eq!(content.properties, properties);
+                assert_eq!(content.body, body);
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(!assembler.in_progress());
+    }
+
+    #[test]
+    fn test_empty_body() {
+        let frames = encode_content(1, &basic::Publish::default(), &Default::default(), &[], 0)
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        let mut assembler = ContentAssembler::new();
+        assert!(assembler.push(&frames[0]).unwrap().is_none());
+        assert!(assembler.push(&frames[1]).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_unexpected_frames() {
+        let frames = encode_content(1, &basic::Publish::default(), &Default::default(), b"a", 0)
+            .unwrap();
+        let mut assembler = ContentAssembler::new();
+        assert!(assembler.push(&frames[1]).is_err());
+        assert!(assembler.push(&frames[0]).unwrap().is_none());
+        assert!(assembler.push(&frames[0]).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let publish = basic::Publish { exchange: "x".repeat(20), ..Default::default() };
+        let frames = encode_content(1, &publish, &Default::default(), &[0; 100], 0).unwrap();
+        let push_all = |limits: DecodeLimits| {
+            let mut assembler = ContentAssembler::with_limits(limits);
+            assembler.push(&frames[0]).and_then(|_| assembler.push(&frames[1]))
+        };
+        assert!(push_all(DecodeLimits { max_body_size: 99, ..Default::default() }).is_err());
+        assert!(push_all(DecodeLimits { max_message_bytes: 20, ..Default::default() }).is_err());
+        assert!(push_all(DecodeLimits { max_body_size: 100, max_message_bytes: 30, ..Default::default() })
+            .is_ok());
+    }
+}
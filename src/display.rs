@@ -0,0 +1,360 @@
+use std::fmt;
+use std::str;
+
+use crate::framing::{ContentHeaderFrame, Frame, FrameType, MethodFrame, UnknownProperties};
+use crate::method::Method;
+use crate::protocol::basic::{self, BasicProperties};
+use crate::table::{Table, TableEntry};
+
+/// The number of body bytes `Frame`'s `Display` impl shows before eliding the rest.
+pub const DEFAULT_BODY_LIMIT: usize = 64;
+
+/// A value of a method argument or property, as shown by the `Display` impls
+/// of the generated method and properties structs.
+pub trait DisplayArgument {
+    fn fmt_argument(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+macro_rules! display_plain {
+    ($($ty:ty),*) => ($(
+        impl DisplayArgument for $ty {
+            fn fmt_argument(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Display::fmt(self, f)
+            }
+        }
+    )*)
+}
+
+display_plain!(bool, u8, u16, u32, u64);
+
+impl DisplayArgument for String {
+    fn fmt_argument(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl DisplayArgument for Table {
+    fn fmt_argument(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Sorted, so the output doesn't depend on the hash map's order.
+        let mut entries: Vec<_> = self.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let mut fields = FieldsDisplay::new(f, "");
+        for (name, value) in entries {
+            fields.field(name, value);
+        }
+        fields.finish()
+    }
+}
+
+/// Only the size, the properties can't be decoded without their types.
+impl DisplayArgument for UnknownProperties {
+    fn fmt_argument(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bytes", self.data.len())
+    }
+}
+
+impl DisplayArgument for TableEntry {
+    fn fmt_argument(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Renders strings quoted, numbers by value and nested tables and arrays
+/// as `{name=value, ...}` and `[value, ...]`.
+impl fmt::Display for TableEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TableEntry::Bool(value) => write!(f, "{}", value),
+            TableEntry::ShortShortInt(value) => write!(f, "{}", value),
+            TableEntry::ShortShortUint(value) => write!(f, "{}", value),
+            TableEntry::ShortInt(value) => write!(f, "{}", value),
+            TableEntry::ShortUint(value) => write!(f, "{}", value),
+            TableEntry::LongInt(value) => write!(f, "{}", value),
+            TableEntry::LongUint(value) => write!(f, "{}", value),
+            TableEntry::LongLongInt(value) => write!(f, "{}", value),
+            TableEntry::LongLongUint(value) => write!(f, "{}", value),
+            TableEntry::Float(value) => write!(f, "{}", value),
+            TableEntry::Double(value) => write!(f, "{}", value),
+            TableEntry::DecimalValue(scale, value) => fmt_decimal(f, scale, value),
+            TableEntry::LongString(ref value) => write!(f, "{:?}", value),
+            TableEntry::FieldArray(ref values) => {
+                f.write_str("[")?;
+                for (idx, value) in values.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            TableEntry::Timestamp(value) => write!(f, "{}", value),
+            TableEntry::FieldTable(ref table) => table.fmt_argument(f),
+            TableEntry::Void => f.write_str("void"),
+        }
+    }
+}
+
+fn fmt_decimal(f: &mut fmt::Formatter, scale: u8, value: u32) -> fmt::Result {
+    let digits = value.to_string();
+    let scale = scale as usize;
+    if scale == 0 {
+        return f.write_str(&digits);
+    }
+    let digits = format!("{:0>width$}", digits, width = scale + 1);
+    let (units, fraction) = digits.split_at(digits.len() - scale);
+    write!(f, "{}.{}", units, fraction)
+}
+
+/// Writes `prefix{name=value, ...}`, dropping the leading underscore of
+/// field names like `_type`.
+pub struct FieldsDisplay<'a, 'b: 'a> {
+    f: &'a mut fmt::Formatter<'b>,
+    result: fmt::Result,
+    has_fields: bool,
+}
+
+impl<'a, 'b: 'a> FieldsDisplay<'a, 'b> {
+    pub fn new(f: &'a mut fmt::Formatter<'b>, prefix: &str) -> Self {
+        let result = f.write_str(prefix).and_then(|_| f.write_str("{"));
+        FieldsDisplay {
+            f,
+            result,
+            has_fields: false,
+        }
+    }
+
+    pub fn field<T: DisplayArgument + ?Sized>(&mut self, name: &str, value: &T) -> &mut Self {
+        if self.result.is_ok() {
+            let separator = if self.has_fields { ", " } else { "" };
+            self.has_fields = true;
+            self.result = write!(self.f, "{}{}=", separator, name.trim_start_matches('_'))
+                .and_then(|_| value.fmt_argument(self.f));
+        }
+        self
+    }
+
+    pub fn finish(&mut self) -> fmt::Result {
+        self.result.and_then(|_| self.f.write_str("}"))
+    }
+}
+
+pub(crate) fn fmt_decoded<M>(method_frame: &MethodFrame, f: &mut fmt::Formatter) -> fmt::Result
+    where M: Method + fmt::Display
+{
+    match M::decode(method_frame.clone()) {
+        Ok(method) => write!(f, "{}", method),
+        Err(err) => write!(f, "{}<malformed: {}>", method_frame.method_name(), err),
+    }
+}
+
+/// Renders the class, body size and the decoded properties of `basic` content,
+/// e.g. `class=60 size=1024 {content_type="application/json", delivery_mode=2}`.
+impl fmt::Display for ContentHeaderFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "class={} size={} ", self.content_class, self.body_size)?;
+        if self.content_class != basic::Publish::CLASS_ID {
+            let flags: Vec<String> =
+                self.properties_flags.iter().map(|word| format!("{:#06x}", word)).collect();
+            return write!(f,
+                          "flags={} properties={} bytes",
+                          flags.join(","),
+                          self.properties.inner().len());
+        }
+        match BasicProperties::decode(self.clone()) {
+            Ok(properties) => write!(f, "{}", properties),
+            Err(err) => write!(f, "<malformed properties: {}>", err),
+        }
+    }
+}
+
+/// Displays a frame, showing at most `body_limit` bytes of a body frame.
+/// Returned by `Frame::display`.
+pub struct FrameDisplay<'a> {
+    frame: &'a Frame,
+    body_limit: Option<usize>,
+}
+
+impl<'a> FrameDisplay<'a> {
+    /// Shows at most `limit` bytes of a body, eliding the rest.
+    pub fn body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = Some(limit);
+        self
+    }
+
+    /// Shows bodies in full.
+    pub fn full_body(mut self) -> Self {
+        self.body_limit = None;
+        self
+    }
+}
+
+impl Frame {
+    /// Displays the frame with a configurable body truncation.
+    /// The `Display` impl of `Frame` shows up to `DEFAULT_BODY_LIMIT` body bytes.
+    pub fn display(&self) -> FrameDisplay<'_> {
+        FrameDisplay {
+            frame: self,
+            body_limit: Some(DEFAULT_BODY_LIMIT),
+        }
+    }
+}
+
+impl<'a> fmt::Display for FrameDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frame = self.frame;
+        write!(f, "ch={} {:?}", frame.channel, frame.frame_type)?;
+        match frame.frame_type {
+            FrameType::METHOD => {
+                match MethodFrame::decode(frame) {
+                    Ok(method_frame) => write!(f, " {}", method_frame),
+                    Err(err) => write!(f, " <malformed: {}>", err),
+                }
+            }
+            FrameType::HEADERS => {
+                match ContentHeaderFrame::decode(frame) {
+                    Ok(header) => write!(f, " {}", header),
+                    Err(err) => write!(f, " <malformed: {}>", err),
+                }
+            }
+            FrameType::BODY => {
+                let body = frame.payload.inner();
+                write!(f, " size={} ", body.len())?;
+                fmt_body(f, body, self.body_limit)
+            }
+            FrameType::HEARTBEAT => Ok(()),
+        }
+    }
+}
+
+/// Renders `ch=1 METHOD basic.publish{...}`, `ch=1 HEADERS class=60 ...`,
+/// `ch=1 BODY size=5 "hello"` or `ch=0 HEARTBEAT`.
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.display().fmt(f)
+    }
+}
+
+/// Writes UTF-8 bodies as a quoted string and others as hex bytes.
+fn fmt_body(f: &mut fmt::Formatter, body: &[u8], limit: Option<usize>) -> fmt::Result {
+    let shown = limit.map_or(body.len(), |limit| limit.min(body.len()));
+    let mut data = &body[..shown];
+    let text = match str::from_utf8(data) {
+        Ok(text) => Some(text),
+        // A character cut in half by the limit doesn't make the body binary.
+        Err(err) if err.error_len().is_none() && shown < body.len() => {
+            data = &data[..err.valid_up_to()];
+            str::from_utf8(data).ok()
+        }
+        Err(_) => None,
+    };
+    match text {
+        Some(text) => write!(f, "{:?}", text)?,
+        None => {
+            f.write_str("<")?;
+            for (idx, byte) in data.iter().enumerate() {
+                if idx > 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{:02x}", byte)?;
+            }
+            f.write_str(">")?;
+        }
+    }
+    if data.len() < body.len() {
+        write!(f, "... ({} more bytes)", body.len() - data.len())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::framing::{ContentHeaderFrame, EncodedProperties, Frame, FramePayload, FrameType};
+    use crate::method::Method;
+    use crate::protocol::{basic, connection, exchange};
+    use crate::table::{Table, TableEntry};
+
+    #[test]
+    fn test_method_frame() {
+        let publish = basic::Publish {
+            exchange: "x".to_string(),
+            routing_key: "k".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(publish.to_frame(1).unwrap().to_string(),
+                   "ch=1 METHOD basic.publish{ticket=0, exchange=\"x\", routing_key=\"k\", \
+                    mandatory=false, immediate=false}");
+        let declare = exchange::Declare {
+            exchange: "logs".to_string(),
+            _type: "topic".to_string(),
+            ..Default::default()
+        };
+        assert!(declare.to_string().contains("type=\"topic\""));
+        assert_eq!(connection::CloseOk.to_frame(0).unwrap().to_string(),
+                   "ch=0 METHOD connection.close-ok");
+
+        let truncated = Frame {
+            frame_type: FrameType::METHOD,
+            channel: 2,
+            payload: FramePayload::new(vec![0, 60, 0, 80, 1]),
+        };
+        assert!(truncated.to_string().starts_with("ch=2 METHOD basic.ack<malformed: "));
+        let unknown = Frame {
+            frame_type: FrameType::METHOD,
+            channel: 2,
+            payload: FramePayload::new(vec![0, 99, 0, 1, 7]),
+        };
+        assert_eq!(unknown.to_string(),
+                   "ch=2 METHOD UNKNOWN{class_id=99, method_id=1, size=1}");
+    }
+
+    #[test]
+    fn test_headers_frame() {
+        let mut headers = Table::new();
+        headers.insert("b".to_string(),
+                       TableEntry::FieldArray(vec![TableEntry::DecimalValue(2, 5),
+                                                   TableEntry::Void]));
+        headers.insert("a".to_string(), TableEntry::LongString("x".to_string()));
+        let properties = basic::BasicProperties {
+            content_type: Some("application/json".to_string()),
+            delivery_mode: Some(2),
+            headers: Some(headers),
+            ..Default::default()
+        };
+        let header = ContentHeaderFrame {
+            content_class: 60,
+            weight: 0,
+            body_size: 1024,
+            properties_flags: properties.flags(),
+            properties: EncodedProperties::new(properties.clone().encode().unwrap()),
+        };
+        let frame = Frame {
+            frame_type: FrameType::HEADERS,
+            channel: 1,
+            payload: FramePayload::new(header.encode().unwrap()),
+        };
+        assert_eq!(frame.to_string(),
+                   "ch=1 HEADERS class=60 size=1024 {content_type=\"application/json\", \
+                    headers={a=\"x\", b=[0.05, void]}, delivery_mode=2}");
+    }
+
+    #[test]
+    fn test_body_frame() {
+        let body = |data: &[u8]| {
+            Frame {
+                frame_type: FrameType::BODY,
+                channel: 1,
+                payload: FramePayload::new(data.to_vec()),
+            }
+        };
+        assert_eq!(body(b"hello").to_string(), "ch=1 BODY size=5 \"hello\"");
+        assert_eq!(body(b"hello world").display().body_limit(5).to_string(),
+                   "ch=1 BODY size=11 \"hello\"... (6 more bytes)");
+        assert_eq!(body("é!".as_bytes()).display().body_limit(1).to_string(),
+                   "ch=1 BODY size=3 \"\"... (3 more bytes)");
+        assert_eq!(body(&[0, 0xff, 1]).display().body_limit(2).to_string(),
+                   "ch=1 BODY size=3 <00 ff>... (1 more bytes)");
+        let long = vec![b'a'; 100];
+        assert_eq!(body(&long).display().full_body().to_string().len(),
+                   "ch=1 BODY size=100 \"\"".len() + 100);
+    }
+}
@@ -0,0 +1,355 @@
+use std::fmt::{self, Write};
+
+use byteorder::{BigEndian, ByteOrder};
+use enum_primitive::FromPrimitive;
+
+use crate::error::*;
+use crate::framing::{ContentHeaderFrame, Frame, FramePayload, FrameType, MethodFrame,
+                     ProtocolHeader};
+
+/// Frame header (type, channel, size) plus the frame end octet.
+const FRAME_OVERHEAD: usize = 8;
+
+/// Which peer sent a captured stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+impl Direction {
+    /// `C->S` or `S->C`.
+    pub fn arrow(&self) -> &'static str {
+        match *self {
+            Direction::ClientToServer => "C->S",
+            Direction::ServerToClient => "S->C",
+        }
+    }
+}
+
+/// Something found in a captured byte stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    ProtocolHeader(ProtocolHeader),
+    Frame(Frame),
+    /// Bytes which don't form a valid frame. Decoding resumes at the next offset
+    /// where a valid frame starts.
+    Malformed(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamItem {
+    /// The byte offset of the event in the stream.
+    pub offset: usize,
+    pub event: StreamEvent,
+}
+
+/// Decodes a captured AMQP byte stream (one direction of a connection) into frames,
+/// skipping the protocol header and flagging malformed data with its offset.
+///
+/// ```
+/// use amq_proto::{Method, StreamDecoder, StreamEvent};
+/// use amq_proto::protocol::basic;
+///
+/// let mut data = b"AMQP\x00\x00\x09\x01".to_vec();
+/// data.extend(basic::Ack { delivery_tag: 1, multiple: false }.to_frame(1).unwrap().encode().unwrap());
+/// let items: Vec<_> = StreamDecoder::new(&data).collect();
+/// assert_eq!(items[1].offset, 8);
+/// match items[1].event {
+///     StreamEvent::Frame(ref frame) => assert_eq!(frame.to_string(), "ch=1 METHOD basic.ack{delivery_tag=1, multiple=false}"),
+///     _ => panic!("expected a frame"),
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct StreamDecoder<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> StreamDecoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StreamDecoder { data, offset: 0 }
+    }
+
+    fn frame_at(&self, offset: usize) -> ::std::result::Result<Frame, String> {
+        let data = &self.data[offset..];
+        if data.len() < FRAME_OVERHEAD - 1 {
+            return Err(format!("truncated frame header: {} bytes", data.len()));
+        }
+        let frame_type = FrameType::from_u8(data[0])
+            .ok_or_else(|| format!("unknown frame type {}", data[0]))?;
+        let channel = BigEndian::read_u16(&data[1..3]);
+        let size = BigEndian::read_u32(&data[3..7]) as usize;
+        if data.len() < size + FRAME_OVERHEAD {
+            return Err(format!("truncated frame: {} bytes of {}",
+                               data.len(),
+                               size + FRAME_OVERHEAD));
+        }
+        if data[size + FRAME_OVERHEAD - 1] != 0xCE {
+            return Err(format!("frame end is {:#04x} instead of 0xce",
+                               data[size + FRAME_OVERHEAD - 1]));
+        }
+        Ok(Frame {
+            frame_type,
+            channel,
+            payload: FramePayload::new(data[7..7 + size].to_vec()),
+        })
+    }
+}
+
+impl<'a> Iterator for StreamDecoder<'a> {
+    type Item = StreamItem;
+
+    fn next(&mut self) -> Option<StreamItem> {
+        if self.offset >= self.data.len() {
+            return None;
+        }
+        let offset = self.offset;
+        if self.data[offset..].starts_with(b"AMQP") {
+            if let Ok(header) = ProtocolHeader::decode(&mut &self.data[offset..]) {
+                self.offset += 8;
+                return Some(StreamItem {
+                    offset,
+                    event: StreamEvent::ProtocolHeader(header),
+                });
+            }
+        }
+        let event = match self.frame_at(offset) {
+            Ok(frame) => {
+                self.offset += frame.payload.inner().len() + FRAME_OVERHEAD;
+                StreamEvent::Frame(frame)
+            }
+            Err(reason) => {
+                // Resynchronise on the next offset holding a complete, valid frame.
+                self.offset = (offset + 1..self.data.len())
+                    .find(|&next| self.frame_at(next).is_ok())
+                    .unwrap_or(self.data.len());
+                StreamEvent::Malformed(format!("{} ({} bytes skipped)", reason, self.offset - offset))
+            }
+        };
+        Some(StreamItem { offset, event })
+    }
+}
+
+/// Parses a hex dump: pairs of hex digits, optionally separated by whitespace or `:`,
+/// with optional `0x` prefixes and `#` comments up to the end of the line.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    let mut digits = vec![];
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        for word in line.split(|c: char| c.is_whitespace() || c == ':' || c == ',') {
+            for c in word.trim_start_matches("0x").trim_start_matches("0X").chars() {
+                let digit = c.to_digit(16)
+                    .ok_or_else(|| ErrorKind::Protocol(format!("invalid hex digit '{}'", c)))?;
+                digits.push(digit as u8);
+            }
+        }
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(ErrorKind::Protocol("odd number of hex digits".to_string()).into());
+    }
+    Ok(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+}
+
+impl StreamItem {
+    /// Renders the item as one line of a trace, e.g.
+    /// `00000008 ch=1 METHOD basic.ack{delivery_tag=1, multiple=false}`.
+    pub fn to_text(&self, body_limit: Option<usize>) -> String {
+        match self.event {
+            StreamEvent::ProtocolHeader(ref header) => {
+                format!("{:08x} PROTOCOL-HEADER {}-{}-{}-{}",
+                        self.offset,
+                        header.protocol_id,
+                        header.major,
+                        header.minor,
+                        header.revision)
+            }
+            StreamEvent::Frame(ref frame) => {
+                let display = match body_limit {
+                    Some(limit) => frame.display().body_limit(limit),
+                    None => frame.display().full_body(),
+                };
+                format!("{:08x} {}", self.offset, display)
+            }
+            StreamEvent::Malformed(ref reason) => format!("{:08x} MALFORMED {}", self.offset, reason),
+        }
+    }
+
+    /// Renders the item as a single line JSON object.
+    pub fn to_json(&self, body_limit: Option<usize>) -> String {
+        let mut object = JsonObject::new();
+        self.write_json(&mut object, body_limit);
+        object.finish()
+    }
+
+    /// Adds the fields of `to_json` to an object, e.g. after fields of the capture.
+    pub(crate) fn write_json(&self, object: &mut JsonObject, body_limit: Option<usize>) {
+        object.value("offset", self.offset);
+        match self.event {
+            StreamEvent::ProtocolHeader(ref header) => {
+                object.string("type", "PROTOCOL-HEADER")
+                    .string("version",
+                            &format!("{}-{}-{}-{}",
+                                     header.protocol_id,
+                                     header.major,
+                                     header.minor,
+                                     header.revision));
+            }
+            StreamEvent::Frame(ref frame) => {
+                object.string("type", &format!("{:?}", frame.frame_type))
+                    .value("channel", frame.channel)
+                    .value("size", frame.payload.inner().len());
+                match frame.frame_type {
+                    FrameType::METHOD => {
+                        if let Ok(method_frame) = MethodFrame::decode(frame) {
+                            object.value("class_id", method_frame.class_id)
+                                .value("method_id", method_frame.method_id)
+                                .string("method", method_frame.method_name());
+                        }
+                    }
+                    FrameType::HEADERS => {
+                        if let Ok(header) = ContentHeaderFrame::decode(frame) {
+                            object.value("class_id", header.content_class)
+                                .value("body_size", header.body_size);
+                        }
+                    }
+                    FrameType::BODY | FrameType::HEARTBEAT => {}
+                }
+                let display = match body_limit {
+                    Some(limit) => frame.display().body_limit(limit),
+                    None => frame.display().full_body(),
+                };
+                object.string("text", &display.to_string());
+            }
+            StreamEvent::Malformed(ref reason) => {
+                object.string("type", "MALFORMED").string("error", reason);
+            }
+        }
+    }
+}
+
+/// A JSON object written one field at a time, for the output of `amq-proto-dump`.
+pub(crate) struct JsonObject {
+    json: String,
+}
+
+impl JsonObject {
+    pub fn new() -> Self {
+        JsonObject { json: String::from("{") }
+    }
+
+    fn name(&mut self, name: &str) {
+        if self.json.len() > 1 {
+            self.json.push(',');
+        }
+        self.json.push_str(&json_string(name));
+        self.json.push(':');
+    }
+
+    /// A number, or anything else which displays as JSON.
+    pub fn value<T: fmt::Display>(&mut self, name: &str, value: T) -> &mut Self {
+        self.name(name);
+        let _ = write!(self.json, "{}", value);
+        self
+    }
+
+    pub fn string(&mut self, name: &str, value: &str) -> &mut Self {
+        self.name(name);
+        self.json.push_str(&json_string(value));
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        self.json.push('}');
+        self.json
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod test {
+    use crate::method::Method;
+    use crate::protocol::basic;
+    use super::*;
+
+    fn ack_frame() -> Vec<u8> {
+        basic::Ack {
+                delivery_tag: 1,
+                multiple: false,
+            }
+            .to_frame(1)
+            .unwrap()
+            .encode()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_decode_stream() {
+        let mut data = ProtocolHeader::default().encode().to_vec();
+        data.extend(ack_frame());
+        let items: Vec<_> = StreamDecoder::new(&data).collect();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].to_text(None), "00000000 PROTOCOL-HEADER 0-0-9-1");
+        assert_eq!(items[1].to_text(None),
+                   "00000008 ch=1 METHOD basic.ack{delivery_tag=1, multiple=false}");
+        assert_eq!(items[1].to_json(None),
+                   "{\"offset\":8,\"type\":\"METHOD\",\"channel\":1,\"size\":13,\"class_id\":60,\
+                    \"method_id\":80,\"method\":\"basic.ack\",\"text\":\"ch=1 METHOD \
+                    basic.ack{delivery_tag=1, multiple=false}\"}");
+    }
+
+    #[test]
+    fn test_malformed() {
+        let mut data = ack_frame();
+        let frame_len = data.len();
+        // A bad frame end, then garbage, then a valid frame, then a truncated one.
+        data[frame_len - 1] = 0;
+        data.extend(&[9, 9, 9]);
+        data.extend(ack_frame());
+        data.extend(&ack_frame()[..10]);
+        let items: Vec<_> = StreamDecoder::new(&data).collect();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].event,
+                   StreamEvent::Malformed(format!("frame end is 0x00 instead of 0xce ({} bytes \
+                                                   skipped)",
+                                                  frame_len + 3)));
+        assert_eq!(items[1].offset, frame_len + 3);
+        assert!(matches!(items[1].event, StreamEvent::Frame(_)));
+        assert_eq!(items[2].offset, 2 * frame_len + 3);
+        assert_eq!(items[2].to_json(None),
+                   format!("{{\"offset\":{},\"type\":\"MALFORMED\",\"error\":\"truncated frame: \
+                            10 bytes of 21 (10 bytes skipped)\"}}",
+                           2 * frame_len + 3));
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("41 4d:51 # comment\n0x50 0001\n").unwrap(),
+                   b"AMQP\x00\x01".to_vec());
+        assert!(parse_hex("414").is_err());
+        assert!(parse_hex("4g").is_err());
+        // Signs and multi-byte characters are not digits.
+        assert!(parse_hex("+1").is_err());
+        assert!(parse_hex("aé1").is_err());
+        assert_eq!(json_string("a\"\n\u{1}"), "\"a\\\"\\n\\u0001\"");
+    }
+}
@@ -0,0 +1,411 @@
+//! Decoding and encoding methods by a field type list known at runtime, for proxies
+//! and analyzers handling methods without compiled structs.
+//!
+//! ```
+//! use amq_proto::MethodFrame;
+//! use amq_proto::dynamic::{MethodSchema, Schema, Value};
+//! use amq_proto::reflection::WireType;
+//!
+//! let mut schema = Schema::builtin();
+//! // A method of a newer broker version.
+//! schema.insert(MethodSchema::new(60, 200, "basic.boost")
+//!     .field("delivery_tag", WireType::LongLong)
+//!     .field("level", WireType::Octet));
+//! let method = schema.method_by_name("basic.boost").unwrap();
+//! let frame = method.encode(&[("delivery_tag".to_string(), Value::LongLong(1)),
+//!                             ("level".to_string(), Value::Octet(3))]).unwrap();
+//! let decoded = schema.decode(&frame).unwrap();
+//! assert_eq!(decoded.to_string(), "basic.boost{delivery_tag=1, level=3}");
+//! assert_eq!(decoded.get("level"), Some(&Value::Octet(3)));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::codegen_macros::{ArgumentsReader, ArgumentsWriter};
+use crate::display::{DisplayArgument, FieldsDisplay};
+use crate::error::*;
+use crate::framing::{Frame, FrameType, MethodFrame};
+use crate::method::EncodedMethod;
+use crate::reflection::{self, MethodInfo, WireType};
+use crate::table::Table;
+
+/// The value of a method argument.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bit(bool),
+    Octet(u8),
+    Short(u16),
+    Long(u32),
+    LongLong(u64),
+    ShortStr(String),
+    LongStr(String),
+    Table(Table),
+    Timestamp(u64),
+}
+
+impl Value {
+    pub fn wire_type(&self) -> WireType {
+        match *self {
+            Value::Bit(_) => WireType::Bit,
+            Value::Octet(_) => WireType::Octet,
+            Value::Short(_) => WireType::Short,
+            Value::Long(_) => WireType::Long,
+            Value::LongLong(_) => WireType::LongLong,
+            Value::ShortStr(_) => WireType::ShortStr,
+            Value::LongStr(_) => WireType::LongStr,
+            Value::Table(_) => WireType::Table,
+            Value::Timestamp(_) => WireType::Timestamp,
+        }
+    }
+
+    fn read(reader: &mut ArgumentsReader, wire_type: WireType) -> Result<Value> {
+        Ok(match wire_type {
+            WireType::Bit => Value::Bit(reader.read_bit()?),
+            WireType::Octet => Value::Octet(reader.read_octet()?),
+            WireType::Short => Value::Short(reader.read_short()?),
+            WireType::Long => Value::Long(reader.read_long()?),
+            WireType::LongLong => Value::LongLong(reader.read_longlong()?),
+            WireType::ShortStr => Value::ShortStr(reader.read_shortstr()?),
+            WireType::LongStr => Value::LongStr(reader.read_longstr()?),
+            WireType::Table => Value::Table(reader.read_table()?),
+            WireType::Timestamp => Value::Timestamp(reader.read_timestamp()?),
+        })
+    }
+
+    fn write(&self, writer: &mut ArgumentsWriter) -> Result<()> {
+        match *self {
+            Value::Bit(ref value) => writer.write_bit(value),
+            Value::Octet(ref value) => writer.write_octet(value),
+            Value::Short(ref value) => writer.write_short(value),
+            Value::Long(ref value) => writer.write_long(value),
+            Value::LongLong(ref value) => writer.write_longlong(value),
+            Value::ShortStr(ref value) => {
+                if value.len() > u8::MAX as usize {
+                    return Err(ErrorKind::Protocol(format!("shortstr of {} bytes", value.len()))
+                        .into());
+                }
+                writer.write_shortstr(value)
+            }
+            Value::LongStr(ref value) => writer.write_longstr(value),
+            Value::Table(ref value) => writer.write_table(value),
+            Value::Timestamp(ref value) => writer.write_timestamp(value),
+        }
+    }
+}
+
+impl DisplayArgument for Value {
+    fn fmt_argument(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Bit(ref value) => value.fmt_argument(f),
+            Value::Octet(ref value) => value.fmt_argument(f),
+            Value::Short(ref value) => value.fmt_argument(f),
+            Value::Long(ref value) => value.fmt_argument(f),
+            Value::LongLong(ref value) | Value::Timestamp(ref value) => value.fmt_argument(f),
+            Value::ShortStr(ref value) | Value::LongStr(ref value) => value.fmt_argument(f),
+            Value::Table(ref value) => value.fmt_argument(f),
+        }
+    }
+}
+
+/// The arguments of a method, by name and wire type in wire order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodSchema {
+    pub class_id: u16,
+    pub method_id: u16,
+    /// The full name, e.g. `basic.publish`.
+    pub name: String,
+    pub fields: Vec<(String, WireType)>,
+}
+
+impl MethodSchema {
+    pub fn new(class_id: u16, method_id: u16, name: &str) -> MethodSchema {
+        MethodSchema {
+            class_id,
+            method_id,
+            name: name.to_string(),
+            fields: vec![],
+        }
+    }
+
+    /// Appends an argument.
+    pub fn field(mut self, name: &str, wire_type: WireType) -> MethodSchema {
+        self.fields.push((name.to_string(), wire_type));
+        self
+    }
+
+    /// Decodes the arguments of a method frame, which must hold exactly the
+    /// arguments of the schema.
+    pub fn decode(&self, method_frame: &MethodFrame) -> Result<Vec<(String, Value)>> {
+        if (method_frame.class_id, method_frame.method_id) != (self.class_id, self.method_id) {
+            return Err(ErrorKind::Protocol(format!("Expected {}, got class {} method {}",
+                                                   self.name,
+                                                   method_frame.class_id,
+                                                   method_frame.method_id))
+                .into());
+        }
+        let mut reader = ArgumentsReader::new(method_frame.arguments.inner());
+        let values = self.fields
+            .iter()
+            .map(|&(ref name, wire_type)| Ok((name.clone(), Value::read(&mut reader, wire_type)?)))
+            .collect::<Result<Vec<_>>>()?;
+        if reader.remaining() > 0 {
+            return Err(ErrorKind::Protocol(format!("{} trailing bytes after the arguments of {}",
+                                                   reader.remaining(),
+                                                   self.name))
+                .into());
+        }
+        Ok(values)
+    }
+
+    /// Encodes arguments, which must match the names and wire types of the schema.
+    pub fn encode(&self, values: &[(String, Value)]) -> Result<MethodFrame> {
+        if values.len() != self.fields.len() {
+            return Err(ErrorKind::Protocol(format!("{} has {} arguments, got {}",
+                                                   self.name,
+                                                   self.fields.len(),
+                                                   values.len()))
+                .into());
+        }
+        let mut writer = ArgumentsWriter::new();
+        for (&(ref name, wire_type), (value_name, value)) in self.fields.iter().zip(values) {
+            if name != value_name || value.wire_type() != wire_type {
+                return Err(ErrorKind::Protocol(format!("Expected {}: {} in {}, got {}: {}",
+                                                       name,
+                                                       wire_type,
+                                                       self.name,
+                                                       value_name,
+                                                       value.wire_type()))
+                    .into());
+            }
+            value.write(&mut writer)?;
+        }
+        Ok(MethodFrame {
+            class_id: self.class_id,
+            method_id: self.method_id,
+            arguments: EncodedMethod::new(writer.as_bytes()),
+        })
+    }
+}
+
+impl<'a> From<&'a MethodInfo> for MethodSchema {
+    fn from(info: &'a MethodInfo) -> MethodSchema {
+        MethodSchema {
+            class_id: info.class_id,
+            method_id: info.id,
+            name: info.full_name.to_string(),
+            fields: info.fields.iter().map(|field| (field.name.to_string(), field.wire_type)).collect(),
+        }
+    }
+}
+
+/// A set of method schemas, looked up by class and method id.
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    methods: HashMap<(u16, u16), MethodSchema>,
+}
+
+impl Schema {
+    pub fn new() -> Schema {
+        Schema::default()
+    }
+
+    /// The methods of the compiled spec.
+    pub fn builtin() -> Schema {
+        let mut schema = Schema::new();
+        for class in reflection::CLASSES {
+            for method in class.methods {
+                schema.insert(method.into());
+            }
+        }
+        schema
+    }
+
+    /// Adds a method, replacing any method with the same ids.
+    pub fn insert(&mut self, method: MethodSchema) {
+        self.methods.insert((method.class_id, method.method_id), method);
+    }
+
+    /// The methods of a spec JSON file in the format `amq-proto-codegen` reads.
+    #[cfg(feature = "spec")]
+    pub fn from_spec_json(json: &str) -> Result<Schema> {
+        let spec = amq_proto_codegen::Spec::from_json(json)
+            .map_err(|err| ErrorKind::InvalidSpec(err.to_string()))?;
+        Schema::from_spec(&spec)
+    }
+
+    /// The methods of a parsed spec, e.g. one with extensions merged in.
+    #[cfg(feature = "spec")]
+    pub fn from_spec(spec: &amq_proto_codegen::Spec) -> Result<Schema> {
+        spec.validate().map_err(|err| ErrorKind::InvalidSpec(err.to_string()))?;
+        let mut schema = Schema::new();
+        for class in &spec.classes {
+            for method in &class.methods {
+                let mut method_schema = MethodSchema::new(class.id,
+                                                          method.id,
+                                                          &format!("{}.{}", class.name, method.name));
+                for argument in &method.arguments {
+                    let ty = spec.argument_type(argument)
+                        .map_err(|err| ErrorKind::InvalidSpec(err.to_string()))?;
+                    let wire_type = WireType::from_name(ty).expect("validated argument type");
+                    method_schema = method_schema.field(&amq_proto_codegen::snake_name(&argument.name),
+                                                        wire_type);
+                }
+                schema.insert(method_schema);
+            }
+        }
+        Ok(schema)
+    }
+
+    pub fn method(&self, class_id: u16, method_id: u16) -> Option<&MethodSchema> {
+        self.methods.get(&(class_id, method_id))
+    }
+
+    pub fn method_by_name(&self, name: &str) -> Option<&MethodSchema> {
+        self.methods.values().find(|method| method.name == name)
+    }
+
+    pub fn decode(&self, method_frame: &MethodFrame) -> Result<DynamicMethod> {
+        let method = self.method(method_frame.class_id, method_frame.method_id)
+            .ok_or_else(|| {
+                Error::from(ErrorKind::Protocol(format!("Unknown method class {} method {}",
+                                                        method_frame.class_id,
+                                                        method_frame.method_id)))
+            })?;
+        Ok(DynamicMethod {
+            class_id: method.class_id,
+            method_id: method.method_id,
+            name: method.name.clone(),
+            fields: method.decode(method_frame)?,
+        })
+    }
+}
+
+/// A method decoded by a `Schema`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynamicMethod {
+    pub class_id: u16,
+    pub method_id: u16,
+    pub name: String,
+    pub fields: Vec<(String, Value)>,
+}
+
+impl DynamicMethod {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.iter().find(|field| field.0 == name).map(|field| &field.1)
+    }
+
+    /// Encodes the fields in their order, without checking them against a schema.
+    pub fn encode(&self) -> Result<MethodFrame> {
+        let mut writer = ArgumentsWriter::new();
+        for (_, value) in &self.fields {
+            value.write(&mut writer)?;
+        }
+        Ok(MethodFrame {
+            class_id: self.class_id,
+            method_id: self.method_id,
+            arguments: EncodedMethod::new(writer.as_bytes()),
+        })
+    }
+
+    pub fn to_frame(&self, channel: u16) -> Result<Frame> {
+        Ok(Frame {
+            frame_type: FrameType::METHOD,
+            channel,
+            payload: self.encode()?.encode()?,
+        })
+    }
+}
+
+/// Renders like the generated method structs, e.g. `basic.ack{delivery_tag=1, multiple=false}`.
+impl fmt::Display for DynamicMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.fields.is_empty() {
+            return f.write_str(&self.name);
+        }
+        let mut fields = FieldsDisplay::new(f, &self.name);
+        for (name, value) in &self.fields {
+            fields.field(name, value);
+        }
+        fields.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::method::Method;
+    use crate::protocol::{basic, queue};
+    use crate::table::TableEntry;
+    use super::*;
+
+    #[test]
+    fn test_decode_matches_the_structs() {
+        let mut arguments = Table::new();
+        arguments.insert("x-max-length".to_string(), TableEntry::LongInt(10));
+        let declare = queue::Declare {
+            queue: "q".to_string(),
+            durable: true,
+            auto_delete: true,
+            arguments,
+            ..Default::default()
+        };
+        let frame = MethodFrame::decode(&declare.to_frame(1).unwrap()).unwrap();
+        let schema = Schema::builtin();
+        let decoded = schema.decode(&frame).unwrap();
+        assert_eq!(decoded.to_string(), declare.to_string());
+        assert_eq!(decoded.get("durable"), Some(&Value::Bit(true)));
+        assert_eq!(decoded.fields[0], ("ticket".to_string(), Value::Short(0)));
+        assert_eq!(decoded.encode().unwrap(), frame);
+        let method = schema.method(queue::Declare::CLASS_ID, queue::Declare::ID).unwrap();
+        assert_eq!(method.encode(&decoded.fields).unwrap(), frame);
+
+        let ack = basic::Ack { delivery_tag: 1, multiple: false }.to_frame(1).unwrap();
+        let decoded = schema.decode(&MethodFrame::decode(&ack).unwrap()).unwrap();
+        assert_eq!(decoded.to_frame(1).unwrap(), ack);
+    }
+
+    #[test]
+    fn test_schema_mismatches() {
+        let schema = Schema::builtin();
+        let ack = schema.method_by_name("basic.ack").unwrap();
+        assert!(ack.encode(&[("delivery_tag".to_string(), Value::LongLong(1))]).is_err());
+        assert!(ack.encode(&[("delivery_tag".to_string(), Value::Long(1)),
+                             ("multiple".to_string(), Value::Bit(false))])
+            .is_err());
+        let mut frame = ack.encode(&[("delivery_tag".to_string(), Value::LongLong(1)),
+                                     ("multiple".to_string(), Value::Bit(false))])
+            .unwrap();
+        let mut arguments = frame.arguments.into_inner();
+        arguments.push(0);
+        frame.arguments = EncodedMethod::new(arguments);
+        assert!(schema.decode(&frame).is_err());
+        frame.method_id = 999;
+        assert!(schema.decode(&frame).is_err());
+    }
+
+    #[cfg(feature = "spec")]
+    #[test]
+    fn test_from_spec_json() {
+        let schema = Schema::from_spec_json(include_str!("../tests/fixtures/stream-spec.json")).unwrap();
+        let seek = schema.method_by_name("stream.seek").unwrap();
+        assert_eq!(seek.fields,
+                   vec![("queue".to_string(), WireType::ShortStr),
+                        ("offset".to_string(), WireType::LongLong),
+                        ("no_wait".to_string(), WireType::Bit),
+                        ("arguments".to_string(), WireType::Table)]);
+        let frame = seek.encode(&[("queue".to_string(), Value::ShortStr("events".to_string())),
+                                  ("offset".to_string(), Value::LongLong(7)),
+                                  ("no_wait".to_string(), Value::Bit(true)),
+                                  ("arguments".to_string(), Value::Table(Table::new()))])
+            .unwrap();
+        let decoded = schema.decode(&frame).unwrap();
+        assert_eq!(decoded.to_string(), "stream.seek{queue=\"events\", offset=7, no_wait=true, arguments={}}");
+
+        // The builtin spec gives the same schema as the compiled structs.
+        let builtin = Schema::from_spec_json(include_str!("../amqp-rabbitmq-0.9.1.json")).unwrap();
+        let declare = builtin.method_by_name("queue.declare").unwrap();
+        assert_eq!(declare, Schema::builtin().method_by_name("queue.declare").unwrap());
+
+        assert!(Schema::from_spec_json("{}").is_err());
+    }
+}
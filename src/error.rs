@@ -4,6 +4,50 @@ error_chain! {
             description("protocol error")
             display("protocol error: '{}'", t)
         }
+        Authentication(t: String) {
+            description("authentication error")
+            display("authentication error: '{}'", t)
+        }
+        InvalidUri(t: String) {
+            description("invalid AMQP URI")
+            display("invalid AMQP URI: '{}'", t)
+        }
+        InvalidTopicPattern(t: String) {
+            description("invalid topic pattern")
+            display("invalid topic pattern: '{}'", t)
+        }
+        InvalidBindingArguments(t: String) {
+            description("invalid binding arguments")
+            display("invalid binding arguments: '{}'", t)
+        }
+        LimitExceeded(t: String) {
+            description("decode limit exceeded")
+            display("decode limit exceeded: {}", t)
+        }
+        InvalidProperty(t: String) {
+            description("invalid property")
+            display("invalid property: '{}'", t)
+        }
+        InvalidCapture(t: String) {
+            description("invalid capture")
+            display("invalid capture: '{}'", t)
+        }
+        InvalidSpec(t: String) {
+            description("invalid spec")
+            display("invalid spec: {}", t)
+        }
+        ReplayDiverged(t: String) {
+            description("replay diverged from the recording")
+            display("replay diverged from the recording: {}", t)
+        }
+        ConnectionClosed(code: u16, text: String) {
+            description("connection closed")
+            display("connection closed: {} '{}'", code, text)
+        }
+        ChannelClosed(code: u16, text: String) {
+            description("channel closed")
+            display("channel closed: {} '{}'", code, text)
+        }
     }
 
     foreign_links {
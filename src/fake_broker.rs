@@ -0,0 +1,124 @@
+use std::io::{BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::content::{encode_content, ContentAssembler, Incoming};
+use crate::framing::{Frame, FrameType, ProtocolHeader};
+use crate::method::Method;
+use crate::protocol::{channel, connection};
+use crate::protocol::basic::BasicProperties;
+use crate::table::{Table, TableEntry};
+
+/// Plays the broker side of a conversation with the async or the blocking
+/// client, asserting on every client method.
+pub(crate) struct FakeBroker {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    assembler: ContentAssembler,
+}
+
+impl FakeBroker {
+    /// Accepts a single connection on a local port and runs `script` on it in a thread.
+    pub fn spawn<F>(script: F) -> (SocketAddr, JoinHandle<()>)
+        where F: FnOnce(&mut FakeBroker) + Send + 'static
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            // Fails the test instead of hanging if the client never sends what `script` expects.
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut broker = FakeBroker {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+                assembler: ContentAssembler::new(),
+            };
+            script(&mut broker);
+        });
+        (address, handle)
+    }
+
+    pub fn read_protocol_header(&mut self) {
+        let mut header = [0u8; 8];
+        self.reader.read_exact(&mut header).unwrap();
+        assert_eq!(header, ProtocolHeader::default().encode());
+    }
+
+    pub fn read_frame(&mut self) -> Frame {
+        Frame::decode(&mut self.reader).unwrap()
+    }
+
+    pub fn recv(&mut self) -> (u16, Incoming) {
+        loop {
+            let frame = self.read_frame();
+            if frame.frame_type == FrameType::HEARTBEAT {
+                continue;
+            }
+            if let Some(incoming) = self.assembler.push(&frame).unwrap() {
+                return (frame.channel, incoming);
+            }
+        }
+    }
+
+    pub fn expect<M: Method>(&mut self) -> (u16, M) {
+        match self.recv() {
+            (channel, Incoming::Method(method_frame)) => (channel, M::decode(method_frame).unwrap()),
+            (_, other) => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    pub fn send<M: Method>(&mut self, channel: u16, method: &M) {
+        self.writer.write_all(&method.to_frame(channel).unwrap().encode().unwrap()).unwrap();
+    }
+
+    pub fn send_content<M: Method>(&mut self, channel: u16, method: &M, body: &[u8]) {
+        for frame in encode_content(channel, method, &BasicProperties::default(), body, 0)
+            .unwrap() {
+            self.writer.write_all(&frame.encode().unwrap()).unwrap();
+        }
+    }
+
+    /// Accepts `user:pass` with PLAIN, returning the client's `connection.open`.
+    pub fn handshake(&mut self, heartbeat: u16) -> connection::Open {
+        self.read_protocol_header();
+        let mut capabilities = Table::new();
+        capabilities.insert("consumer_cancel_notify".to_string(), TableEntry::Bool(true));
+        let mut server_properties = Table::new();
+        server_properties.insert("capabilities".to_string(),
+                                 TableEntry::FieldTable(capabilities));
+        self.send(0,
+                  &connection::Start {
+                      version_major: 0,
+                      version_minor: 9,
+                      server_properties,
+                      mechanisms: "AMQPLAIN PLAIN".to_string(),
+                      locales: "en_US".to_string(),
+                  });
+        let (_, start_ok): (_, connection::StartOk) = self.expect();
+        assert_eq!(start_ok.mechanism, "PLAIN");
+        assert_eq!(start_ok.response, "\0user\0pass");
+        self.send(0,
+                  &connection::Tune {
+                      channel_max: 10,
+                      frame_max: 4096,
+                      heartbeat,
+                  });
+        let (_, tune_ok): (_, connection::TuneOk) = self.expect();
+        assert_eq!(tune_ok.frame_max, 4096);
+        let (_, open): (_, connection::Open) = self.expect();
+        self.send(0, &connection::OpenOk { known_hosts: "".to_string() });
+        open
+    }
+
+    pub fn open_channel(&mut self) -> u16 {
+        let (id, _): (_, channel::Open) = self.expect();
+        self.send(id, &channel::OpenOk { channel_id: "".to_string() });
+        id
+    }
+
+    pub fn close(&mut self) {
+        let (_, _): (_, connection::Close) = self.expect();
+        self.send(0, &connection::CloseOk);
+    }
+}
@@ -1,11 +1,16 @@
-use error::*;
-use std::io::{Read, Write, Cursor};
+use crate::error::*;
+use std::io::{self, Read, Write, Cursor};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use enum_primitive::FromPrimitive;
-use method::EncodedMethod;
+use crate::method::EncodedMethod;
+use crate::display::fmt_decoded;
+use crate::protocol;
+use crate::reflection::{self, MethodInfo};
+use std::fmt;
 
 enum_from_primitive! {
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FrameType {
     METHOD = 1,
     HEADERS = 2,
@@ -50,7 +55,51 @@ impl EncodedProperties {
     }
 }
 
+/// The protocol header a client sends before any frames: `AMQP` followed by
+/// the protocol id and version. A server which doesn't support the requested version
+/// replies with the header of the version it does support and closes the connection.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProtocolHeader {
+    pub protocol_id: u8,
+    pub major: u8,
+    pub minor: u8,
+    pub revision: u8,
+}
+
+impl Default for ProtocolHeader {
+    fn default() -> Self {
+        ProtocolHeader {
+            protocol_id: 0,
+            major: 0,
+            minor: 9,
+            revision: 1,
+        }
+    }
+}
+
+impl ProtocolHeader {
+    pub fn decode<T: Read>(reader: &mut T) -> Result<ProtocolHeader> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != b"AMQP" {
+            return Err(ErrorKind::Protocol("Invalid protocol header".to_string()).into());
+        }
+        Ok(ProtocolHeader {
+            protocol_id: header[4],
+            major: header[5],
+            minor: header[6],
+            revision: header[7],
+        })
+    }
+
+    pub fn encode(&self) -> [u8; 8] {
+        [b'A', b'M', b'Q', b'P', self.protocol_id, self.major, self.minor, self.revision]
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Frame {
     pub frame_type: FrameType,
     pub channel: u16,
@@ -78,7 +127,8 @@ impl FrameHeader {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodFrame {
     pub class_id: u16,
     pub method_id: u16,
@@ -88,22 +138,30 @@ pub struct MethodFrame {
 impl MethodFrame {
     pub fn encode(&self) -> Result<FramePayload> {
         let mut writer = Vec::with_capacity(self.arguments.inner().len() + 4);
-        try!(writer.write_u16::<BigEndian>(self.class_id));
-        try!(writer.write_u16::<BigEndian>(self.method_id));
-        try!(writer.write_all(self.arguments.inner()));
+        writer.write_u16::<BigEndian>(self.class_id)?;
+        writer.write_u16::<BigEndian>(self.method_id)?;
+        writer.write_all(self.arguments.inner())?;
         Ok(FramePayload::new(writer))
     }
 
+    pub fn to_frame(&self, channel: u16) -> Result<Frame> {
+        Ok(Frame {
+            frame_type: FrameType::METHOD,
+            channel,
+            payload: self.encode()?,
+        })
+    }
+
     // We need this method, so we can match on class_id & method_id
     pub fn decode(frame: &Frame) -> Result<MethodFrame> {
         if frame.frame_type != FrameType::METHOD {
             return Err(ErrorKind::Protocol("Not a method frame".to_string()).into());
         }
         let reader = &mut frame.payload.inner();
-        let class_id = try!(reader.read_u16::<BigEndian>());
-        let method_id = try!(reader.read_u16::<BigEndian>());
+        let class_id = reader.read_u16::<BigEndian>()?;
+        let method_id = reader.read_u16::<BigEndian>()?;
         let mut arguments = vec![];
-        try!(reader.read_to_end(&mut arguments));
+        reader.read_to_end(&mut arguments)?;
         Ok(MethodFrame {
             class_id: class_id,
             method_id: method_id,
@@ -118,67 +176,283 @@ impl MethodFrame {
     pub fn carries_content(&self) -> bool {
         method_carries_content(self)
     }
+
+    /// The metadata of the method, `None` for unknown methods.
+    pub fn info(&self) -> Option<&'static MethodInfo> {
+        reflection::method(self.class_id, self.method_id)
+    }
+
+    /// Whether the method expects a reply, false for unknown methods.
+    pub fn is_synchronous(&self) -> bool {
+        method_is_synchronous(self)
+    }
 }
-include!("method_frame_methods.rs");
+
+/// Renders the decoded method, e.g. `basic.ack{delivery_tag=1, multiple=false}`.
+impl fmt::Display for MethodFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_method(self, f)
+    }
+}
+include!(concat!(env!("OUT_DIR"), "/method_frame_methods.rs"));
 
 
 unsafe impl Send for Frame {}
 
-impl Frame {
-    pub fn decode<T: Read>(reader: &mut T) -> Result<Frame> {
+/// How decoders treat frame types and methods the spec doesn't define.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeMode {
+    /// Fail, as a client or broker should.
+    #[default]
+    Strict,
+    /// Keep them as `RawFrame`s and `AnyMethod::Unknown`, which re-encode byte for byte,
+    /// e.g. for proxies forwarding traffic of newer protocol versions.
+    Lenient,
+}
+
+/// Bounds on what decoders accept from a peer, so a broken or hostile peer can't make
+/// them allocate unbounded memory or recurse without end.
+///
+/// The defaults are generous enough for any sane traffic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// The largest frame payload in bytes.
+    pub max_frame_size: u32,
+    /// How deep tables and arrays may be nested, the outermost table being 1.
+    pub max_table_depth: usize,
+    /// The most entries a single table or array may have.
+    pub max_table_entries: usize,
+    /// The most bytes of tables and strings decoded for one method or properties, and
+    /// the largest method plus content header of a message.
+    pub max_message_bytes: usize,
+    /// The largest message body in bytes.
+    pub max_body_size: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_frame_size: 128 * 1024 * 1024,
+            max_table_depth: 64,
+            max_table_entries: 65536,
+            max_message_bytes: 128 * 1024 * 1024,
+            max_body_size: 512 * 1024 * 1024,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// No limits besides the 4 GiB the wire format allows.
+    pub fn unlimited() -> Self {
+        DecodeLimits {
+            max_frame_size: u32::MAX,
+            max_table_depth: usize::MAX,
+            max_table_entries: usize::MAX,
+            max_message_bytes: usize::MAX,
+            max_body_size: u64::MAX,
+        }
+    }
+
+    pub(crate) fn check_frame_size(&self, payload_size: u32) -> Result<()> {
+        if payload_size > self.max_frame_size {
+            return Err(ErrorKind::LimitExceeded(format!("frame payload of {} bytes exceeds {}",
+                                                        payload_size,
+                                                        self.max_frame_size))
+                .into());
+        }
+        Ok(())
+    }
+}
+
+/// A frame of a type the spec doesn't define, kept by lenient decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RawFrame {
+    pub frame_type_id: u8,
+    pub channel: u16,
+    pub payload: FramePayload,
+}
+
+impl RawFrame {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        encode_frame(self.frame_type_id, self.channel, &self.payload)
+    }
+}
+
+/// A frame decoded in a `DecodeMode`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AnyFrame {
+    Frame(Frame),
+    /// Only returned in lenient mode.
+    Unknown(RawFrame),
+}
+
+impl AnyFrame {
+    pub fn decode<T: Read>(reader: &mut T, mode: DecodeMode) -> Result<AnyFrame> {
+        AnyFrame::decode_with_limits(reader, mode, &DecodeLimits::default())
+    }
+
+    pub fn decode_with_limits<T: Read>(reader: &mut T,
+                                       mode: DecodeMode,
+                                       limits: &DecodeLimits)
+                                       -> Result<AnyFrame> {
         let mut header = [0u8; 7];
-        try!(reader.read_exact(&mut header));
+        reader.read_exact(&mut header)?;
         let FrameHeader { frame_type_id, channel, payload_size } = FrameHeader::new(header);
-        let size = payload_size as usize;
-        // We need to use Vec because the size is not know in compile time.
-        let mut payload: Vec<u8> = vec![0u8; size];
-        try!(reader.read_exact(&mut payload));
-        let frame_end = try!(reader.read_u8());
+        limits.check_frame_size(payload_size)?;
+        // Grows with the data actually read rather than trusting the size up front.
+        let mut payload = Vec::new();
+        reader.take(u64::from(payload_size)).read_to_end(&mut payload)?;
+        if payload.len() < payload_size as usize {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated frame payload").into());
+        }
+        let frame_end = reader.read_u8()?;
         if frame_end != 0xCE {
             return Err(ErrorKind::Protocol("Frame didn't end with 0xCE".to_string()).into());
         }
-        let frame_type = match FrameType::from_u8(frame_type_id) {
-            Some(ft) => ft,
-            None => return Err(ErrorKind::Protocol("Unknown frame type".to_string()).into()),
-        };
+        let payload = FramePayload::new(payload);
+        match (FrameType::from_u8(frame_type_id), mode) {
+            (Some(frame_type), _) => {
+                Ok(AnyFrame::Frame(Frame {
+                    frame_type,
+                    channel,
+                    payload,
+                }))
+            }
+            (None, DecodeMode::Lenient) => {
+                Ok(AnyFrame::Unknown(RawFrame {
+                    frame_type_id,
+                    channel,
+                    payload,
+                }))
+            }
+            (None, DecodeMode::Strict) => {
+                Err(ErrorKind::Protocol(format!("Unknown frame type {}", frame_type_id)).into())
+            }
+        }
+    }
 
-        let frame = Frame {
-            frame_type: frame_type,
-            channel: channel,
-            payload: FramePayload::new(payload),
-        };
-        Ok(frame)
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        match *self {
+            AnyFrame::Frame(ref frame) => frame.encode(),
+            AnyFrame::Unknown(ref frame) => frame.encode(),
+        }
+    }
+}
+
+fn encode_frame(frame_type_id: u8, channel: u16, payload: &FramePayload) -> Result<Vec<u8>> {
+    let mut writer = Vec::with_capacity(payload.inner().len() + 8);
+    writer.write_u8(frame_type_id)?;
+    writer.write_u16::<BigEndian>(channel)?;
+    writer.write_u32::<BigEndian>(payload.inner().len() as u32)?;
+    writer.write_all(payload.inner())?;
+    writer.write_u8(0xCE)?;
+    Ok(writer)
+}
+
+impl Frame {
+    /// Decodes a frame in strict mode, failing on unknown frame types.
+    pub fn decode<T: Read>(reader: &mut T) -> Result<Frame> {
+        Frame::decode_with_limits(reader, &DecodeLimits::default())
+    }
+
+    pub fn decode_with_limits<T: Read>(reader: &mut T, limits: &DecodeLimits) -> Result<Frame> {
+        match AnyFrame::decode_with_limits(reader, DecodeMode::Strict, limits)? {
+            AnyFrame::Frame(frame) => Ok(frame),
+            AnyFrame::Unknown(_) => unreachable!("strict decoding rejects unknown frame types"),
+        }
     }
 
     pub fn encode(&self) -> Result<Vec<u8>> {
-        let mut writer = Vec::with_capacity(self.payload.inner().len() + 8);
-        try!(writer.write_u8(self.frame_type as u8));
-        try!(writer.write_u16::<BigEndian>(self.channel));
-        try!(writer.write_u32::<BigEndian>(self.payload.inner().len() as u32));
-        try!(writer.write_all(self.payload.inner()));
-        try!(writer.write_u8(0xCE));
-        Ok(writer)
+        encode_frame(self.frame_type as u8, self.channel, &self.payload)
     }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ContentHeaderFrame {
     pub content_class: u16,
     pub weight: u16,
     pub body_size: u64,
-    pub properties_flags: u16,
+    /// The property flag words. Bit 15 of the first word flags the first property,
+    /// bit 0 of each word flags that another word follows. A single `u16` before flag
+    /// continuation words were supported.
+    pub properties_flags: Vec<u16>,
     pub properties: EncodedProperties,
 }
 
+/// The number of properties flagged by one flag word, the last bit being the continuation flag.
+const PROPERTIES_PER_FLAG_WORD: usize = 15;
+
+/// Whether the property at `idx` is flagged.
+pub fn property_flag(flags: &[u16], idx: usize) -> bool {
+    flags.get(idx / PROPERTIES_PER_FLAG_WORD)
+        .is_some_and(|word| word & (0x8000 >> (idx % PROPERTIES_PER_FLAG_WORD)) != 0)
+}
+
+/// Flags the property at `idx`, adding flag words as needed.
+pub fn set_property_flag(flags: &mut Vec<u16>, idx: usize) {
+    let word = idx / PROPERTIES_PER_FLAG_WORD;
+    if flags.len() <= word {
+        flags.resize(word + 1, 0);
+    }
+    flags[word] |= 0x8000 >> (idx % PROPERTIES_PER_FLAG_WORD);
+}
+
+/// The flags of the properties from `idx` on, without continuation bits.
+pub fn property_flags_from(flags: &[u16], idx: usize) -> Vec<u16> {
+    let mut rest = vec![0; flags.len()];
+    for (word, flag_word) in rest.iter_mut().enumerate() {
+        for bit in 0..PROPERTIES_PER_FLAG_WORD {
+            let property = word * PROPERTIES_PER_FLAG_WORD + bit;
+            if property >= idx && property_flag(flags, property) {
+                *flag_word |= 0x8000 >> bit;
+            }
+        }
+    }
+    rest
+}
+
+/// Sets the continuation bit on every flag word but the last, which always exists.
+pub fn finish_property_flags(mut flags: Vec<u16>) -> Vec<u16> {
+    if flags.is_empty() {
+        flags.push(0);
+    }
+    let last = flags.len() - 1;
+    for (idx, word) in flags.iter_mut().enumerate() {
+        if idx == last {
+            *word &= !1;
+        } else {
+            *word |= 1;
+        }
+    }
+    flags
+}
+
+/// Properties after the ones the class defines, kept by lenient decoding so they
+/// re-encode unchanged.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnknownProperties {
+    /// The flag words, with only the unknown properties flagged and without continuation bits.
+    pub flags: Vec<u16>,
+    /// Everything after the known properties.
+    pub data: Vec<u8>,
+}
+
 impl ContentHeaderFrame {
     pub fn decode(frame: &Frame) -> Result<ContentHeaderFrame> {
         let mut reader = Cursor::new(frame.payload.inner());
-        let content_class = try!(reader.read_u16::<BigEndian>());
-        let weight = try!(reader.read_u16::<BigEndian>()); //0 all the time for now
-        let body_size = try!(reader.read_u64::<BigEndian>());
-        let properties_flags = try!(reader.read_u16::<BigEndian>());
+        let content_class = reader.read_u16::<BigEndian>()?;
+        let weight = reader.read_u16::<BigEndian>()?; //0 all the time for now
+        let body_size = reader.read_u64::<BigEndian>()?;
+        let mut properties_flags = vec![reader.read_u16::<BigEndian>()?];
+        while properties_flags[properties_flags.len() - 1] & 1 != 0 {
+            properties_flags.push(reader.read_u16::<BigEndian>()?);
+        }
         let mut properties = vec![];
-        try!(reader.read_to_end(&mut properties));
+        reader.read_to_end(&mut properties)?;
         Ok(ContentHeaderFrame {
             content_class: content_class,
             weight: weight,
@@ -189,16 +463,90 @@ impl ContentHeaderFrame {
     }
 
     pub fn encode(&self) -> Result<Vec<u8>> {
-        let mut writer = Vec::with_capacity(self.properties.inner().len() + 14);
-        try!(writer.write_u16::<BigEndian>(self.content_class));
-        try!(writer.write_u16::<BigEndian>(self.weight)); //0 all the time for now
-        try!(writer.write_u64::<BigEndian>(self.body_size));
-        try!(writer.write_u16::<BigEndian>(self.properties_flags));
-        try!(writer.write_all(self.properties.inner()));
+        let mut writer = Vec::with_capacity(self.properties.inner().len() + 12 +
+                                            2 * self.properties_flags.len());
+        writer.write_u16::<BigEndian>(self.content_class)?;
+        writer.write_u16::<BigEndian>(self.weight)?; //0 all the time for now
+        writer.write_u64::<BigEndian>(self.body_size)?;
+        for word in finish_property_flags(self.properties_flags.clone()) {
+            writer.write_u16::<BigEndian>(word)?;
+        }
+        writer.write_all(self.properties.inner())?;
         Ok(writer)
     }
 }
 
+#[test]
+fn test_property_flag_continuation() {
+    let mut flags = vec![];
+    set_property_flag(&mut flags, 0);
+    set_property_flag(&mut flags, 14);
+    set_property_flag(&mut flags, 16);
+    assert_eq!(flags, vec![0x8002, 0x4000]);
+    assert!(property_flag(&flags, 16) && !property_flag(&flags, 15) && !property_flag(&flags, 40));
+    assert_eq!(property_flags_from(&flags, 14), vec![0x0002, 0x4000]);
+    assert_eq!(finish_property_flags(flags.clone()), vec![0x8003, 0x4000]);
+    assert_eq!(finish_property_flags(vec![]), vec![0]);
+
+    let header = ContentHeaderFrame {
+        content_class: 60,
+        weight: 0,
+        body_size: 5,
+        properties_flags: finish_property_flags(flags),
+        properties: EncodedProperties::new(vec![1, 2]),
+    };
+    let frame = Frame {
+        frame_type: FrameType::HEADERS,
+        channel: 1,
+        payload: FramePayload::new(header.encode().unwrap()),
+    };
+    let decoded = ContentHeaderFrame::decode(&frame).unwrap();
+    assert_eq!(decoded.properties_flags, vec![0x8003, 0x4000]);
+    assert_eq!(decoded.encode().unwrap(), header.encode().unwrap());
+}
+
+#[test]
+fn test_frame_size_limit() {
+    let frame = Frame {
+        frame_type: FrameType::BODY,
+        channel: 1,
+        payload: FramePayload::new(vec![0; 100]),
+    };
+    let encoded = frame.encode().unwrap();
+    let limits = DecodeLimits { max_frame_size: 99, ..Default::default() };
+    assert!(Frame::decode_with_limits(&mut &encoded[..], &limits).is_err());
+    let limits = DecodeLimits { max_frame_size: 100, ..Default::default() };
+    assert_eq!(Frame::decode_with_limits(&mut &encoded[..], &limits).unwrap(), frame);
+    // The size is checked before the payload is read, or allocated.
+    let header = [3, 0, 1, 0xFF, 0xFF, 0xFF, 0xFF];
+    assert!(Frame::decode(&mut &header[..]).is_err());
+    assert!(Frame::decode_with_limits(&mut &header[..], &DecodeLimits::unlimited()).is_err());
+}
+
+#[test]
+fn test_lenient_decoding() {
+    let raw = RawFrame {
+        frame_type_id: 9,
+        channel: 2,
+        payload: FramePayload::new(vec![1, 2, 3]),
+    };
+    let encoded = raw.encode().unwrap();
+    assert!(Frame::decode(&mut &encoded[..]).is_err());
+    assert!(AnyFrame::decode(&mut &encoded[..], DecodeMode::Strict).is_err());
+    let decoded = AnyFrame::decode(&mut &encoded[..], DecodeMode::Lenient).unwrap();
+    assert_eq!(decoded, AnyFrame::Unknown(raw));
+    assert_eq!(decoded.encode().unwrap(), encoded);
+
+    let heartbeat = Frame {
+        frame_type: FrameType::HEARTBEAT,
+        channel: 0,
+        payload: FramePayload::new(vec![]),
+    };
+    let encoded = heartbeat.encode().unwrap();
+    assert_eq!(AnyFrame::decode(&mut &encoded[..], DecodeMode::Lenient).unwrap(),
+               AnyFrame::Frame(heartbeat));
+}
+
 #[test]
 fn test_encode_decode() {
     let frame = Frame {
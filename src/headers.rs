@@ -0,0 +1,213 @@
+use crate::protocol::basic::BasicProperties;
+use crate::table::{Table, TableEntry};
+use crate::error::*;
+
+/// The `x-match` argument of a headers exchange binding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XMatch {
+    /// Every binding argument has to match (the default).
+    All,
+    /// At least one binding argument has to match.
+    Any,
+    /// Like `All`, also considering arguments starting with `x-`.
+    AllWithX,
+    /// Like `Any`, also considering arguments starting with `x-`.
+    AnyWithX,
+}
+
+impl XMatch {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            XMatch::All => "all",
+            XMatch::Any => "any",
+            XMatch::AllWithX => "all-with-x",
+            XMatch::AnyWithX => "any-with-x",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<XMatch> {
+        [XMatch::All, XMatch::Any, XMatch::AllWithX, XMatch::AnyWithX]
+            .iter()
+            .cloned()
+            .find(|x_match| x_match.name() == name)
+    }
+}
+
+/// Matches message headers against the `arguments` of a headers exchange binding,
+/// following RabbitMQ's rules:
+///
+/// * `x-match` is never matched itself, other `x-` arguments only with the
+///   `-with-x` variants.
+/// * An argument without a value (`Void`) only requires the header to be present.
+/// * Values compare by value within their type class, like `rabbit_misc:type_class`:
+///   all integer types are one class (`LongInt(1)` equals `ShortShortUint(1)`), and
+///   `Float` and `Double` another. Integers never equal floats, and all other types,
+///   including `Timestamp`, only equal values of the same type. Inside arrays and
+///   nested tables the types have to be identical.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadersMatcher {
+    x_match: XMatch,
+    arguments: Vec<(String, TableEntry)>,
+}
+
+impl HeadersMatcher {
+    pub fn new(arguments: &Table) -> Result<HeadersMatcher> {
+        let x_match = match arguments.get("x-match") {
+            None => XMatch::All,
+            Some(TableEntry::LongString(name)) => {
+                XMatch::from_name(name).ok_or_else(|| {
+                        ErrorKind::InvalidBindingArguments(format!("invalid x-match value '{}'",
+                                                                   name))
+                    })?
+            }
+            Some(other) => {
+                return Err(ErrorKind::InvalidBindingArguments(format!("invalid x-match value \
+                                                                       {:?}",
+                                                                      other))
+                    .into())
+            }
+        };
+        let with_x = x_match == XMatch::AllWithX || x_match == XMatch::AnyWithX;
+        let arguments = arguments.iter()
+            .filter(|&(name, _)| name != "x-match" && (with_x || !name.starts_with("x-")))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        Ok(HeadersMatcher {
+            x_match,
+            arguments,
+        })
+    }
+
+    pub fn x_match(&self) -> XMatch {
+        self.x_match
+    }
+
+    pub fn matches(&self, headers: &Table) -> bool {
+        let mut matching = self.arguments.iter().map(|(name, value)| {
+            match headers.get(name) {
+                Some(header) => *value == TableEntry::Void || values_equal(value, header),
+                None => false,
+            }
+        });
+        match self.x_match {
+            XMatch::All | XMatch::AllWithX => matching.all(|matched| matched),
+            XMatch::Any | XMatch::AnyWithX => matching.any(|matched| matched),
+        }
+    }
+
+    /// Matches the `headers` of a message, treating missing headers as an empty table.
+    pub fn matches_properties(&self, properties: &BasicProperties) -> bool {
+        match properties.headers {
+            Some(ref headers) => self.matches(headers),
+            None => self.matches(&Table::new()),
+        }
+    }
+}
+
+enum Number {
+    Integer(i128),
+    Float(f64),
+}
+
+fn number(entry: &TableEntry) -> Option<Number> {
+    Some(match *entry {
+        TableEntry::ShortShortInt(value) => Number::Integer(value.into()),
+        TableEntry::ShortShortUint(value) => Number::Integer(value.into()),
+        TableEntry::ShortInt(value) => Number::Integer(value.into()),
+        TableEntry::ShortUint(value) => Number::Integer(value.into()),
+        TableEntry::LongInt(value) => Number::Integer(value.into()),
+        TableEntry::LongUint(value) => Number::Integer(value.into()),
+        TableEntry::LongLongInt(value) => Number::Integer(value.into()),
+        TableEntry::LongLongUint(value) => Number::Integer(value.into()),
+        TableEntry::Float(value) => Number::Float(value.into()),
+        TableEntry::Double(value) => Number::Float(value),
+        _ => return None,
+    })
+}
+
+/// Compares a binding argument with a header value the way RabbitMQ does.
+pub fn values_equal(argument: &TableEntry, header: &TableEntry) -> bool {
+    if let (Some(argument), Some(header)) = (number(argument), number(header)) {
+        return match (argument, header) {
+            (Number::Integer(a), Number::Integer(b)) => a == b,
+            (Number::Float(a), Number::Float(b)) => a == b,
+            (Number::Integer(_), Number::Float(_)) |
+            (Number::Float(_), Number::Integer(_)) => false,
+        };
+    }
+    argument == header
+}
+
+#[cfg(test)]
+mod test {
+    use crate::protocol::basic::BasicProperties;
+    use crate::table::{Table, TableEntry};
+    use super::*;
+
+    fn table(entries: &[(&str, TableEntry)]) -> Table {
+        entries.iter().map(|(name, value)| (name.to_string(), value.clone())).collect()
+    }
+
+    fn string(value: &str) -> TableEntry {
+        TableEntry::LongString(value.to_string())
+    }
+
+    #[test]
+    fn test_all_and_any() {
+        let headers = table(&[("format", string("pdf")), ("type", string("report"))]);
+        let all = HeadersMatcher::new(&table(&[("format", string("pdf")),
+                                               ("type", string("log"))]))
+            .unwrap();
+        assert_eq!(all.x_match(), XMatch::All);
+        assert!(!all.matches(&headers));
+        let any = HeadersMatcher::new(&table(&[("x-match", string("any")),
+                                               ("format", string("pdf")),
+                                               ("type", string("log"))]))
+            .unwrap();
+        assert!(any.matches(&headers));
+        assert!(!any.matches(&Table::new()));
+
+        // With no arguments to match, `all` matches everything and `any` nothing.
+        assert!(HeadersMatcher::new(&Table::new()).unwrap().matches(&Table::new()));
+        let empty_any = HeadersMatcher::new(&table(&[("x-match", string("any"))])).unwrap();
+        assert!(!empty_any.matches(&headers));
+    }
+
+    #[test]
+    fn test_x_arguments() {
+        let headers = table(&[("x-tenant", string("a")), ("kind", string("b"))]);
+        let arguments = |x_match: &str| {
+            HeadersMatcher::new(&table(&[("x-match", string(x_match)),
+                                         ("x-tenant", string("other")),
+                                         ("kind", string("b"))]))
+                .unwrap()
+        };
+        assert!(arguments("all").matches(&headers));
+        assert!(!arguments("all-with-x").matches(&headers));
+        assert!(arguments("any-with-x").matches(&headers));
+        assert!(HeadersMatcher::new(&table(&[("x-match", string("some"))])).is_err());
+        assert!(HeadersMatcher::new(&table(&[("x-match", TableEntry::Bool(true))])).is_err());
+    }
+
+    #[test]
+    fn test_value_comparison() {
+        assert!(values_equal(&TableEntry::LongInt(1), &TableEntry::ShortShortUint(1)));
+        assert!(values_equal(&TableEntry::Double(2.0), &TableEntry::Float(2.0)));
+        // Integers, floats and timestamps are different classes, even for equal values.
+        assert!(!values_equal(&TableEntry::LongLongUint(5), &TableEntry::Timestamp(5)));
+        assert!(values_equal(&TableEntry::Timestamp(5), &TableEntry::Timestamp(5)));
+        assert!(!values_equal(&TableEntry::Double(2.0), &TableEntry::ShortInt(2)));
+        assert!(!values_equal(&TableEntry::Bool(true), &TableEntry::ShortShortUint(1)));
+        assert!(!values_equal(&string("1"), &TableEntry::LongInt(1)));
+        assert!(!values_equal(&TableEntry::DecimalValue(1, 10), &TableEntry::LongInt(1)));
+        // Nested values keep their types.
+        assert!(!values_equal(&TableEntry::FieldArray(vec![TableEntry::LongInt(1)]),
+                              &TableEntry::FieldArray(vec![TableEntry::LongLongInt(1)])));
+        assert!(values_equal(&TableEntry::FieldArray(vec![string("a")]),
+                             &TableEntry::FieldArray(vec![string("a")])));
+
+        let present = HeadersMatcher::new(&table(&[("trace", TableEntry::Void)])).unwrap();
+        assert!(present.matches(&table(&[("trace", TableEntry::Bool(false))])));
+        assert!(!present.matches_properties(&BasicProperties::default()));
+    }
+}
@@ -11,14 +11,9 @@
 //! > Expect the API to be changed in the future.
 //!
 //!
-//! The methods encoding/decoding code is generated using codegen.rb &
-//! amqp-rabbitmq-0.9.1.json spec.
-//!
-//! To generate a new spec, run:
-//!
-//! ```sh
-//! make
-//! ```
+//! The methods encoding/decoding code is generated from the
+//! amqp-rabbitmq-0.9.1.json spec by the amq-proto-codegen crate,
+//! which the build script runs.
 //!
 //! To build project, use cargo:
 //!
@@ -42,6 +37,8 @@ extern crate error_chain;
 extern crate log;
 #[macro_use]
 extern crate enum_primitive;
+// Lets `#[derive(AmqpMethod)]` refer to `::amq_proto` inside this crate too.
+extern crate self as amq_proto;
 
 mod framing;
 mod table;
@@ -49,11 +46,74 @@ mod method;
 #[macro_use]
 mod codegen_macros;
 mod error;
+mod consumer;
+mod content;
+mod display;
+mod dump;
+mod recording;
+#[cfg(feature = "serde")]
+mod serialization;
+mod confirms;
+mod handshake;
+mod connection_state;
+mod peer_properties;
+pub mod sasl;
+pub mod reflection;
+pub mod dynamic;
+mod uri;
+mod headers;
+mod properties;
+mod topic;
+#[cfg(feature = "tokio")]
+mod codec;
+#[cfg(any(feature = "client", feature = "blocking"))]
+mod client_core;
+#[cfg(all(test, any(feature = "client", feature = "blocking")))]
+mod fake_broker;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "broker")]
+pub mod broker;
+#[cfg(feature = "pcap")]
+pub mod pcap;
 
 pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 pub mod protocol;
 
-pub use table::{Table, TableEntry};
-pub use method::{Method, EncodedMethod};
-pub use framing::*;
-pub use error::*;
+pub use crate::table::{Table, TableEntry, decode_table, decode_table_with_limits, encode_table};
+pub use crate::method::{Method, EncodedMethod, expect_method, expect_method_with_limits};
+pub use crate::codegen_macros::{ArgumentsReader, ArgumentsWriter};
+#[cfg(feature = "derive")]
+pub use amq_proto_derive::AmqpMethod;
+pub use crate::framing::{AnyFrame, ContentHeaderFrame, DecodeLimits, DecodeMode, EncodedProperties,
+                         Frame, FrameHeader, FramePayload, FrameType, MethodFrame, ProtocolHeader,
+                         RawFrame, UnknownProperties};
+pub use crate::display::{DisplayArgument, FrameDisplay, DEFAULT_BODY_LIMIT};
+pub use crate::dump::{parse_hex, Direction, StreamDecoder, StreamEvent, StreamItem};
+pub use crate::recording::{RecordedFrame, Recorder, Recording, RecordingReader, ReplayReader,
+                           ReplayWriter, Tap, DEFAULT_REPLAY_TIMEOUT};
+pub use crate::consumer::{Consumer, ConsumerRegistry, Delivery};
+pub use crate::content::{encode_content, Content, ContentAssembler, GetMessage, Incoming, Returned};
+pub use crate::confirms::ConfirmTracker;
+pub use crate::handshake::{Handshake, Negotiated, Step};
+pub use crate::connection_state::{Action, ChannelState, ConnectionState};
+pub use crate::uri::{AmqpUri, Scheme, TLS_PORT};
+pub use crate::headers::{HeadersMatcher, XMatch};
+pub use crate::properties::{ContentType, DeliveryMode, MAX_PRIORITY};
+pub use crate::topic::{TopicPattern, TopicTrie};
+pub use crate::peer_properties::{Capability, ClientProperties, ServerProperties, ALL_CAPABILITIES};
+#[cfg(feature = "tokio")]
+pub use crate::codec::{AmqpCodec, AmqpMessage};
+pub use crate::error::*;
+
+/// What the code generated by `amq-proto-codegen` uses besides the public API. Not
+/// covered by semver.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::codegen_macros::log_decoding;
+    pub use crate::display::FieldsDisplay;
+    pub use crate::framing::{finish_property_flags, property_flag, property_flags_from,
+                             set_property_flag};
+}
@@ -1,5 +1,6 @@
-use framing::{FrameType, Frame, FramePayload, MethodFrame};
-use error::Result;
+use crate::framing::{DecodeLimits, FrameType, Frame, FramePayload, MethodFrame};
+use crate::protocol::{channel, connection};
+use crate::error::*;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct EncodedMethod(Vec<u8>);
@@ -20,10 +21,19 @@ impl EncodedMethod {
 
 pub trait Method {
     fn decode(method_frame: MethodFrame) -> Result<Self> where Self: Sized;
+    /// Like `decode`, with `limits` on the tables and strings of the arguments. The
+    /// default ignores them, the generated and derived methods enforce them.
+    fn decode_with_limits(method_frame: MethodFrame, _limits: &DecodeLimits) -> Result<Self>
+        where Self: Sized
+    {
+        Self::decode(method_frame)
+    }
     fn encode(&self) -> Result<EncodedMethod>;
     fn name(&self) -> &'static str;
     const ID: u16;
     const CLASS_ID: u16;
+    /// Whether the method expects a reply, according to the spec.
+    const SYNCHRONOUS: bool = false;
 
     fn encode_method_frame(&self) -> Result<FramePayload> {
         let frame = MethodFrame {
@@ -42,3 +52,29 @@ pub trait Method {
         })
     }
 }
+
+/// Decodes a method frame into `M`, turning an unexpected `connection.close`
+/// or `channel.close` into the matching error.
+pub fn expect_method<M: Method>(method_frame: MethodFrame) -> Result<M> {
+    expect_method_with_limits(method_frame, &DecodeLimits::default())
+}
+
+/// Like `expect_method`, decoding `M` with `limits`.
+pub fn expect_method_with_limits<M: Method>(method_frame: MethodFrame,
+                                            limits: &DecodeLimits)
+                                            -> Result<M> {
+    if method_frame.class_id == M::CLASS_ID && method_frame.method_id == M::ID {
+        return M::decode_with_limits(method_frame, limits);
+    }
+    match method_frame.method_name() {
+        "connection.close" => {
+            let close = connection::Close::decode(method_frame)?;
+            Err(ErrorKind::ConnectionClosed(close.reply_code, close.reply_text).into())
+        }
+        "channel.close" => {
+            let close = channel::Close::decode(method_frame)?;
+            Err(ErrorKind::ChannelClosed(close.reply_code, close.reply_text).into())
+        }
+        name => Err(ErrorKind::Protocol(format!("Unexpected method {}", name)).into()),
+    }
+}
@@ -0,0 +1,654 @@
+//! Reads AMQP connections from pcap and pcapng captures, e.g. made with
+//! `tcpdump -w capture.pcap port 5672`.
+//!
+//! TCP segments to or from the AMQP port are reassembled into one stream per
+//! direction and connection, which are decoded into a timeline of frames:
+//!
+//! ```no_run
+//! use amq_proto::pcap::CaptureReader;
+//!
+//! for connection in CaptureReader::new().read_file("capture.pcap").unwrap() {
+//!     println!("{} -> {}", connection.client, connection.server);
+//!     for entry in connection.timeline() {
+//!         println!("{}", entry.to_text(Some(64)));
+//!     }
+//! }
+//! ```
+//!
+//! Ethernet (with VLAN tags), raw IP, BSD loopback and Linux cooked captures
+//! of IPv4 and IPv6 are supported; fragmented IP packets are skipped.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use crate::dump::{Direction, JsonObject, StreamDecoder, StreamItem};
+use crate::error::*;
+use crate::protocol::PORT;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_IPV6: u16 = 229;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_ACK: u8 = 0x10;
+
+fn invalid<T>(message: &str) -> Result<T> {
+    Err(ErrorKind::InvalidCapture(message.to_string()).into())
+}
+
+/// A captured link layer packet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    /// The capture time, since the Unix epoch.
+    pub timestamp: Duration,
+    pub link_type: u16,
+    pub data: Vec<u8>,
+}
+
+/// Reads the packets of a pcap or pcapng capture, telling the formats apart
+/// by their magic numbers.
+pub fn read_packets(data: &[u8]) -> Result<Vec<Packet>> {
+    if data.len() < 4 {
+        return invalid("capture too short");
+    }
+    if LittleEndian::read_u32(data) == PCAPNG_SECTION_HEADER {
+        read_pcapng(data)
+    } else {
+        read_pcap(data)
+    }
+}
+
+fn read_pcap(data: &[u8]) -> Result<Vec<Packet>> {
+    if data.len() < 24 {
+        return invalid("truncated pcap header");
+    }
+    let (big_endian, nanos) = match (LittleEndian::read_u32(data), BigEndian::read_u32(data)) {
+        (PCAP_MAGIC_MICROS, _) => (false, false),
+        (PCAP_MAGIC_NANOS, _) => (false, true),
+        (_, PCAP_MAGIC_MICROS) => (true, false),
+        (_, PCAP_MAGIC_NANOS) => (true, true),
+        _ => return invalid("not a pcap or pcapng capture"),
+    };
+    let read_u32 = |bytes: &[u8]| if big_endian {
+        BigEndian::read_u32(bytes)
+    } else {
+        LittleEndian::read_u32(bytes)
+    };
+    let link_type = read_u32(&data[20..24]) as u16;
+    let mut packets = vec![];
+    let mut offset = 24;
+    while offset < data.len() {
+        if data.len() < offset + 16 {
+            return invalid("truncated pcap record header");
+        }
+        let seconds = read_u32(&data[offset..]);
+        let fraction = read_u32(&data[offset + 4..]);
+        let length = read_u32(&data[offset + 8..]) as usize;
+        offset += 16;
+        if data.len() < offset + length {
+            return invalid("truncated pcap record");
+        }
+        let timestamp = Duration::new(seconds.into(),
+                                      if nanos { fraction } else { fraction.saturating_mul(1000) });
+        packets.push(Packet {
+            timestamp,
+            link_type,
+            data: data[offset..offset + length].to_vec(),
+        });
+        offset += length;
+    }
+    Ok(packets)
+}
+
+struct Interface {
+    link_type: u16,
+    snap_length: usize,
+    /// Timestamp units per second.
+    resolution: u64,
+}
+
+fn read_pcapng(data: &[u8]) -> Result<Vec<Packet>> {
+    let mut packets = vec![];
+    let mut interfaces: Vec<Interface> = vec![];
+    let mut big_endian = false;
+    let mut offset = 0;
+    while offset < data.len() {
+        if data.len() < offset + 12 {
+            return invalid("truncated pcapng block");
+        }
+        if LittleEndian::read_u32(&data[offset..]) == PCAPNG_SECTION_HEADER {
+            // Each section sets its own byte order and interfaces.
+            big_endian = match (LittleEndian::read_u32(&data[offset + 8..]),
+                                BigEndian::read_u32(&data[offset + 8..])) {
+                (PCAPNG_BYTE_ORDER_MAGIC, _) => false,
+                (_, PCAPNG_BYTE_ORDER_MAGIC) => true,
+                _ => return invalid("invalid pcapng byte order magic"),
+            };
+            interfaces.clear();
+        }
+        let read_u16 = |bytes: &[u8]| if big_endian {
+            BigEndian::read_u16(bytes)
+        } else {
+            LittleEndian::read_u16(bytes)
+        };
+        let read_u32 = |bytes: &[u8]| if big_endian {
+            BigEndian::read_u32(bytes)
+        } else {
+            LittleEndian::read_u32(bytes)
+        };
+        let block_type = read_u32(&data[offset..]);
+        let length = read_u32(&data[offset + 4..]) as usize;
+        if length < 12 || !length.is_multiple_of(4) || data.len() < offset + length {
+            return invalid("invalid pcapng block length");
+        }
+        let body = &data[offset + 8..offset + length - 4];
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => {
+                if body.len() < 8 {
+                    return invalid("truncated pcapng interface description");
+                }
+                let mut resolution = 1_000_000;
+                let mut options = &body[8..];
+                while options.len() >= 4 {
+                    let code = read_u16(options);
+                    let option_length = read_u16(&options[2..]) as usize;
+                    let padded = 4 + option_length.div_ceil(4) * 4;
+                    if code == 0 || options.len() < padded {
+                        break;
+                    }
+                    // if_tsresol: a negative power of 10, or of 2 with the high bit set.
+                    if code == 9 && option_length >= 1 {
+                        let exponent = u32::from(options[4] & 0x7f);
+                        let base: u64 = if options[4] & 0x80 == 0 { 10 } else { 2 };
+                        resolution = base.checked_pow(exponent)
+                            .ok_or_else(|| ErrorKind::InvalidCapture("invalid if_tsresol".into()))?;
+                    }
+                    options = &options[padded..];
+                }
+                interfaces.push(Interface {
+                    link_type: read_u16(body),
+                    snap_length: read_u32(&body[4..]) as usize,
+                    resolution,
+                });
+            }
+            PCAPNG_ENHANCED_PACKET => {
+                if body.len() < 20 {
+                    return invalid("truncated pcapng packet block");
+                }
+                let interface = interfaces.get(read_u32(body) as usize)
+                    .ok_or_else(|| ErrorKind::InvalidCapture("unknown pcapng interface".into()))?;
+                let units = (u64::from(read_u32(&body[4..])) << 32) |
+                            u64::from(read_u32(&body[8..]));
+                let captured = read_u32(&body[12..]) as usize;
+                if body.len() < 20 + captured {
+                    return invalid("truncated pcapng packet data");
+                }
+                let nanos = u128::from(units % interface.resolution) * 1_000_000_000 /
+                            u128::from(interface.resolution);
+                packets.push(Packet {
+                    timestamp: Duration::new(units / interface.resolution, nanos as u32),
+                    link_type: interface.link_type,
+                    data: body[20..20 + captured].to_vec(),
+                });
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let interface = interfaces.first()
+                    .ok_or_else(|| ErrorKind::InvalidCapture("unknown pcapng interface".into()))?;
+                if body.len() < 4 {
+                    return invalid("truncated pcapng packet block");
+                }
+                let mut captured = read_u32(body) as usize;
+                if interface.snap_length != 0 {
+                    captured = captured.min(interface.snap_length);
+                }
+                if body.len() < 4 + captured {
+                    return invalid("truncated pcapng packet data");
+                }
+                // Simple packet blocks carry no timestamp.
+                packets.push(Packet {
+                    timestamp: Duration::from_secs(0),
+                    link_type: interface.link_type,
+                    data: body[4..4 + captured].to_vec(),
+                });
+            }
+            _ => {}
+        }
+        offset += length;
+    }
+    Ok(packets)
+}
+
+/// A TCP segment of a captured packet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub sequence: u32,
+    pub flags: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    /// Extracts the TCP segment, if the packet holds one. Packets of other protocols,
+    /// fragmented or truncated IP packets and unknown link types give `None`.
+    pub fn tcp_segment(&self) -> Option<Segment> {
+        let data = &self.data[..];
+        let ip = match self.link_type {
+            LINKTYPE_ETHERNET => {
+                let mut offset = 12;
+                loop {
+                    let ether_type = BigEndian::read_u16(data.get(offset..offset + 2)?);
+                    match ether_type {
+                        // VLAN tags
+                        0x8100 | 0x88a8 => offset += 4,
+                        0x0800 | 0x86dd => break &data[offset + 2..],
+                        _ => return None,
+                    }
+                }
+            }
+            LINKTYPE_NULL => data.get(4..)?,
+            LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => data,
+            LINKTYPE_LINUX_SLL => data.get(16..)?,
+            LINKTYPE_LINUX_SLL2 => data.get(20..)?,
+            _ => return None,
+        };
+        match ip.first()? >> 4 {
+            4 => ipv4_segment(ip),
+            6 => ipv6_segment(ip),
+            _ => None,
+        }
+    }
+}
+
+fn ipv4_segment(ip: &[u8]) -> Option<Segment> {
+    let header_length = usize::from(ip.first()? & 0x0f) * 4;
+    let total_length = usize::from(BigEndian::read_u16(ip.get(2..4)?));
+    let fragment = BigEndian::read_u16(ip.get(6..8)?);
+    // More fragments, or a fragment offset.
+    if ip.get(9)? != &6 || fragment & 0x3fff != 0 || header_length < 20 ||
+       total_length < header_length || header_length > ip.len() {
+        return None;
+    }
+    let addresses = ip.get(12..20)?;
+    let source = IpAddr::V4(Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]));
+    let destination =
+        IpAddr::V4(Ipv4Addr::new(addresses[4], addresses[5], addresses[6], addresses[7]));
+    // The total length drops Ethernet padding.
+    tcp_segment(source, destination, ip.get(header_length..total_length)?)
+}
+
+fn ipv6_segment(ip: &[u8]) -> Option<Segment> {
+    let payload_length = usize::from(BigEndian::read_u16(ip.get(4..6)?));
+    let mut next_header = *ip.get(6)?;
+    let mut source = [0u8; 16];
+    source.copy_from_slice(ip.get(8..24)?);
+    let mut destination = [0u8; 16];
+    destination.copy_from_slice(ip.get(24..40)?);
+    let mut payload = ip.get(40..40 + payload_length)?;
+    loop {
+        match next_header {
+            6 => break,
+            // Hop-by-hop, routing and destination options headers.
+            0 | 43 | 60 => {
+                let length = (usize::from(*payload.get(1)?) + 1) * 8;
+                next_header = payload[0];
+                payload = payload.get(length..)?;
+            }
+            _ => return None,
+        }
+    }
+    tcp_segment(IpAddr::V6(Ipv6Addr::from(source)),
+                IpAddr::V6(Ipv6Addr::from(destination)),
+                payload)
+}
+
+fn tcp_segment(source: IpAddr, destination: IpAddr, tcp: &[u8]) -> Option<Segment> {
+    let data_offset = usize::from(tcp.get(12)? >> 4) * 4;
+    Some(Segment {
+        source: SocketAddr::new(source, BigEndian::read_u16(&tcp[0..2])),
+        destination: SocketAddr::new(destination, BigEndian::read_u16(&tcp[2..4])),
+        sequence: BigEndian::read_u32(&tcp[4..8]),
+        flags: *tcp.get(13)?,
+        payload: tcp.get(data_offset..)?.to_vec(),
+    })
+}
+
+/// The reassembled data sent in one direction of a TCP connection.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CapturedStream {
+    pub data: Vec<u8>,
+    /// Offsets into `data` where bytes are missing from the capture.
+    pub gaps: Vec<usize>,
+    /// When the data from each offset on was captured.
+    arrivals: Vec<(usize, Duration)>,
+}
+
+impl CapturedStream {
+    /// The capture time of the byte at `offset`.
+    pub fn timestamp_at(&self, offset: usize) -> Option<Duration> {
+        let idx = match self.arrivals.binary_search_by(|&(start, _)| start.cmp(&offset)) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        Some(self.arrivals[idx].1)
+    }
+
+    pub fn items(&self) -> StreamDecoder<'_> {
+        StreamDecoder::new(&self.data)
+    }
+}
+
+/// Collects the segments of one direction until the connection is complete.
+#[derive(Default)]
+struct StreamBuilder {
+    /// The sequence number of the first data byte, known from the SYN.
+    first_sequence: Option<u32>,
+    segments: Vec<(u32, Duration, Vec<u8>)>,
+}
+
+impl StreamBuilder {
+    fn add(&mut self, timestamp: Duration, segment: Segment) {
+        if segment.flags & TCP_SYN != 0 {
+            self.first_sequence = Some(segment.sequence.wrapping_add(1));
+            return;
+        }
+        if !segment.payload.is_empty() {
+            self.segments.push((segment.sequence, timestamp, segment.payload));
+        }
+    }
+
+    fn build(self) -> CapturedStream {
+        let mut stream = CapturedStream::default();
+        let first = match (self.first_sequence, self.segments.first()) {
+            (Some(first), _) => first,
+            // The capture started mid-connection: begin at the lowest sequence number seen.
+            (None, Some(&(sequence, _, _))) => {
+                self.segments
+                    .iter()
+                    .map(|&(other, _, _)| other.wrapping_sub(sequence) as i32)
+                    .min()
+                    .map_or(sequence, |lowest| sequence.wrapping_add(lowest as u32))
+            }
+            (None, None) => return stream,
+        };
+        // Sequence numbers relative to the start of the stream, ordered by arrival
+        // for retransmissions.
+        let mut segments: BTreeMap<(u32, usize), (Duration, Vec<u8>)> = BTreeMap::new();
+        for (idx, (sequence, timestamp, payload)) in self.segments.into_iter().enumerate() {
+            segments.insert((sequence.wrapping_sub(first), idx), (timestamp, payload));
+        }
+        let mut end: u64 = 0;
+        for ((start, _), (timestamp, payload)) in segments {
+            let start = u64::from(start);
+            let segment_end = start + payload.len() as u64;
+            if segment_end <= end {
+                continue;
+            }
+            if start > end {
+                stream.gaps.push(stream.data.len());
+            }
+            let new_data = &payload[end.saturating_sub(start) as usize..];
+            stream.arrivals.push((stream.data.len(), timestamp));
+            stream.data.extend_from_slice(new_data);
+            end = segment_end;
+        }
+        stream
+    }
+}
+
+/// An AMQP connection found in a capture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedConnection {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub client_stream: CapturedStream,
+    pub server_stream: CapturedStream,
+}
+
+/// A frame of a captured connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineEntry {
+    /// The capture time of the segment completing the frame.
+    pub timestamp: Duration,
+    pub direction: Direction,
+    pub item: StreamItem,
+}
+
+impl TimelineEntry {
+    /// E.g. `1500000000.000120 C->S 00000008 ch=0 METHOD connection.start-ok{...}`.
+    pub fn to_text(&self, body_limit: Option<usize>) -> String {
+        format!("{}.{:06} {} {}",
+                self.timestamp.as_secs(),
+                self.timestamp.subsec_micros(),
+                self.direction.arrow(),
+                self.item.to_text(body_limit))
+    }
+
+    /// The JSON of the stream item, preceded by the addresses of the connection, the
+    /// capture time and the direction.
+    pub fn to_json(&self, connection: &CapturedConnection, body_limit: Option<usize>) -> String {
+        let mut object = JsonObject::new();
+        object.string("client", &connection.client.to_string())
+            .string("server", &connection.server.to_string())
+            .value("timestamp",
+                   format!("{}.{:09}", self.timestamp.as_secs(), self.timestamp.subsec_nanos()))
+            .string("direction", self.direction.arrow());
+        self.item.write_json(&mut object, body_limit);
+        object.finish()
+    }
+}
+
+impl CapturedConnection {
+    pub fn stream(&self, direction: Direction) -> &CapturedStream {
+        match direction {
+            Direction::ClientToServer => &self.client_stream,
+            Direction::ServerToClient => &self.server_stream,
+        }
+    }
+
+    /// Decodes both directions into one timeline, ordered by capture time.
+    pub fn timeline(&self) -> Vec<TimelineEntry> {
+        let mut timeline = vec![];
+        for &direction in &[Direction::ClientToServer, Direction::ServerToClient] {
+            let stream = self.stream(direction);
+            let mut items = stream.items().peekable();
+            while let Some(item) = items.next() {
+                let end = items.peek().map_or(stream.data.len(), |next| next.offset);
+                timeline.push(TimelineEntry {
+                    timestamp: stream.timestamp_at(end - 1).unwrap_or_default(),
+                    direction,
+                    item,
+                });
+            }
+        }
+        // Stable, so frames of one direction stay in stream order.
+        timeline.sort_by_key(|entry| entry.timestamp);
+        timeline
+    }
+}
+
+/// Reassembles the AMQP connections of a capture.
+#[derive(Debug, Clone)]
+pub struct CaptureReader {
+    port: u16,
+}
+
+impl Default for CaptureReader {
+    fn default() -> Self {
+        CaptureReader { port: PORT }
+    }
+}
+
+impl CaptureReader {
+    /// Looks for connections to the default AMQP port, 5672.
+    pub fn new() -> Self {
+        CaptureReader::default()
+    }
+
+    pub fn with_port(port: u16) -> Self {
+        CaptureReader { port }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn read_file<P: AsRef<Path>>(&self, path: P) -> Result<Vec<CapturedConnection>> {
+        self.read(&fs::read(path)?)
+    }
+
+    /// Returns the connections in the order they were first seen.
+    pub fn read(&self, capture: &[u8]) -> Result<Vec<CapturedConnection>> {
+        let mut connections: Vec<(SocketAddr, SocketAddr, StreamBuilder, StreamBuilder)> = vec![];
+        for packet in read_packets(capture)? {
+            let segment = match packet.tcp_segment() {
+                Some(segment) => segment,
+                None => continue,
+            };
+            let (client, server, direction) = if segment.destination.port() == self.port {
+                (segment.source, segment.destination, Direction::ClientToServer)
+            } else if segment.source.port() == self.port {
+                (segment.destination, segment.source, Direction::ServerToClient)
+            } else {
+                continue;
+            };
+            // A new SYN on a known address pair starts a new connection.
+            let reused = segment.flags & (TCP_SYN | TCP_ACK) == TCP_SYN;
+            let position = connections.iter()
+                .rposition(|connection| connection.0 == client && connection.1 == server);
+            let idx = match position {
+                Some(idx) if !reused => idx,
+                _ => {
+                    connections.push((client,
+                                      server,
+                                      StreamBuilder::default(),
+                                      StreamBuilder::default()));
+                    connections.len() - 1
+                }
+            };
+            let connection = &mut connections[idx];
+            match direction {
+                Direction::ClientToServer => connection.2.add(packet.timestamp, segment),
+                Direction::ServerToClient => connection.3.add(packet.timestamp, segment),
+            }
+        }
+        Ok(connections.into_iter()
+            .map(|(client, server, client_stream, server_stream)| {
+                CapturedConnection {
+                    client,
+                    server,
+                    client_stream: client_stream.build(),
+                    server_stream: server_stream.build(),
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dump::{Direction, StreamEvent};
+    use super::*;
+
+    const PCAP: &[u8] = include_bytes!("../tests/fixtures/handshake.pcap");
+    const PCAPNG: &[u8] = include_bytes!("../tests/fixtures/publish.pcapng");
+
+    fn methods(connection: &CapturedConnection) -> Vec<String> {
+        connection.timeline()
+            .iter()
+            .map(|entry| format!("{} {}", entry.direction.arrow(), entry.item.to_text(None)))
+            .collect()
+    }
+
+    #[test]
+    fn test_pcap_handshake() {
+        let connections = CaptureReader::new().read(PCAP).unwrap();
+        assert_eq!(connections.len(), 1);
+        let connection = &connections[0];
+        assert_eq!(connection.server, "10.0.0.2:5672".parse().unwrap());
+        assert!(connection.client_stream.gaps.is_empty());
+        let timeline = connection.timeline();
+        assert!(timeline.iter().all(|entry| !matches!(entry.item.event, StreamEvent::Malformed(_))));
+        let names: Vec<_> = methods(connection);
+        assert!(names[0].starts_with("C->S 00000000 PROTOCOL-HEADER"));
+        assert!(names[1].starts_with("S->C 00000000 ch=0 METHOD connection.start{"));
+        // The start-ok was split across an out of order and a retransmitted segment.
+        assert!(names[2].starts_with("C->S 00000008 ch=0 METHOD connection.start-ok{"));
+        assert!(names.last().unwrap().contains("connection.open-ok"));
+        assert_eq!(timeline[1].timestamp, Duration::new(1_500_000_000, 1_000_000));
+        assert_eq!(CaptureReader::with_port(5673).read(PCAP).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_pcapng_publish() {
+        let connections = CaptureReader::new().read(PCAPNG).unwrap();
+        assert_eq!(connections.len(), 1);
+        let connection = &connections[0];
+        assert!(connection.client.is_ipv6());
+        let timeline = connection.timeline();
+        let client: Vec<_> = timeline.iter()
+            .filter(|entry| entry.direction == Direction::ClientToServer)
+            .map(|entry| entry.item.to_text(None))
+            .collect();
+        assert_eq!(client,
+                   vec!["00000000 ch=1 METHOD basic.publish{ticket=0, exchange=\"\", \
+                         routing_key=\"work\", mandatory=false, immediate=false}",
+                        "00000015 ch=1 HEADERS class=60 size=5 {content_type=\"text/plain\"}",
+                        "00000036 ch=1 BODY size=5 \"hello\""]);
+        // The publish is completed by the second segment; if_tsresol gives nanoseconds.
+        assert_eq!(timeline[0].timestamp, Duration::new(1_500_000_000, 123_456_900));
+        let json = timeline[2].to_json(connection, Some(4));
+        assert!(json.starts_with(&format!("{{\"client\":\"{}\",\"server\":\"{}\",\
+                                           \"timestamp\":1500000000.",
+                                          connection.client,
+                                          connection.server)));
+        assert!(json.ends_with(",\"direction\":\"C->S\",\"offset\":54,\"type\":\"BODY\",\
+                                \"channel\":1,\"size\":5,\"text\":\"ch=1 BODY size=5 \\\"hell\\\"... (1 more bytes)\"}"),
+                "{}",
+                json);
+    }
+
+    #[test]
+    fn test_invalid_capture() {
+        assert!(read_packets(b"nope").is_err());
+        assert!(read_packets(&PCAP[..30]).is_err());
+        assert!(read_packets(&PCAPNG[..40]).is_err());
+    }
+
+    #[test]
+    fn test_truncated_packets() {
+        // An IPv4 header of 20 bytes, then a TCP header of 20 bytes.
+        let mut packet = vec![0x45, 0, 0, 40, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        packet.extend_from_slice(&[0x16, 0x28, 0x04, 0xd2, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18]);
+        packet.extend_from_slice(&[0; 6]);
+        let segment = ipv4_segment(&packet).unwrap();
+        assert_eq!(segment.destination.port(), 1234);
+        for len in 0..packet.len() {
+            assert!(ipv4_segment(&packet[..len]).is_none(), "{} bytes", len);
+        }
+        // Header lengths below the minimum, or past the end of the packet.
+        packet[0] = 0x44;
+        assert!(ipv4_segment(&packet).is_none());
+        packet[0] = 0x4f;
+        packet[3] = 60;
+        assert!(ipv4_segment(&packet).is_none());
+    }
+}
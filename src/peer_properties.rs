@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use crate::table::{Table, TableEntry};
+use crate::protocol::connection;
+use crate::VERSION;
+
+/// Protocol extensions RabbitMQ only enables when the client advertises them
+/// in the `capabilities` table of `connection.start-ok`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    PublisherConfirms,
+    ConsumerCancelNotify,
+    BasicNack,
+    ConnectionBlocked,
+    AuthenticationFailureClose,
+    ExchangeExchangeBindings,
+}
+
+pub const ALL_CAPABILITIES: [Capability; 6] = [Capability::PublisherConfirms,
+                                               Capability::ConsumerCancelNotify,
+                                               Capability::BasicNack,
+                                               Capability::ConnectionBlocked,
+                                               Capability::AuthenticationFailureClose,
+                                               Capability::ExchangeExchangeBindings];
+
+impl Capability {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Capability::PublisherConfirms => "publisher_confirms",
+            Capability::ConsumerCancelNotify => "consumer_cancel_notify",
+            Capability::BasicNack => "basic.nack",
+            Capability::ConnectionBlocked => "connection.blocked",
+            Capability::AuthenticationFailureClose => "authentication_failure_close",
+            Capability::ExchangeExchangeBindings => "exchange_exchange_bindings",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Capability> {
+        ALL_CAPABILITIES.iter().cloned().find(|capability| capability.name() == name)
+    }
+}
+
+/// Builder for the `client_properties` table sent in `connection.start-ok`.
+///
+/// By default it describes this crate and advertises all known capabilities.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientProperties {
+    product: String,
+    version: String,
+    platform: String,
+    information: Option<String>,
+    connection_name: Option<String>,
+    capabilities: Vec<Capability>,
+    extra: Table,
+}
+
+impl Default for ClientProperties {
+    fn default() -> Self {
+        ClientProperties {
+            product: "amq-proto".to_string(),
+            version: VERSION.to_string(),
+            platform: "Rust".to_string(),
+            information: None,
+            connection_name: None,
+            capabilities: ALL_CAPABILITIES.to_vec(),
+            extra: Table::new(),
+        }
+    }
+}
+
+impl ClientProperties {
+    pub fn new() -> Self {
+        ClientProperties::default()
+    }
+
+    pub fn product(mut self, product: &str) -> Self {
+        self.product = product.to_string();
+        self
+    }
+
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = version.to_string();
+        self
+    }
+
+    pub fn platform(mut self, platform: &str) -> Self {
+        self.platform = platform.to_string();
+        self
+    }
+
+    pub fn information(mut self, information: &str) -> Self {
+        self.information = Some(information.to_string());
+        self
+    }
+
+    /// Name shown for the connection in the RabbitMQ management UI.
+    pub fn connection_name(mut self, connection_name: &str) -> Self {
+        self.connection_name = Some(connection_name.to_string());
+        self
+    }
+
+    pub fn capability(mut self, capability: Capability, enabled: bool) -> Self {
+        self.capabilities.retain(|c| *c != capability);
+        if enabled {
+            self.capabilities.push(capability);
+        }
+        self
+    }
+
+    /// Adds an arbitrary top-level property, overriding the generated ones on conflict.
+    pub fn property(mut self, name: &str, value: TableEntry) -> Self {
+        self.extra.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn has_capability(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Capabilities advertised by both the client and the server.
+    pub fn negotiated(&self, server: &ServerProperties) -> Vec<Capability> {
+        self.capabilities.iter().cloned().filter(|c| server.supports(*c)).collect()
+    }
+
+    pub fn to_table(&self) -> Table {
+        let mut capabilities = Table::new();
+        for capability in &self.capabilities {
+            capabilities.insert(capability.name().to_string(), TableEntry::Bool(true));
+        }
+        let mut table = Table::new();
+        table.insert("product".to_string(),
+                     TableEntry::LongString(self.product.clone()));
+        table.insert("version".to_string(),
+                     TableEntry::LongString(self.version.clone()));
+        table.insert("platform".to_string(),
+                     TableEntry::LongString(self.platform.clone()));
+        if let Some(ref information) = self.information {
+            table.insert("information".to_string(),
+                         TableEntry::LongString(information.clone()));
+        }
+        if let Some(ref connection_name) = self.connection_name {
+            table.insert("connection_name".to_string(),
+                         TableEntry::LongString(connection_name.clone()));
+        }
+        table.insert("capabilities".to_string(),
+                     TableEntry::FieldTable(capabilities));
+        for (name, value) in &self.extra {
+            table.insert(name.clone(), value.clone());
+        }
+        table
+    }
+
+    /// Builds a `connection.start-ok` carrying these properties.
+    pub fn start_ok(&self, mechanism: &str, response: &str, locale: &str) -> connection::StartOk {
+        connection::StartOk {
+            client_properties: self.to_table(),
+            mechanism: mechanism.to_string(),
+            response: response.to_string(),
+            locale: locale.to_string(),
+        }
+    }
+}
+
+/// The broker's `server_properties` from `connection.start`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerProperties {
+    pub product: Option<String>,
+    pub version: Option<String>,
+    pub platform: Option<String>,
+    pub cluster_name: Option<String>,
+    pub capabilities: HashMap<String, bool>,
+    pub properties: Table,
+}
+
+impl ServerProperties {
+    pub fn from_table(table: &Table) -> Self {
+        let mut capabilities = HashMap::new();
+        if let Some(TableEntry::FieldTable(caps)) = table.get("capabilities") {
+            for (name, value) in caps {
+                if let TableEntry::Bool(enabled) = *value {
+                    capabilities.insert(name.clone(), enabled);
+                }
+            }
+        }
+        ServerProperties {
+            product: string_property(table, "product"),
+            version: string_property(table, "version"),
+            platform: string_property(table, "platform"),
+            cluster_name: string_property(table, "cluster_name"),
+            capabilities,
+            properties: table.clone(),
+        }
+    }
+
+    pub fn from_start(start: &connection::Start) -> Self {
+        ServerProperties::from_table(&start.server_properties)
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.supports_name(capability.name())
+    }
+
+    /// Checks a capability by its wire name, including ones not covered by `Capability`.
+    pub fn supports_name(&self, name: &str) -> bool {
+        self.capabilities.get(name).cloned().unwrap_or(false)
+    }
+
+    pub fn supported_capabilities(&self) -> Vec<Capability> {
+        ALL_CAPABILITIES.iter().cloned().filter(|c| self.supports(*c)).collect()
+    }
+}
+
+fn string_property(table: &Table, name: &str) -> Option<String> {
+    match table.get(name) {
+        Some(TableEntry::LongString(value)) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::table::{Table, TableEntry, decode_table, encode_table};
+    use super::*;
+
+    #[test]
+    fn test_client_properties_table() {
+        let table = ClientProperties::new()
+            .connection_name("test")
+            .capability(Capability::BasicNack, false)
+            .to_table();
+        assert_eq!(table["product"], TableEntry::LongString("amq-proto".to_string()));
+        assert_eq!(table["version"], TableEntry::LongString(VERSION.to_string()));
+        assert_eq!(table["connection_name"],
+                   TableEntry::LongString("test".to_string()));
+        match table["capabilities"] {
+            TableEntry::FieldTable(ref caps) => {
+                assert_eq!(caps.len(), 5);
+                assert_eq!(caps["consumer_cancel_notify"], TableEntry::Bool(true));
+                assert!(!caps.contains_key("basic.nack"));
+            }
+            ref other => panic!("Unexpected capabilities entry: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_server_properties_capabilities() {
+        let mut caps = Table::new();
+        caps.insert("publisher_confirms".to_string(), TableEntry::Bool(true));
+        caps.insert("basic.nack".to_string(), TableEntry::Bool(false));
+        caps.insert("per_consumer_qos".to_string(), TableEntry::Bool(true));
+        let mut table = Table::new();
+        table.insert("product".to_string(),
+                     TableEntry::LongString("RabbitMQ".to_string()));
+        table.insert("capabilities".to_string(), TableEntry::FieldTable(caps));
+
+        // Go through the wire format to make sure booleans survive decoding.
+        let mut encoded = vec![];
+        encode_table(&mut encoded, &table).unwrap();
+        let (decoded, _) = decode_table(&mut Cursor::new(encoded)).unwrap();
+
+        let server = ServerProperties::from_table(&decoded);
+        assert_eq!(server.product, Some("RabbitMQ".to_string()));
+        assert!(server.supports(Capability::PublisherConfirms));
+        assert!(!server.supports(Capability::BasicNack));
+        assert!(!server.supports(Capability::ConnectionBlocked));
+        assert!(server.supports_name("per_consumer_qos"));
+        assert_eq!(ClientProperties::new().negotiated(&server),
+                   vec![Capability::PublisherConfirms]);
+    }
+}
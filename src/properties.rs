@@ -0,0 +1,260 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::protocol::basic::BasicProperties;
+use crate::error::*;
+
+/// The highest priority of a message.
+pub const MAX_PRIORITY: u8 = 9;
+
+/// Whether the broker keeps a message on disk, the `delivery_mode` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeliveryMode {
+    Transient = 1,
+    Persistent = 2,
+}
+
+impl DeliveryMode {
+    pub fn from_u8(value: u8) -> Option<DeliveryMode> {
+        match value {
+            1 => Some(DeliveryMode::Transient),
+            2 => Some(DeliveryMode::Persistent),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed `content_type` property, e.g. `text/plain; charset=utf-8`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    /// The lowercase type and subtype, e.g. `text/plain`.
+    pub mime_type: String,
+    /// The parameters in order, with lowercase names and unquoted values.
+    pub parameters: Vec<(String, String)>,
+}
+
+impl ContentType {
+    pub fn new(mime_type: &str) -> ContentType {
+        ContentType {
+            mime_type: mime_type.to_lowercase(),
+            parameters: vec![],
+        }
+    }
+
+    pub fn with_charset(mut self, charset: &str) -> ContentType {
+        self.parameters.retain(|(name, _)| name != "charset");
+        self.parameters.push(("charset".to_string(), charset.to_string()));
+        self
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.parameters.iter().find(|parameter| parameter.0 == name).map(|parameter| &parameter.1[..])
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.parameter("charset")
+    }
+}
+
+impl FromStr for ContentType {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<ContentType> {
+        let invalid = || ErrorKind::InvalidProperty(format!("invalid content type '{}'", value));
+        let mut parts = value.split(';');
+        let mime_type = parts.next().unwrap_or("").trim();
+        match mime_type.split_once('/') {
+            Some((kind, subtype)) if !kind.is_empty() && !subtype.is_empty() &&
+                                     !subtype.contains('/') => {}
+            _ => return Err(invalid().into()),
+        }
+        let mut parameters = vec![];
+        for parameter in parts.map(str::trim).filter(|parameter| !parameter.is_empty()) {
+            let (name, value) = parameter.split_once('=').ok_or_else(invalid)?;
+            let value = value.trim();
+            let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                &value[1..value.len() - 1]
+            } else {
+                value
+            };
+            parameters.push((name.trim().to_lowercase(), value.to_string()));
+        }
+        Ok(ContentType {
+            mime_type: mime_type.to_lowercase(),
+            parameters,
+        })
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.mime_type)?;
+        for (name, value) in &self.parameters {
+            if value.is_empty() || value.contains([';', ' ', '"']) {
+                write!(f, "; {}=\"{}\"", name, value)?;
+            } else {
+                write!(f, "; {}={}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Typed access to the properties. The getters fail on raw values which are set but
+/// invalid, the raw fields are kept as they are for round-tripping.
+impl BasicProperties {
+    pub fn delivery_mode(&self) -> Result<Option<DeliveryMode>> {
+        self.delivery_mode
+            .map(|mode| {
+                DeliveryMode::from_u8(mode).ok_or_else(|| {
+                    ErrorKind::InvalidProperty(format!("invalid delivery mode {}", mode)).into()
+                })
+            })
+            .transpose()
+    }
+
+    pub fn set_delivery_mode(&mut self, mode: DeliveryMode) {
+        self.delivery_mode = Some(mode as u8);
+    }
+
+    /// Whether the message is persistent, false if the delivery mode isn't set or invalid.
+    pub fn is_persistent(&self) -> bool {
+        self.delivery_mode == Some(DeliveryMode::Persistent as u8)
+    }
+
+    pub fn priority(&self) -> Result<Option<u8>> {
+        match self.priority {
+            Some(priority) if priority > MAX_PRIORITY => {
+                Err(ErrorKind::InvalidProperty(format!("priority {} is above {}", priority, MAX_PRIORITY))
+                    .into())
+            }
+            priority => Ok(priority),
+        }
+    }
+
+    pub fn set_priority(&mut self, priority: u8) -> Result<()> {
+        if priority > MAX_PRIORITY {
+            return Err(ErrorKind::InvalidProperty(format!("priority {} is above {}",
+                                                          priority,
+                                                          MAX_PRIORITY))
+                .into());
+        }
+        self.priority = Some(priority);
+        Ok(())
+    }
+
+    /// The per-message TTL, which RabbitMQ expects as a number of milliseconds.
+    pub fn expiration(&self) -> Result<Option<Duration>> {
+        self.expiration
+            .as_ref()
+            .map(|expiration| {
+                expiration.parse::<u64>().map(Duration::from_millis).map_err(|_| {
+                    ErrorKind::InvalidProperty(format!("invalid expiration '{}'", expiration)).into()
+                })
+            })
+            .transpose()
+    }
+
+    /// Sets the expiration in whole milliseconds, rounding down.
+    pub fn set_expiration(&mut self, ttl: Duration) {
+        self.expiration = Some(ttl.as_millis().to_string());
+    }
+
+    /// The timestamp, in whole seconds since the epoch on the wire. Fails for
+    /// timestamps too far in the future to be a `SystemTime`.
+    pub fn timestamp(&self) -> Result<Option<SystemTime>> {
+        self.timestamp
+            .map(|seconds| {
+                UNIX_EPOCH.checked_add(Duration::from_secs(seconds)).ok_or_else(|| {
+                    ErrorKind::InvalidProperty(format!("timestamp {} is out of range", seconds)).into()
+                })
+            })
+            .transpose()
+    }
+
+    /// Sets the timestamp, rounding down to seconds. Times before the epoch are sent as 0.
+    pub fn set_timestamp(&mut self, time: SystemTime) {
+        self.timestamp = Some(time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()));
+    }
+
+    pub fn content_type(&self) -> Result<Option<ContentType>> {
+        self.content_type.as_ref().map(|content_type| content_type.parse()).transpose()
+    }
+
+    pub fn set_content_type(&mut self, content_type: &ContentType) {
+        self.content_type = Some(content_type.to_string());
+    }
+
+    /// The `charset` parameter of the content type.
+    pub fn charset(&self) -> Result<Option<String>> {
+        Ok(self.content_type()?.and_then(|content_type| content_type.charset().map(str::to_string)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::protocol::basic::BasicProperties;
+    use super::*;
+
+    #[test]
+    fn test_typed_properties() {
+        let mut properties = BasicProperties::default();
+        assert_eq!(properties.delivery_mode().unwrap(), None);
+        assert_eq!(properties.expiration().unwrap(), None);
+        assert_eq!(properties.timestamp().unwrap(), None);
+
+        properties.set_delivery_mode(DeliveryMode::Persistent);
+        properties.set_priority(9).unwrap();
+        assert!(properties.set_priority(10).is_err());
+        properties.set_expiration(Duration::from_millis(60500));
+        properties.set_timestamp(UNIX_EPOCH + Duration::new(1_500_000_000, 999));
+        properties.set_content_type(&ContentType::new("Text/Plain").with_charset("utf-8"));
+        assert_eq!(properties,
+                   BasicProperties {
+                       delivery_mode: Some(2),
+                       priority: Some(9),
+                       expiration: Some("60500".to_string()),
+                       timestamp: Some(1_500_000_000),
+                       content_type: Some("text/plain; charset=utf-8".to_string()),
+                       ..Default::default()
+                   });
+        assert_eq!(properties.delivery_mode().unwrap(), Some(DeliveryMode::Persistent));
+        assert!(properties.is_persistent());
+        assert_eq!(properties.priority().unwrap(), Some(9));
+        assert_eq!(properties.expiration().unwrap(), Some(Duration::from_millis(60500)));
+        assert_eq!(properties.timestamp().unwrap(),
+                   Some(UNIX_EPOCH + Duration::from_secs(1_500_000_000)));
+        assert_eq!(properties.charset().unwrap(), Some("utf-8".to_string()));
+
+        let invalid = BasicProperties {
+            delivery_mode: Some(3),
+            priority: Some(200),
+            expiration: Some("soon".to_string()),
+            timestamp: Some(u64::MAX),
+            content_type: Some("json".to_string()),
+            ..Default::default()
+        };
+        assert!(invalid.delivery_mode().is_err());
+        assert!(!invalid.is_persistent());
+        assert!(invalid.priority().is_err());
+        assert!(invalid.expiration().is_err());
+        assert!(invalid.timestamp().is_err());
+        assert!(invalid.content_type().is_err());
+    }
+
+    #[test]
+    fn test_content_type() {
+        let content_type: ContentType = "application/JSON ; Charset=\"UTF-8\"; q=1".parse().unwrap();
+        assert_eq!(content_type.mime_type, "application/json");
+        assert_eq!(content_type.charset(), Some("UTF-8"));
+        assert_eq!(content_type.parameter("Q"), Some("1"));
+        assert_eq!(content_type.to_string(), "application/json; charset=UTF-8; q=1");
+        assert_eq!(ContentType::new("text/plain").charset(), None);
+        for invalid in &["", "text", "/plain", "text/", "a/b/c", "text/plain; charset"] {
+            assert!(invalid.parse::<ContentType>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Metadata about the classes, methods and fields of the spec, generated along with
+//! the protocol, for tools which handle methods generically.
+//!
+//! ```
+//! use amq_proto::reflection::{self, WireType};
+//!
+//! let declare = reflection::method_by_name("queue.declare").unwrap();
+//! assert_eq!((declare.class_id, declare.id), (50, 10));
+//! assert_eq!(declare.fields[1].name, "queue");
+//! assert_eq!(declare.fields[1].wire_type, WireType::ShortStr);
+//! assert_eq!(reflection::class(50).unwrap().name, "queue");
+//! ```
+
+use std::fmt;
+
+pub use crate::protocol::CLASSES;
+
+/// The type of a field on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WireType {
+    Bit,
+    Octet,
+    Short,
+    Long,
+    LongLong,
+    ShortStr,
+    LongStr,
+    Table,
+    Timestamp,
+}
+
+impl WireType {
+    /// The name used by the spec, e.g. `longstr`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            WireType::Bit => "bit",
+            WireType::Octet => "octet",
+            WireType::Short => "short",
+            WireType::Long => "long",
+            WireType::LongLong => "longlong",
+            WireType::ShortStr => "shortstr",
+            WireType::LongStr => "longstr",
+            WireType::Table => "table",
+            WireType::Timestamp => "timestamp",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<WireType> {
+        let wire_type = match name {
+            "bit" => WireType::Bit,
+            "octet" => WireType::Octet,
+            "short" => WireType::Short,
+            "long" => WireType::Long,
+            "longlong" => WireType::LongLong,
+            "shortstr" => WireType::ShortStr,
+            "longstr" => WireType::LongStr,
+            "table" => WireType::Table,
+            "timestamp" => WireType::Timestamp,
+            _ => return None,
+        };
+        Some(wire_type)
+    }
+}
+
+impl fmt::Display for WireType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A default value from the spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultValue {
+    Bit(bool),
+    Number(u64),
+    Str(&'static str),
+    EmptyTable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldInfo {
+    /// The name of the struct field, e.g. `consumer_tag`.
+    pub name: &'static str,
+    /// The name in the spec, e.g. `consumer-tag`.
+    pub spec_name: &'static str,
+    pub wire_type: WireType,
+    pub default: Option<DefaultValue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MethodInfo {
+    pub class_id: u16,
+    pub id: u16,
+    /// e.g. `declare`.
+    pub name: &'static str,
+    /// e.g. `queue.declare`, as returned by `MethodFrame::method_name`.
+    pub full_name: &'static str,
+    pub synchronous: bool,
+    /// Whether the method is followed by a content header and body.
+    pub content: bool,
+    /// The arguments in wire order.
+    pub fields: &'static [FieldInfo],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClassInfo {
+    pub id: u16,
+    pub name: &'static str,
+    /// The content properties, in the order of the property flags.
+    pub properties: &'static [FieldInfo],
+    pub methods: &'static [MethodInfo],
+}
+
+impl ClassInfo {
+    pub fn method(&self, method_id: u16) -> Option<&'static MethodInfo> {
+        self.methods.iter().find(|method| method.id == method_id)
+    }
+
+    /// Looks up a method by its name without the class, e.g. `declare`.
+    pub fn method_by_name(&self, name: &str) -> Option<&'static MethodInfo> {
+        self.methods.iter().find(|method| method.name == name)
+    }
+}
+
+impl MethodInfo {
+    pub fn class(&self) -> &'static ClassInfo {
+        class(self.class_id).expect("methods belong to a known class")
+    }
+
+    pub fn field(&self, name: &str) -> Option<&'static FieldInfo> {
+        self.fields.iter().find(|field| field.name == name || field.spec_name == name)
+    }
+}
+
+pub fn class(class_id: u16) -> Option<&'static ClassInfo> {
+    CLASSES.iter().find(|class| class.id == class_id)
+}
+
+pub fn class_by_name(name: &str) -> Option<&'static ClassInfo> {
+    CLASSES.iter().find(|class| class.name == name)
+}
+
+pub fn method(class_id: u16, method_id: u16) -> Option<&'static MethodInfo> {
+    class(class_id).and_then(|class| class.method(method_id))
+}
+
+/// Looks up a method by its full name, e.g. `basic.publish`.
+pub fn method_by_name(full_name: &str) -> Option<&'static MethodInfo> {
+    let mut parts = full_name.splitn(2, '.');
+    let class = class_by_name(parts.next()?)?;
+    class.method_by_name(parts.next()?)
+}
+
+/// The class and method id of a method, e.g. `(60, 40)` for `basic.publish`.
+pub fn method_ids(full_name: &str) -> Option<(u16, u16)> {
+    method_by_name(full_name).map(|method| (method.class_id, method.id))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::framing::MethodFrame;
+    use crate::method::Method;
+    use crate::protocol::basic;
+    use super::*;
+
+    #[test]
+    fn test_metadata_matches_the_protocol() {
+        for class in CLASSES {
+            for method in class.methods {
+                let frame = MethodFrame {
+                    class_id: class.id,
+                    method_id: method.id,
+                    arguments: crate::method::EncodedMethod::new(vec![]),
+                };
+                assert_eq!(frame.method_name(), method.full_name);
+                assert_eq!(frame.carries_content(), method.content);
+                assert_eq!(frame.is_synchronous(), method.synchronous);
+                assert_eq!(method_ids(method.full_name), Some((class.id, method.id)));
+                assert_eq!(method.class().name, class.name);
+                assert_eq!(frame.info(), Some(method));
+            }
+        }
+        let publish = method_by_name("basic.publish").unwrap();
+        assert!(publish.content && !publish.synchronous);
+        assert_eq!((publish.class_id, publish.id), (basic::Publish::CLASS_ID, basic::Publish::ID));
+        assert_eq!(publish.field("routing-key").unwrap().name, "routing_key");
+        assert_eq!(publish.field("mandatory").unwrap().default, Some(DefaultValue::Bit(false)));
+        assert_eq!(method_by_name("exchange.declare").unwrap().field("_type").unwrap().default,
+                   Some(DefaultValue::Str("direct")));
+        assert_eq!(class_by_name("basic").unwrap().properties.len(), 14);
+        assert_eq!(class_by_name("basic").unwrap().properties[3].wire_type, WireType::Octet);
+        assert!(method_by_name("basic").is_none());
+        assert!(method_by_name("basic.nope").is_none());
+        assert!(method(60, 999).is_none());
+        assert_eq!(WireType::from_name("longlong"), Some(WireType::LongLong));
+    }
+}
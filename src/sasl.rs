@@ -0,0 +1,235 @@
+use crate::table::{Table, TableEntry, encode_table};
+use crate::protocol::connection;
+use crate::error::*;
+
+/// A SASL mechanism used to authenticate in `connection.start-ok`
+/// and answer `connection.secure` challenges.
+pub trait SaslMechanism: Send {
+    /// Mechanism name as advertised in `connection.start`'s `mechanisms`.
+    fn name(&self) -> &'static str;
+
+    /// The `response` field of `connection.start-ok`.
+    fn initial_response(&mut self) -> Result<String>;
+
+    /// The `response` field of `connection.secure-ok` for a server challenge.
+    fn challenge(&mut self, _challenge: &str) -> Result<String> {
+        Err(ErrorKind::Authentication(format!("{} does not support challenges", self.name()))
+            .into())
+    }
+
+    fn secure_ok(&mut self, secure: &connection::Secure) -> Result<connection::SecureOk> {
+        Ok(connection::SecureOk { response: self.challenge(&secure.challenge)? })
+    }
+}
+
+/// RFC 4616 PLAIN: `\0username\0password`.
+#[derive(Debug, Clone)]
+pub struct Plain {
+    pub username: String,
+    pub password: String,
+}
+
+impl Plain {
+    pub fn new(username: &str, password: &str) -> Self {
+        Plain {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+}
+
+impl SaslMechanism for Plain {
+    fn name(&self) -> &'static str {
+        "PLAIN"
+    }
+
+    fn initial_response(&mut self) -> Result<String> {
+        Ok(format!("\0{}\0{}", self.username, self.password))
+    }
+}
+
+/// AMQPLAIN: the credentials as a field table of `LOGIN` and `PASSWORD`,
+/// without the leading table length.
+///
+/// The username and password are limited to 127 bytes each: the response is
+/// a `String`, and a longer value's length would contain a byte of 0x80 or
+/// more, which isn't valid UTF-8.
+#[derive(Debug, Clone)]
+pub struct AmqPlain {
+    pub username: String,
+    pub password: String,
+}
+
+impl AmqPlain {
+    pub fn new(username: &str, password: &str) -> Self {
+        AmqPlain {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+}
+
+impl SaslMechanism for AmqPlain {
+    fn name(&self) -> &'static str {
+        "AMQPLAIN"
+    }
+
+    fn initial_response(&mut self) -> Result<String> {
+        for &(name, value) in &[("username", &self.username), ("password", &self.password)] {
+            if value.len() > 127 {
+                return Err(ErrorKind::Authentication(format!("AMQPLAIN {} is {} bytes, but at most 127 can be encoded",
+                                                             name,
+                                                             value.len()))
+                    .into());
+            }
+        }
+        let mut table = Table::new();
+        table.insert("LOGIN".to_string(),
+                     TableEntry::LongString(self.username.clone()));
+        table.insert("PASSWORD".to_string(),
+                     TableEntry::LongString(self.password.clone()));
+        let mut encoded = vec![];
+        encode_table(&mut encoded, &table)?;
+        encoded.drain(..4);
+        // Every length byte is below 0x80, so the encoded table is valid UTF-8.
+        Ok(String::from_utf8(encoded).expect("AMQPLAIN response is valid UTF-8"))
+    }
+}
+
+/// EXTERNAL: the identity comes from outside of AMQP, usually a TLS client certificate.
+#[derive(Debug, Clone, Default)]
+pub struct External;
+
+impl SaslMechanism for External {
+    fn name(&self) -> &'static str {
+        "EXTERNAL"
+    }
+
+    fn initial_response(&mut self) -> Result<String> {
+        Ok(String::new())
+    }
+}
+
+/// RabbitMQ's `RABBIT-CR-DEMO` challenge/response mechanism: the username is sent
+/// in `connection.start-ok` and the password in reply to a `connection.secure` challenge.
+#[derive(Debug, Clone)]
+pub struct RabbitCrDemo {
+    pub username: String,
+    pub password: String,
+}
+
+impl RabbitCrDemo {
+    pub fn new(username: &str, password: &str) -> Self {
+        RabbitCrDemo {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+}
+
+impl SaslMechanism for RabbitCrDemo {
+    fn name(&self) -> &'static str {
+        "RABBIT-CR-DEMO"
+    }
+
+    fn initial_response(&mut self) -> Result<String> {
+        Ok(self.username.clone())
+    }
+
+    fn challenge(&mut self, challenge: &str) -> Result<String> {
+        if challenge == "Please tell me your password" {
+            Ok(format!("My password is {}", self.password))
+        } else {
+            Err(ErrorKind::Authentication(format!("Unexpected challenge: '{}'", challenge))
+                .into())
+        }
+    }
+}
+
+/// Splits the space-separated `mechanisms` field of `connection.start`.
+pub fn server_mechanisms(mechanisms: &str) -> Vec<&str> {
+    mechanisms.split_whitespace().collect()
+}
+
+/// Picks the first of `candidates` (in order of preference) that the server supports.
+pub fn negotiate(mechanisms: &str,
+                 candidates: Vec<Box<dyn SaslMechanism>>)
+                 -> Result<Box<dyn SaslMechanism>> {
+    let supported = server_mechanisms(mechanisms);
+    let mut names = vec![];
+    for candidate in candidates {
+        if supported.contains(&candidate.name()) {
+            return Ok(candidate);
+        }
+        names.push(candidate.name());
+    }
+    Err(ErrorKind::Authentication(format!("No common mechanism, server supports '{}', \
+                                           client supports '{}'",
+                                          mechanisms,
+                                          names.join(" ")))
+        .into())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use byteorder::{BigEndian, WriteBytesExt};
+    use crate::table::{TableEntry, decode_table};
+    use crate::protocol::connection;
+    use super::*;
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(Plain::new("guest", "secret").initial_response().unwrap(),
+                   "\0guest\0secret");
+    }
+
+    #[test]
+    fn test_amqplain_is_a_table_without_length() {
+        let response = AmqPlain::new("guest", "secret").initial_response().unwrap();
+        let mut encoded = vec![];
+        encoded.write_u32::<BigEndian>(response.len() as u32).unwrap();
+        encoded.extend_from_slice(response.as_bytes());
+        let (table, _) = decode_table(&mut Cursor::new(encoded)).unwrap();
+        assert_eq!(table["LOGIN"], TableEntry::LongString("guest".to_string()));
+        assert_eq!(table["PASSWORD"], TableEntry::LongString("secret".to_string()));
+    }
+
+    #[test]
+    fn test_amqplain_length_limit() {
+        let longest = "x".repeat(127);
+        let response = AmqPlain::new(&longest, &longest).initial_response().unwrap();
+        let mut encoded = vec![];
+        encoded.write_u32::<BigEndian>(response.len() as u32).unwrap();
+        encoded.extend_from_slice(response.as_bytes());
+        let (table, _) = decode_table(&mut Cursor::new(encoded)).unwrap();
+        assert_eq!(table["LOGIN"], TableEntry::LongString(longest.clone()));
+        assert_eq!(table["PASSWORD"], TableEntry::LongString(longest));
+
+        let too_long = "x".repeat(128);
+        let err = AmqPlain::new(&too_long, "secret").initial_response().unwrap_err();
+        assert_eq!(err.to_string(),
+                   "authentication error: 'AMQPLAIN username is 128 bytes, but at most 127 can be encoded'");
+        assert!(AmqPlain::new("guest", &too_long).initial_response().is_err());
+    }
+
+    #[test]
+    fn test_rabbit_cr_demo() {
+        let mut mechanism = RabbitCrDemo::new("guest", "secret");
+        assert_eq!(mechanism.initial_response().unwrap(), "guest");
+        let secure = connection::Secure { challenge: "Please tell me your password".to_string() };
+        assert_eq!(mechanism.secure_ok(&secure).unwrap(),
+                   connection::SecureOk { response: "My password is secret".to_string() });
+        assert!(Plain::new("guest", "secret").challenge("anything").is_err());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let candidates: Vec<Box<dyn SaslMechanism>> = vec![Box::new(External),
+                                                           Box::new(AmqPlain::new("a", "b")),
+                                                           Box::new(Plain::new("a", "b"))];
+        let mechanism = negotiate("PLAIN AMQPLAIN", candidates).unwrap();
+        assert_eq!(mechanism.name(), "AMQPLAIN");
+        assert!(negotiate("PLAIN", vec![Box::new(External)]).is_err());
+    }
+}
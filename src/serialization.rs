@@ -0,0 +1,147 @@
+//! `serde` support for the raw byte newtypes, which are written as lowercase hex
+//! strings. Everything else derives its representation.
+//!
+//! Frames keep their encoded payload, so they re-encode byte for byte. Methods and
+//! properties encode the same after a round trip: `Table` is a `HashMap`, but
+//! `encode_table` writes the entries sorted by name. A table received from a peer in
+//! another order still re-encodes sorted.
+
+use std::fmt::Write;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+use crate::framing::{EncodedProperties, FramePayload};
+use crate::method::EncodedMethod;
+
+fn to_hex(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(data.len() * 2);
+    for byte in data {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+fn from_hex<E: de::Error>(hex: &str) -> Result<Vec<u8>, E> {
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return Err(E::custom("invalid hex string"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| E::custom("invalid hex string"))
+}
+
+macro_rules! hex_bytes {
+    ($($ty:ident),*) => ($(
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&to_hex(self.inner()))
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let hex = String::deserialize(deserializer)?;
+                from_hex(&hex).map($ty::new)
+            }
+        }
+    )*)
+}
+
+hex_bytes!(FramePayload, EncodedMethod, EncodedProperties);
+
+#[cfg(test)]
+mod test {
+    use crate::framing::{ContentHeaderFrame, EncodedProperties, Frame};
+    use crate::method::Method;
+    use crate::protocol::{basic, exchange};
+    use crate::table::{Table, TableEntry};
+
+    #[test]
+    fn test_table_entries_keep_their_type() {
+        let mut table = Table::new();
+        table.insert("short".to_string(), TableEntry::ShortInt(5));
+        table.insert("long".to_string(), TableEntry::LongInt(5));
+        table.insert("decimal".to_string(), TableEntry::DecimalValue(2, 500));
+        table.insert("nested".to_string(),
+                     TableEntry::FieldArray(vec![TableEntry::Void, TableEntry::Bool(true)]));
+        assert_eq!(serde_json::to_string(&TableEntry::ShortInt(5)).unwrap(),
+                   r#"{"type":"ShortInt","value":5}"#);
+        assert_eq!(serde_json::to_string(&TableEntry::Void).unwrap(), r#"{"type":"Void"}"#);
+        let json = serde_json::to_string(&table).unwrap();
+        assert_eq!(serde_json::from_str::<Table>(&json).unwrap(), table);
+    }
+
+    #[test]
+    fn test_methods_and_properties() {
+        let mut arguments = Table::new();
+        arguments.insert("alternate-exchange".to_string(),
+                         TableEntry::LongString("ae".to_string()));
+        let declare = exchange::Declare {
+            exchange: "logs".to_string(),
+            _type: "topic".to_string(),
+            durable: true,
+            arguments,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&declare).unwrap();
+        assert_eq!(serde_json::from_str::<exchange::Declare>(&json).unwrap(), declare);
+
+        let properties = basic::BasicProperties {
+            content_type: Some("text/plain".to_string()),
+            delivery_mode: Some(2),
+            ..Default::default()
+        };
+        // Unset properties are left out.
+        assert_eq!(serde_json::to_string(&properties).unwrap(),
+                   r#"{"content_type":"text/plain","delivery_mode":2}"#);
+        assert_eq!(serde_json::from_str::<basic::BasicProperties>(r#"{"priority":1}"#).unwrap(),
+                   basic::BasicProperties { priority: Some(1), ..Default::default() });
+    }
+
+    #[test]
+    fn test_tables_round_trip_byte_for_byte() {
+        let mut arguments = Table::new();
+        for (idx, name) in ["x-queue-type", "x-max-length", "x-dead-letter-exchange", "x-expires",
+                            "x-message-ttl", "x-overflow", "x-single-active-consumer"]
+            .iter()
+            .enumerate() {
+            arguments.insert(name.to_string(), TableEntry::LongInt(idx as i32));
+        }
+        let declare = exchange::Declare { exchange: "logs".to_string(), arguments, ..Default::default() };
+        let encoded = declare.encode().unwrap();
+        // Every deserialized `HashMap` has its own random order.
+        for _ in 0..4 {
+            let json = serde_json::to_string(&declare).unwrap();
+            let decoded: exchange::Declare = serde_json::from_str(&json).unwrap();
+            assert_eq!(decoded.encode().unwrap(), encoded);
+        }
+    }
+
+    #[test]
+    fn test_frames_round_trip_byte_for_byte() {
+        let frame = basic::Ack { delivery_tag: 7, multiple: true }.to_frame(3).unwrap();
+        let json = serde_json::to_string(&frame).unwrap();
+        assert_eq!(json,
+                   r#"{"frame_type":"METHOD","channel":3,"payload":"003c0050000000000000000701"}"#);
+        let decoded: Frame = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.encode().unwrap(), frame.encode().unwrap());
+
+        let properties = basic::BasicProperties { priority: Some(1), ..Default::default() };
+        let header = ContentHeaderFrame {
+            content_class: 60,
+            weight: 0,
+            body_size: 3,
+            properties_flags: properties.flags(),
+            properties: EncodedProperties::new(properties.encode().unwrap()),
+        };
+        let json = serde_json::to_string(&header).unwrap();
+        let decoded: ContentHeaderFrame = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.encode().unwrap(), header.encode().unwrap());
+
+        assert!(serde_json::from_str::<Frame>(
+            r#"{"frame_type":"BODY","channel":1,"payload":"abc"}"#).is_err());
+    }
+}
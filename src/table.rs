@@ -1,9 +1,14 @@
 use std::collections::HashMap;
-use error::*;
-use std::io::{Read, Write};
+use crate::error::*;
+use crate::framing::DecodeLimits;
+use std::io::{self, Read, Write};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
+/// With the `serde` feature, entries are tagged with their type,
+/// e.g. `{"type":"ShortInt","value":5}`, so they keep it through a round trip.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "value"))]
 pub enum TableEntry {
     Bool(bool),
     ShortShortInt(i8),
@@ -37,25 +42,90 @@ impl Init for Table {
     }
 }
 
-fn read_table_entry<T>(reader: &mut T) -> Result<(TableEntry, usize)>
+/// Keeps track of the `DecodeLimits` while decoding tables, the tables and arrays
+/// nested in them and, for `ArgumentsReader`, the strings next to them.
+#[derive(Debug, Clone)]
+pub(crate) struct DecodeBudget {
+    limits: DecodeLimits,
+    depth: usize,
+    /// The bytes of tables and strings decoded so far.
+    decoded: usize,
+}
+
+impl DecodeBudget {
+    pub fn new(limits: DecodeLimits) -> Self {
+        DecodeBudget {
+            limits,
+            depth: 0,
+            decoded: 0,
+        }
+    }
+
+    /// Accounts for `bytes` about to be decoded.
+    pub fn charge(&mut self, bytes: usize) -> Result<()> {
+        self.decoded = self.decoded.saturating_add(bytes);
+        if self.decoded > self.limits.max_message_bytes {
+            return Err(ErrorKind::LimitExceeded(format!("more than {} bytes of tables and strings",
+                                                        self.limits.max_message_bytes))
+                .into());
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > self.limits.max_table_depth {
+            return Err(ErrorKind::LimitExceeded(format!("tables nested deeper than {}",
+                                                        self.limits.max_table_depth))
+                .into());
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn check_entries(&self, entries: usize) -> Result<()> {
+        if entries > self.limits.max_table_entries {
+            return Err(ErrorKind::LimitExceeded(format!("more than {} table or array entries",
+                                                        self.limits.max_table_entries))
+                .into());
+        }
+        Ok(())
+    }
+
+    /// Reads `size` bytes, allocating as they arrive rather than up front.
+    pub fn read_bytes<T: Read>(&mut self, reader: &mut T, size: usize) -> Result<Vec<u8>> {
+        self.charge(size)?;
+        let mut buffer = Vec::new();
+        reader.take(size as u64).read_to_end(&mut buffer)?;
+        if buffer.len() < size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated string").into());
+        }
+        Ok(buffer)
+    }
+}
+
+fn read_table_entry<T>(reader: &mut T, budget: &mut DecodeBudget) -> Result<(TableEntry, usize)>
     where T: Read
 {
-    let (entry, entry_size) = match try!(reader.read_u8()) {
-        b't' => (TableEntry::Bool(!try!(reader.read_u8()) != 0), 1),
-        b'b' => (TableEntry::ShortShortInt(try!(reader.read_i8())), 1),
-        b'B' => (TableEntry::ShortShortUint(try!(reader.read_u8())), 1),
-        b'U' => (TableEntry::ShortInt(try!(reader.read_i16::<BigEndian>())), 2),
-        b'u' => (TableEntry::ShortUint(try!(reader.read_u16::<BigEndian>())), 2),
-        b'I' => (TableEntry::LongInt(try!(reader.read_i32::<BigEndian>())), 4),
-        b'i' => (TableEntry::LongUint(try!(reader.read_u32::<BigEndian>())), 4),
-        b'L' => (TableEntry::LongLongInt(try!(reader.read_i64::<BigEndian>())), 8),
-        b'l' => (TableEntry::LongLongUint(try!(reader.read_u64::<BigEndian>())), 8),
-        b'f' => (TableEntry::Float(try!(reader.read_f32::<BigEndian>())), 4),
-        b'd' => (TableEntry::Double(try!(reader.read_f64::<BigEndian>())), 8),
+    let (entry, entry_size) = match reader.read_u8()? {
+        b't' => (TableEntry::Bool(reader.read_u8()? != 0), 1),
+        b'b' => (TableEntry::ShortShortInt(reader.read_i8()?), 1),
+        b'B' => (TableEntry::ShortShortUint(reader.read_u8()?), 1),
+        b'U' => (TableEntry::ShortInt(reader.read_i16::<BigEndian>()?), 2),
+        b'u' => (TableEntry::ShortUint(reader.read_u16::<BigEndian>()?), 2),
+        b'I' => (TableEntry::LongInt(reader.read_i32::<BigEndian>()?), 4),
+        b'i' => (TableEntry::LongUint(reader.read_u32::<BigEndian>()?), 4),
+        b'L' => (TableEntry::LongLongInt(reader.read_i64::<BigEndian>()?), 8),
+        b'l' => (TableEntry::LongLongUint(reader.read_u64::<BigEndian>()?), 8),
+        b'f' => (TableEntry::Float(reader.read_f32::<BigEndian>()?), 4),
+        b'd' => (TableEntry::Double(reader.read_f64::<BigEndian>()?), 8),
         b'D' => {
             ({
-                 TableEntry::DecimalValue(try!(reader.read_u8()),
-                                          try!(reader.read_u32::<BigEndian>()))
+                 TableEntry::DecimalValue(reader.read_u8()?,
+                                          reader.read_u32::<BigEndian>()?)
              },
              5)
         }
@@ -66,28 +136,33 @@ fn read_table_entry<T>(reader: &mut T) -> Result<(TableEntry, usize)>
         //  ShortString(str)
         // },
         b'S' => {
-            let size = try!(reader.read_u32::<BigEndian>()) as usize;
-            let mut buffer: Vec<u8> = vec![0u8; size];
-            try!(reader.read(&mut buffer[..]));
+            let size = reader.read_u32::<BigEndian>()? as usize;
+            let buffer = budget.read_bytes(reader, size)?;
             let string = String::from_utf8_lossy(&buffer).to_string();
             let entry = TableEntry::LongString(string);
             (entry, 4 + size)
         }
         b'A' => {
-            let array_len = try!(reader.read_u32::<BigEndian>()) as usize;
+            let array_len = reader.read_u32::<BigEndian>()? as usize;
             let mut read_len = 0;
             let mut arr = Vec::new();
+            budget.enter()?;
             while read_len < array_len {
-                let (entry, entry_len) = try!(read_table_entry(reader));
+                let (entry, entry_len) = read_table_entry(reader, budget)?;
                 read_len += entry_len;
-                arr.push(entry)
+                arr.push(entry);
+                budget.check_entries(arr.len())?;
+            }
+            budget.leave();
+            if read_len > array_len {
+                return Err(ErrorKind::Protocol("Array entries overrun its length".to_string()).into());
             }
             let entry = TableEntry::FieldArray(arr);
             (entry, 4 + array_len)
         }
-        b'T' => (TableEntry::Timestamp(try!(reader.read_u64::<BigEndian>())), 8),
+        b'T' => (TableEntry::Timestamp(reader.read_u64::<BigEndian>()?), 8),
         b'F' => {
-            let (table, table_size) = try!(decode_table(reader));
+            let (table, table_size) = decode_table_within(reader, budget)?;
             let entry = TableEntry::FieldTable(table);
             (entry, table_size)
         }
@@ -103,53 +178,53 @@ fn read_table_entry<T>(reader: &mut T) -> Result<(TableEntry, usize)>
 fn write_table_entry(writer: &mut Vec<u8>, table_entry: &TableEntry) -> Result<()> {
     match *table_entry {
         TableEntry::Bool(val) => {
-            try!(writer.write_u8(b't'));
-            try!(writer.write_u8(val as u8));
+            writer.write_u8(b't')?;
+            writer.write_u8(val as u8)?;
         }
         TableEntry::ShortShortInt(val) => {
-            try!(writer.write_u8(b'b'));
-            try!(writer.write_i8(val));
+            writer.write_u8(b'b')?;
+            writer.write_i8(val)?;
         }
         TableEntry::ShortShortUint(val) => {
-            try!(writer.write_u8(b'B'));
-            try!(writer.write_u8(val));
+            writer.write_u8(b'B')?;
+            writer.write_u8(val)?;
         }
         TableEntry::ShortInt(val) => {
-            try!(writer.write_u8(b'U'));
-            try!(writer.write_i16::<BigEndian>(val));
+            writer.write_u8(b'U')?;
+            writer.write_i16::<BigEndian>(val)?;
         }
         TableEntry::ShortUint(val) => {
-            try!(writer.write_u8(b'u'));
-            try!(writer.write_u16::<BigEndian>(val));
+            writer.write_u8(b'u')?;
+            writer.write_u16::<BigEndian>(val)?;
         }
         TableEntry::LongInt(val) => {
-            try!(writer.write_u8(b'I'));
-            try!(writer.write_i32::<BigEndian>(val));
+            writer.write_u8(b'I')?;
+            writer.write_i32::<BigEndian>(val)?;
         }
         TableEntry::LongUint(val) => {
-            try!(writer.write_u8(b'i'));
-            try!(writer.write_u32::<BigEndian>(val));
+            writer.write_u8(b'i')?;
+            writer.write_u32::<BigEndian>(val)?;
         }
         TableEntry::LongLongInt(val) => {
-            try!(writer.write_u8(b'L'));
-            try!(writer.write_i64::<BigEndian>(val));
+            writer.write_u8(b'L')?;
+            writer.write_i64::<BigEndian>(val)?;
         }
         TableEntry::LongLongUint(val) => {
-            try!(writer.write_u8(b'l'));
-            try!(writer.write_u64::<BigEndian>(val));
+            writer.write_u8(b'l')?;
+            writer.write_u64::<BigEndian>(val)?;
         }
         TableEntry::Float(val) => {
-            try!(writer.write_u8(b'f'));
-            try!(writer.write_f32::<BigEndian>(val));
+            writer.write_u8(b'f')?;
+            writer.write_f32::<BigEndian>(val)?;
         }
         TableEntry::Double(val) => {
-            try!(writer.write_u8(b'd'));
-            try!(writer.write_f64::<BigEndian>(val));
+            writer.write_u8(b'd')?;
+            writer.write_f64::<BigEndian>(val)?;
         }
         TableEntry::DecimalValue(scale, value) => {
-            try!(writer.write_u8(b'D'));
-            try!(writer.write_u8(scale));
-            try!(writer.write_u32::<BigEndian>(value));
+            writer.write_u8(b'D')?;
+            writer.write_u8(scale)?;
+            writer.write_u32::<BigEndian>(value)?;
         }
         // ShortString(str) => {
         //  try!(writer.write_u8(b's'));
@@ -157,68 +232,206 @@ fn write_table_entry(writer: &mut Vec<u8>, table_entry: &TableEntry) -> Result<(
         //  try!(writer.write_all(str.as_bytes()));
         // },
         TableEntry::LongString(ref str) => {
-            try!(writer.write_u8(b'S'));
-            try!(writer.write_u32::<BigEndian>(str.len() as u32));
-            try!(writer.write_all(str.as_bytes()));
+            writer.write_u8(b'S')?;
+            writer.write_u32::<BigEndian>(str.len() as u32)?;
+            writer.write_all(str.as_bytes())?;
         }
         TableEntry::FieldArray(ref arr) => {
-            try!(writer.write_u8(b'A'));
+            writer.write_u8(b'A')?;
             let mut tmp_buffer = vec![];
             for item in arr.iter() {
-                try!(write_table_entry(&mut tmp_buffer, item));
+                write_table_entry(&mut tmp_buffer, item)?;
             }
-            try!(writer.write_u32::<BigEndian>(tmp_buffer.len() as u32));
-            try!(writer.write(&tmp_buffer));
+            writer.write_u32::<BigEndian>(tmp_buffer.len() as u32)?;
+            writer.write_all(&tmp_buffer)?;
         }
         TableEntry::Timestamp(val) => {
-            try!(writer.write_u8(b'T'));
-            try!(writer.write_u64::<BigEndian>(val))
+            writer.write_u8(b'T')?;
+            writer.write_u64::<BigEndian>(val)?
         }
         TableEntry::FieldTable(ref table) => {
-            try!(writer.write_u8(b'F'));
-            try!(encode_table(writer, table));
+            writer.write_u8(b'F')?;
+            encode_table(writer, table)?;
         }
-        TableEntry::Void => try!(writer.write_u8(b'V')),
+        TableEntry::Void => writer.write_u8(b'V')?,
     }
     Ok(())
 }
 
 pub fn decode_table<T>(reader: &mut T) -> Result<(Table, usize)>
     where T: Read
+{
+    decode_table_with_limits(reader, &DecodeLimits::default())
+}
+
+pub fn decode_table_with_limits<T>(reader: &mut T, limits: &DecodeLimits) -> Result<(Table, usize)>
+    where T: Read
+{
+    decode_table_within(reader, &mut DecodeBudget::new(*limits))
+}
+
+pub(crate) fn decode_table_within<T>(reader: &mut T, budget: &mut DecodeBudget) -> Result<(Table, usize)>
+    where T: Read
 {
     let mut table = Table::new();
-    let table_len = try!(reader.read_u32::<BigEndian>()) as usize;
+    let table_len = reader.read_u32::<BigEndian>()? as usize;
     debug!("decoding table, len: {}", table_len);
     let mut bytes_read = 0;
+    let mut entries = 0;
 
+    budget.enter()?;
     while bytes_read < table_len {
-        let field_name_len = try!(reader.read_u8()) as usize;
+        let field_name_len = reader.read_u8()? as usize;
+        budget.charge(field_name_len)?;
         let mut field_name: Vec<u8> = vec![0u8; field_name_len];
-        try!(reader.read(&mut field_name[..]));
-        let (table_entry, table_entry_size) = try!(read_table_entry(reader));
-        let stringified_field_name = String::from_utf8_lossy(&field_name).to_string();
+        reader.read_exact(&mut field_name[..])?;
+        let (table_entry, table_entry_size) = read_table_entry(reader, budget)?;
+        let stringified_field_name = shortstr_lossy(&field_name, "field name")?;
         debug!("Read table entry: {:?}:{} = {:?}",
                stringified_field_name,
                table_entry_size,
                table_entry);
         table.insert(stringified_field_name, table_entry);
+        entries += 1;
+        budget.check_entries(entries)?;
         bytes_read += 1 + field_name_len + table_entry_size; // a byte for length of the field_name
         debug!("bytes_read: {} of {}", bytes_read, table_len);
     }
+    budget.leave();
+    if bytes_read > table_len {
+        return Err(ErrorKind::Protocol("Table entries overrun its length".to_string()).into());
+    }
     debug!("table decoded, table len: {}, bytes_read: {}",
            table_len,
            bytes_read);
     Ok((table, bytes_read + 4)) // 4 bytes for the table_len
 }
 
+/// Decodes a short string the way long strings are decoded, replacing invalid UTF-8.
+/// Each replacement takes up to 3 bytes, so the result is rejected if it no longer
+/// fits the 255 bytes of a short string and couldn't be encoded again.
+pub(crate) fn shortstr_lossy(bytes: &[u8], what: &str) -> Result<String> {
+    let string = String::from_utf8_lossy(bytes).into_owned();
+    if string.len() > 255 {
+        return Err(ErrorKind::Protocol(format!("{} of {} bytes after replacing invalid UTF-8 is \
+                                                too long",
+                                               what,
+                                               string.len()))
+            .into());
+    }
+    Ok(string)
+}
+
+/// Entries are written sorted by name, so equal tables encode the same.
 pub fn encode_table<T: Write>(writer: &mut T, table: &Table) -> Result<()> {
     let mut tmp_buffer = vec![];
-    for (field_name, table_entry) in table.iter() {
-        try!(tmp_buffer.write_u8(field_name.len() as u8));
-        try!(tmp_buffer.write_all(field_name.as_bytes()));
-        try!(write_table_entry(&mut tmp_buffer, table_entry));
+    let mut entries: Vec<_> = table.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    for (field_name, table_entry) in entries {
+        if field_name.len() > u8::MAX as usize {
+            return Err(ErrorKind::Protocol(format!("field name of {} bytes is too long",
+                                                   field_name.len()))
+                .into());
+        }
+        tmp_buffer.write_u8(field_name.len() as u8)?;
+        tmp_buffer.write_all(field_name.as_bytes())?;
+        write_table_entry(&mut tmp_buffer, table_entry)?;
     }
-    try!(writer.write_u32::<BigEndian>(tmp_buffer.len() as u32));
-    try!(writer.write_all(&tmp_buffer));
+    writer.write_u32::<BigEndian>(tmp_buffer.len() as u32)?;
+    writer.write_all(&tmp_buffer)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use super::*;
+
+    fn nested(depth: usize) -> Table {
+        let mut table = Table::new();
+        if depth > 1 {
+            table.insert("t".to_string(), TableEntry::FieldTable(nested(depth - 1)));
+        }
+        table
+    }
+
+    fn encoded(table: &Table) -> Vec<u8> {
+        let mut buffer = vec![];
+        encode_table(&mut buffer, table).unwrap();
+        buffer
+    }
+
+    /// Booleans used to decode inverted, as `!octet != 0`.
+    #[test]
+    fn test_decode_bool() {
+        let data = [0, 0, 0, 12, 1, b'f', b't', 0, 1, b't', b't', 1, 1, b'x', b't', 0xFF];
+        let (table, _) = decode_table(&mut Cursor::new(&data[..])).unwrap();
+        assert_eq!(table["f"], TableEntry::Bool(false));
+        assert_eq!(table["t"], TableEntry::Bool(true));
+        assert_eq!(table["x"], TableEntry::Bool(true));
+        let mut single = Table::new();
+        single.insert("t".to_string(), TableEntry::Bool(true));
+        assert_eq!(encoded(&single), vec![0, 0, 0, 4, 1, b't', b't', 1]);
+    }
+
+    #[test]
+    fn test_decode_edge_cases() {
+        // Any non-zero octet is true.
+        let data = [0, 0, 0, 8, 1, b'a', b't', 2, 1, b'b', b't', 0];
+        let (table, size) = decode_table(&mut Cursor::new(&data[..])).unwrap();
+        assert_eq!(size, 12);
+        assert_eq!(table["a"], TableEntry::Bool(true));
+        assert_eq!(table["b"], TableEntry::Bool(false));
+        // Truncated field names and strings are errors, not short reads.
+        assert!(decode_table(&mut Cursor::new(&[0, 0, 0, 5, 4, b'a', b'b'][..])).is_err());
+        assert!(decode_table(&mut Cursor::new(&[0, 0, 0, 9, 1, b's', b'S', 0, 0, 0, 3, b'x'][..])).is_err());
+        // Invalid UTF-8 in field names is replaced, as long as the name still fits.
+        let data = [0, 0, 0, 4, 2, 0xff, b'a', b'V'];
+        let (table, _) = decode_table(&mut Cursor::new(&data[..])).unwrap();
+        assert_eq!(table["\u{fffd}a"], TableEntry::Void);
+        let mut data = vec![0, 0, 0, 202, 200];
+        data.extend(vec![0xff; 200]);
+        data.push(b'V');
+        assert!(decode_table(&mut Cursor::new(&data[..])).is_err());
+
+        let mut table = Table::new();
+        for name in &["c", "a", "b"] {
+            table.insert(name.to_string(), TableEntry::Void);
+        }
+        assert_eq!(encoded(&table), vec![0, 0, 0, 9, 1, b'a', b'V', 1, b'b', b'V', 1, b'c', b'V']);
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        let limits = DecodeLimits { max_table_depth: 3, max_table_entries: 2, max_message_bytes: 64, ..Default::default() };
+        let decode = |data: &[u8]| decode_table_with_limits(&mut Cursor::new(data), &limits);
+
+        assert_eq!(decode(&encoded(&nested(3))).unwrap().0, nested(3));
+        assert!(decode(&encoded(&nested(4))).is_err());
+        let mut arrays = TableEntry::FieldArray(vec![]);
+        for _ in 0..3 {
+            arrays = TableEntry::FieldArray(vec![arrays]);
+        }
+        let mut table = Table::new();
+        table.insert("a".to_string(), arrays);
+        assert!(decode(&encoded(&table)).is_err());
+
+        let mut table = Table::new();
+        table.insert("a".to_string(), TableEntry::FieldArray(vec![TableEntry::Void; 3]));
+        assert!(decode(&encoded(&table)).is_err());
+        table.insert("a".to_string(), TableEntry::FieldArray(vec![TableEntry::Void; 2]));
+        table.insert("b".to_string(), TableEntry::Void);
+        assert!(decode(&encoded(&table)).is_ok());
+        table.insert("c".to_string(), TableEntry::Void);
+        assert!(decode(&encoded(&table)).is_err());
+
+        let mut table = Table::new();
+        table.insert("s".to_string(), TableEntry::LongString("x".repeat(64)));
+        assert!(decode(&encoded(&table)).is_err());
+        assert!(decode_table(&mut Cursor::new(encoded(&table))).is_ok());
+
+        // A 4 GiB string in a few bytes is cut short by the data, not allocated up front.
+        let huge = [0, 0, 0, 10, 1, b's', b'S', 0xFF, 0xFF, 0xFF, 0xFF, b'x'];
+        assert!(decode_table_with_limits(&mut Cursor::new(&huge[..]), &DecodeLimits::unlimited()).is_err());
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Word {
+    Literal(String),
+    /// `*`, exactly one word.
+    Star,
+    /// `#`, zero or more words.
+    Hash,
+}
+
+/// Splits a routing key or pattern into its dot-separated words.
+/// Like RabbitMQ, the empty string has no words at all.
+fn split(key: &str) -> Vec<&str> {
+    if key.is_empty() {
+        vec![]
+    } else {
+        key.split('.').collect()
+    }
+}
+
+/// A topic exchange binding key, such as `stock.*.nyse` or `logs.#`.
+///
+/// `*` matches exactly one word and `#` zero or more words. Both have to stand
+/// for a whole word, so `a*` or `#b` are rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopicPattern {
+    pattern: String,
+    words: Vec<Word>,
+}
+
+impl TopicPattern {
+    pub fn parse(pattern: &str) -> Result<TopicPattern> {
+        if pattern.len() > 255 {
+            return Err(ErrorKind::InvalidTopicPattern(format!("{} is longer than 255 bytes",
+                                                              pattern))
+                .into());
+        }
+        let mut words = vec![];
+        for word in split(pattern) {
+            words.push(match word {
+                "*" => Word::Star,
+                "#" => Word::Hash,
+                _ if word.contains(['*', '#']) => {
+                    return Err(ErrorKind::InvalidTopicPattern(format!("{}: wildcards have to be \
+                                                                       whole words",
+                                                                      pattern))
+                        .into())
+                }
+                _ => Word::Literal(word.to_string()),
+            });
+        }
+        Ok(TopicPattern {
+            pattern: pattern.to_string(),
+            words,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// True if the pattern contains no wildcards.
+    pub fn is_literal(&self) -> bool {
+        self.words.iter().all(|word| matches!(word, Word::Literal(_)))
+    }
+
+    pub fn matches(&self, routing_key: &str) -> bool {
+        // Positions in the pattern reachable after consuming the words so far.
+        let mut active = vec![false; self.words.len() + 1];
+        active[0] = true;
+        self.close(&mut active);
+        for word in split(routing_key) {
+            let mut next = vec![false; self.words.len() + 1];
+            for (position, pattern_word) in self.words.iter().enumerate() {
+                if !active[position] {
+                    continue;
+                }
+                match *pattern_word {
+                    Word::Hash => next[position] = true,
+                    Word::Star => next[position + 1] = true,
+                    Word::Literal(ref literal) => {
+                        if literal == word {
+                            next[position + 1] = true;
+                        }
+                    }
+                }
+            }
+            self.close(&mut next);
+            if !next.contains(&true) {
+                return false;
+            }
+            active = next;
+        }
+        active[self.words.len()]
+    }
+
+    /// `#` may match zero words, so the position after it is reachable too.
+    fn close(&self, active: &mut [bool]) {
+        for (position, word) in self.words.iter().enumerate() {
+            if active[position] && *word == Word::Hash {
+                active[position + 1] = true;
+            }
+        }
+    }
+}
+
+impl FromStr for TopicPattern {
+    type Err = Error;
+
+    fn from_str(pattern: &str) -> Result<TopicPattern> {
+        TopicPattern::parse(pattern)
+    }
+}
+
+impl fmt::Display for TopicPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    literals: HashMap<String, Node<T>>,
+    star: Option<Box<Node<T>>>,
+    hash: Option<Box<Node<T>>>,
+    values: Vec<T>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            literals: HashMap::new(),
+            star: None,
+            hash: None,
+            values: vec![],
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn child_mut(&mut self, word: &Word) -> &mut Node<T> {
+        match *word {
+            Word::Literal(ref literal) => self.literals.entry(literal.clone()).or_default(),
+            Word::Star => self.star.get_or_insert_with(Default::default),
+            Word::Hash => self.hash.get_or_insert_with(Default::default),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.literals.is_empty() && self.star.is_none() &&
+        self.hash.is_none()
+    }
+
+    fn remove(&mut self, words: &[Word], value: &T) -> bool
+        where T: PartialEq
+    {
+        let (word, rest) = match words.split_first() {
+            Some(split) => split,
+            None => {
+                return match self.values.iter().position(|other| other == value) {
+                    Some(position) => {
+                        self.values.remove(position);
+                        true
+                    }
+                    None => false,
+                }
+            }
+        };
+        match *word {
+            Word::Literal(ref literal) => {
+                let removed = self.literals
+                    .get_mut(literal)
+                    .is_some_and(|child| child.remove(rest, value));
+                if self.literals.get(literal).is_some_and(|child| child.is_empty()) {
+                    self.literals.remove(literal);
+                }
+                removed
+            }
+            Word::Star => remove_boxed(&mut self.star, rest, value),
+            Word::Hash => remove_boxed(&mut self.hash, rest, value),
+        }
+    }
+
+    /// Removes values and prunes empty branches, returning the number of removed values.
+    fn retain<F: FnMut(&T) -> bool>(&mut self, f: &mut F) -> usize {
+        let before = self.values.len();
+        self.values.retain(|value| f(value));
+        let mut removed = before - self.values.len();
+        self.literals.retain(|_, child| {
+            removed += child.retain(f);
+            !child.is_empty()
+        });
+        for child in [&mut self.star, &mut self.hash] {
+            if let Some(ref mut node) = *child {
+                removed += node.retain(f);
+                if node.is_empty() {
+                    *child = None;
+                }
+            }
+        }
+        removed
+    }
+}
+
+fn remove_boxed<T: PartialEq>(child: &mut Option<Box<Node<T>>>, words: &[Word], value: &T) -> bool {
+    let removed = child.as_mut().is_some_and(|node| node.remove(words, value));
+    if child.as_ref().is_some_and(|node| node.is_empty()) {
+        *child = None;
+    }
+    removed
+}
+
+/// Topic patterns sharing common prefixes in a trie, so a routing key is matched
+/// against all of them in one pass over its words.
+///
+/// Each pattern maps to any number of values, e.g. the queues bound with it.
+#[derive(Debug)]
+pub struct TopicTrie<T> {
+    root: Node<T>,
+    len: usize,
+}
+
+impl<T> Default for TopicTrie<T> {
+    fn default() -> Self {
+        TopicTrie {
+            root: Node::default(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> TopicTrie<T> {
+    pub fn new() -> Self {
+        TopicTrie::default()
+    }
+
+    pub fn insert(&mut self, pattern: &TopicPattern, value: T) {
+        let mut node = &mut self.root;
+        for word in &pattern.words {
+            node = node.child_mut(word);
+        }
+        node.values.push(value);
+        self.len += 1;
+    }
+
+    /// Removes one value stored under `pattern`, returning whether it was found.
+    pub fn remove(&mut self, pattern: &TopicPattern, value: &T) -> bool
+        where T: PartialEq
+    {
+        let removed = self.root.remove(&pattern.words, value);
+        if removed {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    /// Keeps only the values for which `f` returns true.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        self.len -= self.root.retain(&mut f);
+    }
+
+    /// Values of all patterns matching `routing_key`. A pattern reachable along
+    /// several paths, such as `#.#`, still contributes its values once.
+    pub fn matches(&self, routing_key: &str) -> Vec<&T> {
+        let mut active: Vec<(&Node<T>, bool)> = vec![];
+        add_active(&mut active, &self.root, false);
+        for word in split(routing_key) {
+            let mut next = vec![];
+            for &(node, after_hash) in &active {
+                if after_hash {
+                    add_active(&mut next, node, true);
+                }
+                if let Some(child) = node.literals.get(word) {
+                    add_active(&mut next, child, false);
+                }
+                if let Some(ref child) = node.star {
+                    add_active(&mut next, child, false);
+                }
+            }
+            if next.is_empty() {
+                return vec![];
+            }
+            active = next;
+        }
+        active.iter().flat_map(|&(node, _)| node.values.iter()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Adds a node and, since `#` may match zero words, its chain of `#` children.
+/// `after_hash` marks nodes reached through `#`, which can consume further words.
+fn add_active<'a, T>(active: &mut Vec<(&'a Node<T>, bool)>, node: &'a Node<T>, after_hash: bool) {
+    match active.iter_mut().find(|&&mut (other, _)| ::std::ptr::eq(other, node)) {
+        Some(entry) => entry.1 |= after_hash,
+        None => active.push((node, after_hash)),
+    }
+    if let Some(ref hash) = node.hash {
+        add_active(active, hash, true);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches(pattern: &str, routing_key: &str) -> bool {
+        let pattern = TopicPattern::parse(pattern).unwrap();
+        let mut trie = TopicTrie::new();
+        trie.insert(&pattern, ());
+        let trie_matches = !trie.matches(routing_key).is_empty();
+        assert_eq!(pattern.matches(routing_key),
+                   trie_matches,
+                   "pattern and trie disagree on {} / {}",
+                   pattern,
+                   routing_key);
+        trie_matches
+    }
+
+    #[test]
+    fn test_literals_and_star() {
+        assert!(matches("a.b.c", "a.b.c"));
+        assert!(!matches("a.b.c", "a.b"));
+        assert!(!matches("a.b", "a.b.c"));
+        assert!(matches("a.*.c", "a.b.c"));
+        assert!(!matches("a.*", "a"));
+        assert!(!matches("*", ""));
+        assert!(matches("*.*", "a.b"));
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+        // Empty words are words too.
+        assert!(matches("a..b", "a..b"));
+        assert!(matches("a.*.b", "a..b"));
+        assert!(matches("a.*", "a."));
+    }
+
+    #[test]
+    fn test_hash() {
+        for key in &["", "a", "a.b.c"] {
+            assert!(matches("#", key));
+            assert!(matches("#.#", key));
+        }
+        assert!(matches("a.#", "a"));
+        assert!(matches("a.#", "a.b.c"));
+        assert!(!matches("a.#", "b.a"));
+        assert!(matches("#.a", "a"));
+        assert!(matches("#.a", "b.c.a"));
+        assert!(!matches("#.a", "a.b"));
+        assert!(matches("a.#.b", "a.b"));
+        assert!(matches("a.#.b", "a.x.y.b"));
+        assert!(!matches("a.#.b", "a"));
+        assert!(!matches("a.#.b", "a.b.c"));
+        assert!(matches("a.#.#.b", "a.b"));
+        assert!(matches("a.#.#.b", "a.x.b"));
+        assert!(matches("#.a.#", "a"));
+        assert!(matches("#.a.#", "x.a.y.z"));
+        assert!(!matches("#.a.#", "x.y"));
+        assert!(matches("a.#.b.#", "a.b.b.b"));
+        assert!(matches("#.b.b", "b.b.b"));
+        assert!(!matches("#.b.b", "b"));
+    }
+
+    #[test]
+    fn test_hash_and_star() {
+        assert!(!matches("#.*", ""));
+        assert!(matches("#.*", "a"));
+        assert!(matches("#.*", "a.b.c"));
+        assert!(matches("*.#", "a"));
+        assert!(!matches("*.#.*", "a"));
+        assert!(matches("*.#.*", "a.b"));
+        assert!(matches("*.#.*", "a.b.c.d"));
+        assert!(matches("#.*.#", "a"));
+        assert!(!matches("#.*.#", ""));
+        assert!(matches("a.*.#", "a.b"));
+        assert!(!matches("a.*.#", "a"));
+        assert!(matches("#.*.c", "a.b.c"));
+        assert!(!matches("#.*.c", "c"));
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        for pattern in &["a*", "#a", "a.b#", "*.**", "##"] {
+            assert!(TopicPattern::parse(pattern).is_err(), "{} should be invalid", pattern);
+        }
+        assert!(TopicPattern::parse(&"a".repeat(256)).is_err());
+        assert_eq!("a.*".parse::<TopicPattern>().unwrap().to_string(), "a.*");
+        assert!("a.b".parse::<TopicPattern>().unwrap().is_literal());
+    }
+
+    #[test]
+    fn test_trie() {
+        let mut trie = TopicTrie::new();
+        for &(pattern, queue) in &[("stock.#", "all"),
+                                   ("stock.*.nyse", "nyse"),
+                                   ("stock.ibm.*", "ibm"),
+                                   ("#.#", "everything"),
+                                   ("stock.ibm.nyse", "exact"),
+                                   ("stock.ibm.nyse", "exact2")] {
+            trie.insert(&TopicPattern::parse(pattern).unwrap(), queue);
+        }
+        assert_eq!(trie.len(), 6);
+        let mut matched = trie.matches("stock.ibm.nyse");
+        matched.sort();
+        assert_eq!(matched, vec![&"all", &"everything", &"exact", &"exact2", &"ibm", &"nyse"]);
+        assert_eq!(trie.matches("weather"), vec![&"everything"]);
+
+        let exact = TopicPattern::parse("stock.ibm.nyse").unwrap();
+        assert!(trie.remove(&exact, &"exact"));
+        assert!(!trie.remove(&exact, &"exact"));
+        assert!(!trie.remove(&TopicPattern::parse("stock").unwrap(), &"all"));
+        trie.retain(|queue| *queue != "everything");
+        assert_eq!(trie.len(), 4);
+        let mut matched = trie.matches("stock.ibm.nyse");
+        matched.sort();
+        assert_eq!(matched, vec![&"all", &"exact2", &"ibm", &"nyse"]);
+        assert!(trie.matches("weather").is_empty());
+    }
+}
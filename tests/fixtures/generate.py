@@ -0,0 +1,140 @@
+#!/usr/bin/env python3
+"""Generates the capture fixtures used by the pcap module's tests.
+
+handshake.pcap: a pcap (microseconds, little endian, Ethernet, IPv4) of a connection
+handshake, with a connection.start-ok split over an out of order segment, an
+overlapping segment and a retransmission.
+
+publish.pcapng: a pcapng (nanosecond if_tsresol, Ethernet with a VLAN tag, IPv6)
+of a basic.publish split over several segments, next to unrelated traffic.
+"""
+import os
+import struct
+
+BASE = 1500000000
+
+
+def shortstr(value):
+    return struct.pack(">B", len(value)) + value
+
+
+def longstr(value):
+    return struct.pack(">I", len(value)) + value
+
+
+def frame(frame_type, channel, payload):
+    return struct.pack(">BHI", frame_type, channel, len(payload)) + payload + b"\xce"
+
+
+def method(channel, class_id, method_id, arguments):
+    return frame(1, channel, struct.pack(">HH", class_id, method_id) + arguments)
+
+
+def tcp(src_port, dst_port, seq, flags, payload):
+    return struct.pack(">HHIIBBHHH", src_port, dst_port, seq, 0, 5 << 4, flags, 65535, 0, 0) + payload
+
+
+def ipv4(src, dst, segment):
+    header = struct.pack(">BBHHHBBH4s4s", 0x45, 0, 20 + len(segment), 0, 0x4000, 64, 6, 0,
+                         bytes(src), bytes(dst))
+    return header + segment
+
+
+def ipv6(src, dst, next_header, payload):
+    return struct.pack(">IHBB16s16s", 6 << 28, len(payload), next_header, 64, bytes(src),
+                       bytes(dst)) + payload
+
+
+def ethernet(ether_type, payload, vlan=None):
+    header = b"\x02\x00\x00\x00\x00\x02\x02\x00\x00\x00\x00\x01"
+    if vlan is not None:
+        header += struct.pack(">HH", 0x8100, vlan)
+    packet = header + struct.pack(">H", ether_type) + payload
+    return packet + b"\x00" * max(0, 60 - len(packet))
+
+
+def handshake_pcap():
+    client, server = [10, 0, 0, 1], [10, 0, 0, 2]
+    c_seq, s_seq = 1000, 5000
+    start = method(0, 10, 10, b"\x00\x09" + longstr(b"") + longstr(b"PLAIN") + longstr(b"en_US"))
+    start_ok = method(0, 10, 11, longstr(b"") + shortstr(b"PLAIN") +
+                      longstr(b"\x00guest\x00guest") + shortstr(b"en_US"))
+    tune = method(0, 10, 30, struct.pack(">HIH", 2047, 131072, 60))
+    tune_ok = method(0, 10, 31, struct.pack(">HIH", 2047, 131072, 60))
+    open_ = method(0, 10, 40, shortstr(b"/") + shortstr(b"") + b"\x00")
+    open_ok = method(0, 10, 41, shortstr(b""))
+    header = b"AMQP\x00\x00\x09\x01"
+    split = 20
+
+    def c(seq, flags, payload=b""):
+        return ipv4(client, server, tcp(40000, 5672, seq, flags, payload))
+
+    def s(seq, flags, payload=b""):
+        return ipv4(server, client, tcp(5672, 40000, seq, flags, payload))
+
+    c_data = c_seq + 1
+    s_data = s_seq + 1
+    packets = [
+        (0, c(c_seq, 0x02)),
+        (100, s(s_seq, 0x12)),
+        (200, c(c_data, 0x10)),
+        (500, c(c_data, 0x18, header)),
+        (1000, s(s_data, 0x18, start)),
+        (2000, c(c_data + 8 + split, 0x18, start_ok[split:])),
+        (2100, c(c_data + 8, 0x18, start_ok[:split + 3])),
+        (2200, c(c_data + 8 + split, 0x18, start_ok[split:])),
+        (3000, s(s_data + len(start), 0x18, tune)),
+        (4000, c(c_data + 8 + len(start_ok), 0x18, tune_ok + open_)),
+        (5000, s(s_data + len(start) + len(tune), 0x18, open_ok)),
+    ]
+    data = struct.pack("<IHHiIII", 0xa1b2c3d4, 2, 4, 0, 0, 65535, 1)
+    for micros, packet in packets:
+        packet = ethernet(0x0800, packet)
+        data += struct.pack("<IIII", BASE, micros, len(packet), len(packet)) + packet
+    return data
+
+
+def block(block_type, body):
+    body += b"\x00" * (-len(body) % 4)
+    length = len(body) + 12
+    return struct.pack("<II", block_type, length) + body + struct.pack("<I", length)
+
+
+def publish_pcapng():
+    client = [0x20, 0x01, 0x0d, 0xb8] + [0] * 11 + [1]
+    server = [0x20, 0x01, 0x0d, 0xb8] + [0] * 11 + [2]
+    publish = method(1, 60, 40, struct.pack(">H", 0) + shortstr(b"") + shortstr(b"work") + b"\x00")
+    properties = shortstr(b"text/plain")
+    content_header = frame(2, 1, struct.pack(">HHQH", 60, 0, 5, 0x8000) + properties)
+    body = frame(3, 1, b"hello")
+    stream = publish + content_header + body
+    seq = 7000
+
+    def c(seq, payload, port=5672, next_header=6):
+        segment = tcp(50000, port, seq, 0x18, payload)
+        return ethernet(0x86dd, ipv6(client, server, next_header, segment), vlan=10)
+
+    packets = [
+        (123456789, c(seq, stream[:10])),
+        (123456800, c(seq, b"\x00" * 8, port=80)),
+        (123456900, c(seq + 10, stream[10:len(publish) + 4])),
+        (123457000, c(seq + len(publish) + 4, stream[len(publish) + 4:], next_header=6)),
+        (123457100, ethernet(0x86dd, ipv6(client, server, 17, b"\x00" * 8), vlan=10)),
+    ]
+    data = block(0x0a0d0d0a, struct.pack("<IHHq", 0x1a2b3c4d, 1, 0, -1))
+    # if_tsresol = 9 (nanoseconds), then the end of options.
+    options = struct.pack("<HHB3x", 9, 1, 9) + struct.pack("<HH", 0, 0)
+    data += block(1, struct.pack("<HHI", 1, 0, 65535) + options)
+    for nanos, packet in packets:
+        units = BASE * 1000000000 + nanos
+        data += block(6, struct.pack("<IIIII", 0, units >> 32, units & 0xffffffff, len(packet),
+                                     len(packet)) + packet)
+    return data
+
+
+if __name__ == "__main__":
+    directory = os.path.dirname(os.path.abspath(__file__))
+    with open(os.path.join(directory, "handshake.pcap"), "wb") as f:
+        f.write(handshake_pcap())
+    with open(os.path.join(directory, "publish.pcapng"), "wb") as f:
+        f.write(publish_pcapng())
@@ -0,0 +1,79 @@
+// Generated by amq-proto-codegen from the AMQP 0-9-1 spec. Do not edit.
+
+/// Default port from the spec
+pub const PORT: u16 = 5672;
+
+/// Constants from the spec.
+pub mod constants {
+    /// A soft-error.
+    pub const OFFSET_OUT_OF_RANGE: u16 = 416;
+}
+
+#[allow(missing_copy_implementations)]
+pub mod stream {
+
+    // properties struct for stream
+    ::amq_proto::properties_struct!(StreamProperties,
+        content_type => shortstr,
+        priority => octet
+    );
+
+    // Method 10:seek
+    ::amq_proto::method_struct!(Seek, "stream.seek", 200, 10, true,
+        queue => shortstr = "events".to_string(),
+        offset => longlong,
+        no_wait => bit,
+        arguments => table
+    );
+
+    // Method 11:seek-ok
+    ::amq_proto::method_struct!(SeekOk, "stream.seek-ok", 200, 11, false,
+        offset => longlong
+    );
+}
+
+::amq_proto::any_method!(
+    (200, 10) => StreamSeek(stream::Seek),
+    (200, 11) => StreamSeekOk(stream::SeekOk)
+);
+
+use ::amq_proto::reflection::{ClassInfo, DefaultValue, FieldInfo, MethodInfo, WireType};
+
+/// The classes of the spec, see `amq_proto::reflection`.
+pub static CLASSES: &[ClassInfo] = &[
+    ClassInfo {
+        id: 200,
+        name: "stream",
+        properties: &[
+            FieldInfo { name: "content_type", spec_name: "content-type", wire_type: WireType::ShortStr, default: None },
+            FieldInfo { name: "priority", spec_name: "priority", wire_type: WireType::Octet, default: None },
+        ],
+        methods: &[
+            MethodInfo {
+                class_id: 200,
+                id: 10,
+                name: "seek",
+                full_name: "stream.seek",
+                synchronous: true,
+                content: false,
+                fields: &[
+                    FieldInfo { name: "queue", spec_name: "queue", wire_type: WireType::ShortStr, default: Some(DefaultValue::Str("events")) },
+                    FieldInfo { name: "offset", spec_name: "offset", wire_type: WireType::LongLong, default: None },
+                    FieldInfo { name: "no_wait", spec_name: "no-wait", wire_type: WireType::Bit, default: None },
+                    FieldInfo { name: "arguments", spec_name: "arguments", wire_type: WireType::Table, default: None },
+                ],
+            },
+            MethodInfo {
+                class_id: 200,
+                id: 11,
+                name: "seek-ok",
+                full_name: "stream.seek-ok",
+                synchronous: false,
+                content: false,
+                fields: &[
+                    FieldInfo { name: "offset", spec_name: "offset", wire_type: WireType::LongLong, default: None },
+                ],
+            },
+        ],
+    },
+];